@@ -0,0 +1,181 @@
+use std::path::Path;
+use log::{debug, info, warn};
+use serde::Serialize;
+use tokio::process::Command as TokioCommand;
+
+/// Size of the rendered waveform image
+const WAVEFORM_SIZE: &str = "1800x300";
+
+/// Size of the rendered spectrogram (ffmpeg adds the axes and legend around it)
+const SPECTROGRAM_SIZE: &str = "1600x800";
+
+/// EBU R128 loudness of a track, as measured by ffmpeg's ebur128 filter
+#[derive(Debug, Clone, Serialize)]
+pub struct LoudnessReport {
+    /// Integrated loudness in LUFS
+    pub integrated_lufs: Option<f64>,
+    /// Loudness range in LU
+    pub loudness_range_lu: Option<f64>,
+    /// True peak in dBTP
+    pub true_peak_dbtp: Option<f64>,
+}
+
+/// Result of analysing one audio file of a track
+#[derive(Debug, Clone, Default)]
+pub struct AudioAnalysis {
+    /// Path of the file that was analysed
+    pub source: String,
+    pub loudness: Option<LoudnessReport>,
+    /// Path of the waveform PNG
+    pub waveform: Option<String>,
+    /// Path of the spectrogram PNG
+    pub spectrogram: Option<String>,
+}
+
+impl AudioAnalysis {
+    /// Paths of the rendered images
+    pub fn images(&self) -> Vec<String> {
+        self.waveform.iter().chain(self.spectrogram.iter()).cloned().collect()
+    }
+}
+
+/// Measure loudness and render a waveform and spectrogram of an audio file
+///
+/// The images are written into `output_dir` as `<stem>_waveform.png` and
+/// `<stem>_spectrogram.png`. Every step is independent, so a failing one only
+/// leaves its part of the analysis empty.
+pub async fn analyze_audio(path: &Path, output_dir: &Path, stem: &str) -> AudioAnalysis {
+    let mut analysis = AudioAnalysis {
+        source: path.to_string_lossy().to_string(),
+        ..Default::default()
+    };
+
+    match measure_loudness(path).await {
+        Ok(loudness) => {
+            info!("Loudness of {}: {} LUFS integrated, {} dBTP true peak",
+                  path.display(),
+                  format_measure(loudness.integrated_lufs),
+                  format_measure(loudness.true_peak_dbtp));
+            analysis.loudness = Some(loudness);
+        },
+        Err(e) => warn!("Failed to measure loudness of {}: {}", path.display(), e),
+    }
+
+    let waveform_path = output_dir.join(format!("{}_waveform.png", stem));
+    let waveform_filter = format!("aformat=channel_layouts=mono,showwavespic=s={}:colors=0xff5500", WAVEFORM_SIZE);
+    match render_image(path, &waveform_path, &waveform_filter).await {
+        Ok(()) => analysis.waveform = Some(waveform_path.to_string_lossy().to_string()),
+        Err(e) => warn!("Failed to render waveform of {}: {}", path.display(), e),
+    }
+
+    // A spectrogram shows the lowpass cutoff that gives away transcoded "lossless" uploads
+    let spectrogram_path = output_dir.join(format!("{}_spectrogram.png", stem));
+    let spectrogram_filter = format!("showspectrumpic=s={}:legend=1", SPECTROGRAM_SIZE);
+    match render_image(path, &spectrogram_path, &spectrogram_filter).await {
+        Ok(()) => analysis.spectrogram = Some(spectrogram_path.to_string_lossy().to_string()),
+        Err(e) => warn!("Failed to render spectrogram of {}: {}", path.display(), e),
+    }
+
+    analysis
+}
+
+/// Format an optional measurement for logs and embeds
+pub fn format_measure(value: Option<f64>) -> String {
+    match value {
+        Some(v) if v.is_finite() => format!("{:.1}", v),
+        Some(_) => "-inf".to_string(),
+        None => "?".to_string(),
+    }
+}
+
+/// Run the ebur128 filter over the whole file and parse its summary
+async fn measure_loudness(path: &Path) -> Result<LoudnessReport, Box<dyn std::error::Error + Send + Sync>> {
+    debug!("Executing ffmpeg loudness measurement");
+
+    // The summary is printed to stderr, so it can't be silenced like the other commands
+    let output = TokioCommand::new("ffmpeg")
+        .arg("-hide_banner")
+        .arg("-nostats")
+        .arg("-i")
+        .arg(path)
+        .arg("-af")
+        .arg("ebur128=peak=true")
+        .arg("-f")
+        .arg("null")
+        .arg("-")
+        .kill_on_drop(true)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(format!("ffmpeg failed with exit code: {}", output.status).into());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    parse_ebur128_summary(&stderr).ok_or_else(|| "ffmpeg printed no loudness summary".into())
+}
+
+/// Read integrated loudness, loudness range and true peak from the ebur128 summary
+fn parse_ebur128_summary(output: &str) -> Option<LoudnessReport> {
+    // Per-frame lines come first, only the block after "Summary:" has the totals
+    let summary = &output[output.rfind("Summary:")?..];
+
+    let value_of = |key: &str| {
+        summary.lines()
+            .map(str::trim)
+            .find_map(|line| line.strip_prefix(key))
+            .and_then(|rest| rest.split_whitespace().next())
+            .and_then(|value| value.parse::<f64>().ok())
+    };
+
+    Some(LoudnessReport {
+        integrated_lufs: value_of("I:"),
+        loudness_range_lu: value_of("LRA:"),
+        true_peak_dbtp: value_of("Peak:"),
+    })
+}
+
+/// Render a single image of the whole file with an ffmpeg audio-to-video filter
+async fn render_image(
+    input_path: &Path,
+    output_path: &Path,
+    filter: &str
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    debug!("Executing ffmpeg image rendering command");
+    let mut cmd = TokioCommand::new("ffmpeg");
+
+    // Check if we should show ffmpeg output
+    let show_output = matches!(crate::config::Config::show_ffmpeg_output(), Some(true));
+
+    cmd.arg("-i")
+        .arg(input_path)
+        .arg("-filter_complex")
+        .arg(filter)
+        .arg("-frames:v")
+        .arg("1")
+        .arg("-y") // Overwrite output
+        .kill_on_drop(true); // Ensure process is killed if parent process exits
+
+    // Configure stdout/stderr redirection based on config
+    if !show_output {
+        // Silence ffmpeg output
+        cmd.stdout(std::process::Stdio::null())
+           .stderr(std::process::Stdio::null());
+    }
+
+    // Add output path
+    cmd.arg(output_path);
+
+    debug!("ffmpeg command: -i {} -filter_complex {} -frames:v 1 -y {}",
+          input_path.display(), filter, output_path.display());
+
+    // Execute command
+    let status = cmd.status().await?;
+
+    if !status.success() {
+        return Err(format!("ffmpeg failed with exit code: {}", status).into());
+    }
+
+    debug!("Rendered {}", output_path.display());
+    Ok(())
+}
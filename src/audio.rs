@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::fs;
@@ -7,21 +8,46 @@ use tokio::process::Command as TokioCommand;
 use tokio::fs::File as TokioFile;
 use tokio::io::AsyncWriteExt;
 use uuid::Uuid;
-use crate::soundcloud::{Track, get_stream_url};
-use lazy_static;
+use crate::config::Config;
+use crate::soundcloud::{Track, get_stream_url, get_original_download_url};
+use crate::analysis::AudioAnalysis;
+use crate::formats::{Format, Protocol};
+use crate::transcode::{TranscodeProfile, TranscodeSink};
+use crate::verify::VerificationReport;
+use crate::hashing::FileHash;
 use serde_json::Value;
 
+/// Files made by `process_track_audio`, all inside `work_dir`
+#[derive(Debug)]
+pub struct ProcessedAudio {
+    /// (format_info, file_path) of the downloaded audio files, best first
+    pub audio_files: Vec<(String, String)>,
+    pub artwork: Option<String>,
+    pub json: Option<String>,
+    /// User avatar, profile banner and waveform JSON
+    pub media_files: Vec<String>,
+    /// Loudness and the waveform/spectrogram images, if analysis is enabled
+    pub analysis: Option<AudioAnalysis>,
+    /// SHA-256 hashes of the audio files, taken before any tags were written
+    pub audio_hashes: Vec<FileHash>,
+    /// Paths the local-sink transcode profiles and the analysis images next to them were
+    /// exported to, or would be in a dry run
+    pub local_exports: Vec<String>,
+    /// Temp directory of the track, removed once the track is posted
+    pub work_dir: PathBuf,
+}
+
 /// Download and preserve original audio from a SoundCloud track
-/// Returns a tuple containing:
-/// - Vec of (format_info, file_path) for all downloaded audio files
-/// - Option<String> for artwork file path
-/// - Option<String> for JSON metadata file path
+/// 
+/// In a dry run nothing is written outside the work directory, local-sink
+/// transcode profiles only report where they would be exported to.
 pub async fn process_track_audio(
     track: &Track,
-    temp_dir: Option<&str>
-) -> Result<(Vec<(String, String)>, Option<String>, Option<String>), Box<dyn std::error::Error + Send + Sync>> {
+    config: &Config,
+    dry_run: bool
+) -> Result<ProcessedAudio, Box<dyn std::error::Error + Send + Sync>> {
     // Get the base temp directory
-    let base_dir = match temp_dir {
+    let base_dir = match config.temp_dir.as_deref() {
         Some(dir) => {
             debug!("Using specified temp directory: {}", dir);
             PathBuf::from(dir)
@@ -41,47 +67,73 @@ pub async fn process_track_audio(
     
     info!("Processing audio for track '{}' (ID: {}) in {}", track.title, track.id, work_dir.display());
     
-    // Save raw track data as JSON
-    let mut json_result = None;
     let sanitized_title = sanitize_filename(&track.title);
-    let json_path = work_dir.join(format!("{}_data.json", sanitized_title));
     
-    match save_track_json(track, &json_path).await {
-        Ok(()) => {
-            let file_size = match fs::metadata(&json_path) {
-                Ok(metadata) => metadata.len(),
-                Err(_) => 0,
-            };
-            
-            json_result = Some(json_path.to_string_lossy().to_string());
-            info!("Saved track data as JSON: {} ({} bytes)", json_path.display(), file_size);
-        },
-        Err(e) => {
-            warn!("Failed to save track data as JSON: {}", e);
-        }
-    }
+    // Verification results per file path, written to the manifest in the JSON snapshot
+    let mut verification: HashMap<String, VerificationReport> = HashMap::new();
+    // Downloads that kept failing verification, left out of the post but listed in the manifest
+    let mut rejected_files: Vec<(String, String)> = Vec::new();
     
-    // Extract all available formats from the raw data
+    // Extract all available formats from the raw data, in the order the policy wants them
     let available_formats = extract_available_formats(track);
     debug!("Found {} available formats for track {}", available_formats.len(), track.id);
+    let candidate_formats = config.format_policy.candidates(available_formats);
+    let wanted_formats = config.format_policy.wanted();
+    let mut transcodings_downloaded = 0;
     
     // First try to download all available formats in their original format
     let mut downloaded_files = Vec::new();
     
+    // The original upload is the best possible copy, so grab it first when the uploader allows it
+    if track.downloadable.unwrap_or(false) {
+        info!("Track {} is downloadable, fetching original upload", track.id);
+        let download = || async {
+            download_original(track, &work_dir).await
+        };
+        match download_verified(config, track, "original upload", download).await {
+            Ok((format_info, output_path, report)) => {
+                let file_size = match fs::metadata(&output_path) {
+                    Ok(metadata) => metadata.len(),
+                    Err(_) => 0,
+                };
+                
+                info!("Successfully downloaded original upload: {} ({} bytes)", 
+                      output_path.display(), file_size);
+                accept_download(&mut downloaded_files, &mut verification, &mut rejected_files,
+                                format_info, &output_path, report);
+            },
+            Err(e) => {
+                warn!("Failed to download original upload for track {}: {}", track.id, e);
+            }
+        }
+    } else {
+        debug!("Track {} is not downloadable, skipping original upload", track.id);
+    }
+    
     // If we have raw transcodings data, use it
-    for (format_info, url) in available_formats {
+    for (format, url) in candidate_formats {
+        if wanted_formats.is_some_and(|wanted| transcodings_downloaded >= wanted) {
+            debug!("Format policy satisfied with {} formats, skipping {}", transcodings_downloaded, format);
+            break;
+        }
+        
+        let format_info = format.to_string();
         debug!("Attempting to download format: {} at {}", format_info, url);
         
         // Determine file extension based on format info
-        let extension = determine_extension_from_format(&format_info);
+        let extension = format.extension();
         let safe_format = sanitize_format_string(&format_info);
         let output_path = work_dir.join(format!("{}_{}.{}", sanitized_title, safe_format, extension));
         
         debug!("Downloading stream to: {}", output_path.display());
         
-        // Use the new resolve_and_download_format function
-        match resolve_and_download_format(&format_info, &url, &output_path).await {
-            Ok(()) => {
+        // Signed stream URLs expire, so every retry resolves the format again
+        let download = || async {
+            resolve_and_download_format(&format_info, &url, track.secret_token.as_deref(), &output_path).await?;
+            Ok((format_info.clone(), output_path.clone()))
+        };
+        match download_verified(config, track, &format_info, download).await {
+            Ok((format_info, output_path, report)) => {
                 let file_size = match fs::metadata(&output_path) {
                     Ok(metadata) => metadata.len(),
                     Err(_) => 0,
@@ -89,7 +141,10 @@ pub async fn process_track_audio(
                 
                 info!("Successfully downloaded {} format: {} ({} bytes)", 
                       format_info, output_path.display(), file_size);
-                downloaded_files.push((format_info, output_path.to_string_lossy().to_string()));
+                if accept_download(&mut downloaded_files, &mut verification, &mut rejected_files,
+                                   format_info, &output_path, report) {
+                    transcodings_downloaded += 1;
+                }
             },
             Err(e) => {
                 warn!("Failed to download {} format: {}", format_info, e);
@@ -106,7 +161,7 @@ pub async fn process_track_audio(
         let hls_url = match &track.hls_url {
             Some(url) => {
                 debug!("Resolving HLS URL for track {}", track.id);
-                match get_stream_url(url).await {
+                match get_stream_url(url, track.secret_token.as_deref()).await {
                     Ok(resolved) => {
                         info!("Successfully resolved HLS URL for track {}", track.id);
                         Some(resolved)
@@ -128,7 +183,7 @@ pub async fn process_track_audio(
             match &track.stream_url {
                 Some(url) => {
                     debug!("Resolving stream URL for track {}", track.id);
-                    match get_stream_url(url).await {
+                    match get_stream_url(url, track.secret_token.as_deref()).await {
                         Ok(resolved) => {
                             info!("Successfully resolved stream URL for track {}", track.id);
                             Some(resolved)
@@ -148,13 +203,23 @@ pub async fn process_track_audio(
             None
         };
         
-        // Try to download from HLS URL
+        // Try to download from HLS URL (download_stream falls back to ffmpeg on its own)
         if let Some(url) = &hls_url {
-            let output_path = work_dir.join(format!("{}_hls.m4a", sanitized_title));
-            debug!("Downloading HLS stream to: {}", output_path.display());
+            // The label tells verification which codec to expect, so take it from the
+            // transcoding behind the HLS URL. Without one the codec isn't checked.
+            let hls_format = extract_available_formats(track).into_iter()
+                .find(|(_, transcoding_url)| track.hls_url.as_deref() == Some(transcoding_url.as_str()))
+                .map(|(format, _)| format)
+                .unwrap_or_else(|| Format::parse("hls/unknown"));
+            let output_path = work_dir.join(format!("{}_hls.{}", sanitized_title, hls_format.extension()));
+            debug!("Downloading HLS stream ({}) to: {}", hls_format, output_path.display());
             
-            match download_stream(url, &output_path).await {
-                Ok(()) => {
+            let download = || async {
+                download_stream(url, &output_path).await?;
+                Ok((hls_format.to_string(), output_path.clone()))
+            };
+            match download_verified(config, track, "HLS stream", download).await {
+                Ok((format_info, output_path, report)) => {
                     let file_size = match fs::metadata(&output_path) {
                         Ok(metadata) => metadata.len(),
                         Err(_) => 0,
@@ -162,40 +227,32 @@ pub async fn process_track_audio(
                     
                     info!("Successfully downloaded HLS stream: {} ({} bytes)", 
                          output_path.display(), file_size);
-                    downloaded_files.push(("hls/aac".to_string(), output_path.to_string_lossy().to_string()));
+                    accept_download(&mut downloaded_files, &mut verification, &mut rejected_files,
+                                    format_info, &output_path, report);
                 },
                 Err(e) => {
                     warn!("Failed to download HLS stream: {}", e);
-                    
-                    // If we failed to download directly, use ffmpeg with stream copy as fallback
-                    info!("Trying ffmpeg with stream copy for HLS URL");
-                    match ffmpeg_stream_copy(url, &output_path).await {
-                        Ok(()) => {
-                            let file_size = match fs::metadata(&output_path) {
-                                Ok(metadata) => metadata.len(),
-                                Err(_) => 0,
-                            };
-                            
-                            info!("Successfully saved HLS stream with ffmpeg: {} ({} bytes)", 
-                                 output_path.display(), file_size);
-                            downloaded_files.push(("hls/aac".to_string(), output_path.to_string_lossy().to_string()));
-                        },
-                        Err(e) => {
-                            warn!("Failed to save HLS stream with ffmpeg: {}", e);
-                        }
-                    }
                 }
             }
         }
         
         // Try to download from stream URL if we don't have anything yet
-        if downloaded_files.is_empty() && stream_url.is_some() {
-            let url = stream_url.as_ref().unwrap();
+        if let (true, Some(url)) = (downloaded_files.is_empty(), stream_url.as_ref()) {
             let output_path = work_dir.join(format!("{}_stream.mp3", sanitized_title));
             debug!("Downloading progressive stream to: {}", output_path.display());
             
-            match download_stream(url, &output_path).await {
-                Ok(()) => {
+            let download = || async {
+                if let Err(e) = download_stream(url, &output_path).await {
+                    warn!("Failed to download progressive stream: {}", e);
+                    
+                    // If we failed to download directly, use ffmpeg with stream copy as fallback
+                    info!("Trying ffmpeg with stream copy for progressive URL");
+                    ffmpeg_stream_copy(url, &output_path).await?;
+                }
+                Ok(("progressive/mp3".to_string(), output_path.clone()))
+            };
+            match download_verified(config, track, "progressive stream", download).await {
+                Ok((format_info, output_path, report)) => {
                     let file_size = match fs::metadata(&output_path) {
                         Ok(metadata) => metadata.len(),
                         Err(_) => 0,
@@ -203,34 +260,19 @@ pub async fn process_track_audio(
                     
                     info!("Successfully downloaded progressive stream: {} ({} bytes)", 
                          output_path.display(), file_size);
-                    downloaded_files.push(("progressive/mp3".to_string(), output_path.to_string_lossy().to_string()));
+                    accept_download(&mut downloaded_files, &mut verification, &mut rejected_files,
+                                    format_info, &output_path, report);
                 },
                 Err(e) => {
-                    warn!("Failed to download progressive stream: {}", e);
-                    
-                    // If we failed to download directly, use ffmpeg with stream copy as fallback
-                    info!("Trying ffmpeg with stream copy for progressive URL");
-                    match ffmpeg_stream_copy(url, &output_path).await {
-                        Ok(()) => {
-                            let file_size = match fs::metadata(&output_path) {
-                                Ok(metadata) => metadata.len(),
-                                Err(_) => 0,
-                            };
-                            
-                            info!("Successfully saved progressive stream with ffmpeg: {} ({} bytes)", 
-                                 output_path.display(), file_size);
-                            downloaded_files.push(("progressive/mp3".to_string(), output_path.to_string_lossy().to_string()));
-                        },
-                        Err(e) => {
-                            warn!("Failed to save progressive stream with ffmpeg: {}", e);
-                        }
-                    }
+                    warn!("Failed to save progressive stream: {}", e);
                 }
             }
         }
         
         // Last resort: If we still have nothing, try transcoding as before
-        if downloaded_files.is_empty() && (hls_url.is_some() || stream_url.is_some()) {
+        if downloaded_files.is_empty() && !config.format_policy.allow_mp3_fallback {
+            warn!("No format downloaded for track {} and MP3 fallback is disabled", track.id);
+        } else if downloaded_files.is_empty() && (hls_url.is_some() || stream_url.is_some()) {
             warn!("Direct downloads failed, falling back to transcoding");
             
             // File paths for transcoded files
@@ -241,8 +283,12 @@ pub async fn process_track_audio(
                 debug!("Input URL: {}", url);
                 debug!("Output path: {}", mp3_path.display());
                 
-                match transcode_to_mp3(url, &mp3_path).await {
-                    Ok(_) => {
+                let download = || async {
+                    transcode_to_mp3(url, &mp3_path).await?;
+                    Ok(("transcoded/mp3".to_string(), mp3_path.clone()))
+                };
+                match download_verified(config, track, "MP3 transcode", download).await {
+                    Ok((format_info, mp3_path, report)) => {
                         let file_size = match fs::metadata(&mp3_path) {
                             Ok(metadata) => metadata.len(),
                             Err(_) => 0,
@@ -250,7 +296,8 @@ pub async fn process_track_audio(
                         
                         info!("Successfully transcoded to MP3 (fallback): {} ({} bytes)", 
                              mp3_path.display(), file_size);
-                        downloaded_files.push(("transcoded/mp3".to_string(), mp3_path.to_string_lossy().to_string()));
+                        accept_download(&mut downloaded_files, &mut verification, &mut rejected_files,
+                                        format_info, &mp3_path, report);
                     },
                     Err(e) => {
                         error!("Failed to transcode to MP3 (fallback): {}", e);
@@ -260,31 +307,166 @@ pub async fn process_track_audio(
         }
     }
     
+    // Derivative files of the configured transcode profiles, made from the best download.
+    // Discord profiles join the downloaded files, local ones are exported once tagged.
+    let mut local_exports: Vec<(&TranscodeProfile, String)> = Vec::new();
+    if !config.transcode_profiles.is_empty() {
+        let best_file = downloaded_files.iter()
+            .min_by_key(|(format_info, _)| Format::parse(format_info).priority())
+            .cloned();
+        
+        match best_file {
+            Some(_) if !ffmpeg_available() => warn!("Skipping transcode profiles, ffmpeg not found in PATH"),
+            Some((source_format, source_path)) => {
+                for profile in &config.transcode_profiles {
+                    let output_path = work_dir.join(format!("{}_{}.{}",
+                        sanitized_title, sanitize_format_string(&profile.name), profile.extension));
+                    
+                    info!("Transcoding {} format to {} profile", source_format, profile.name);
+                    match crate::transcode::transcode_with_profile(profile, Path::new(&source_path), &output_path).await {
+                        Ok(()) => {
+                            let path = output_path.to_string_lossy().to_string();
+                            match profile.sink {
+                                TranscodeSink::Discord => downloaded_files.push((profile.format_info(), path)),
+                                TranscodeSink::Local => local_exports.push((profile, path)),
+                            }
+                        },
+                        Err(e) => warn!("Failed to transcode track {} to {} profile: {}", track.id, profile.name, e),
+                    }
+                }
+            },
+            None => debug!("No audio files to transcode for track {}", track.id),
+        }
+    }
+    
+    // Artwork falls back to the uploader's avatar, like on SoundCloud itself
+    let track_artwork = track.artwork_url.as_ref().filter(|url| !url.is_empty());
+    let avatar_url = track.user.avatar_url.as_ref().filter(|url| !url.is_empty());
+    let artwork_url = track_artwork.or(avatar_url);
+    if track_artwork.is_none() && avatar_url.is_some() {
+        info!("Track {} has no artwork, using the uploader's avatar", track.id);
+    }
+    
     // Download artwork if available
     let mut artwork_result = None;
-    if let Some(artwork_url) = &track.artwork_url {
-        if !artwork_url.is_empty() {
-            // Get the original high-res image URL
-            info!("Downloading original artwork from: {}", artwork_url);
-            
-            // Create file path for artwork
-            let artwork_path = work_dir.join(format!("{}_cover.jpg", sanitized_title));
-            
-            // Download the artwork
-            match download_artwork(&artwork_url, &artwork_path).await {
-                Ok(()) => {
-                    let file_size = match fs::metadata(&artwork_path) {
-                        Ok(metadata) => metadata.len(),
-                        Err(_) => 0,
-                    };
-                    
-                    artwork_result = Some(artwork_path.to_string_lossy().to_string());
-                    info!("Successfully downloaded artwork: {} ({} bytes)", artwork_path.display(), file_size);
+    if let Some(artwork_url) = artwork_url {
+        info!("Downloading original artwork from: {}", artwork_url);
+        let artwork_path = work_dir.join(format!("{}_cover.{}", sanitized_title, image_extension(artwork_url)));
+        artwork_result = save_media(artwork_url, &artwork_path, "artwork").await;
+    }
+    
+    // Everything else SoundCloud shows next to the track
+    let mut media_files = Vec::new();
+    
+    // Avatar, unless it is already saved as the cover
+    if let (Some(avatar_url), Some(_)) = (avatar_url, track_artwork) {
+        let avatar_path = work_dir.join(format!("{}_avatar.{}", sanitized_title, image_extension(avatar_url)));
+        media_files.extend(save_media(avatar_url, &avatar_path, "user avatar").await);
+    }
+    
+    match crate::soundcloud::get_user_banner_url(&track.user.id).await {
+        Ok(Some(banner_url)) => {
+            let banner_path = work_dir.join(format!("{}_banner.{}", sanitized_title, image_extension(&banner_url)));
+            media_files.extend(save_media(&banner_url, &banner_path, "profile banner").await);
+        },
+        Ok(None) => debug!("User {} has no profile banner", track.user.id),
+        Err(e) => warn!("Failed to look up profile banner of user {}: {}", track.user.id, e),
+    }
+    
+    if let Some(waveform_url) = crate::soundcloud::get_waveform_json_url(track) {
+        let waveform_path = work_dir.join(format!("{}_soundcloud_waveform.json", sanitized_title));
+        media_files.extend(save_media(&waveform_url, &waveform_path, "waveform data").await);
+    }
+    
+    // Hash the audio before tagging. Tags carry the track ID and URL, so tagged copies of
+    // the same download would never match the file of an earlier upload.
+    let hash_inputs: Vec<(String, String)> = downloaded_files.iter()
+        .map(|(_, path)| {
+            let file_name = Path::new(path)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            (path.clone(), file_name)
+        })
+        .collect();
+    let audio_hashes = crate::hashing::hash_files(&hash_inputs).await;
+    
+    // Tag the files so the archive is browsable without the JSON snapshot
+    if config.embed_metadata {
+        let exported_files: Vec<(String, String)> = local_exports.iter()
+            .map(|(profile, path)| (profile.format_info(), path.clone()))
+            .collect();
+        embed_metadata_in_files(track, &downloaded_files, artwork_result.as_deref()).await;
+        embed_metadata_in_files(track, &exported_files, artwork_result.as_deref()).await;
+    }
+    
+    // Local profiles leave the work directory before it is posted and cleaned up
+    let mut exported_paths = Vec::new();
+    for (profile, path) in &local_exports {
+        if dry_run {
+            match crate::transcode::local_sink_path(profile, track) {
+                Ok(target) => {
+                    info!("Dry run, not exporting {} profile of track {} to {}", profile.name, track.id, target.display());
+                    exported_paths.push(target.to_string_lossy().to_string());
                 },
-                Err(e) => {
-                    warn!("Failed to download artwork: {}", e);
-                }
+                Err(e) => warn!("Cannot export {} profile of track {}: {}", profile.name, track.id, e),
             }
+            continue;
+        }
+        
+        match crate::transcode::export_to_local_sink(profile, track, Path::new(path)).await {
+            Ok(target) => exported_paths.push(target.to_string_lossy().to_string()),
+            Err(e) => warn!("Failed to export {} profile of track {}: {}", profile.name, track.id, e),
+        }
+    }
+    
+    // Verify the files that didn't go through the retry loop (transcode profile outputs)
+    for (format_info, path) in &downloaded_files {
+        if !verification.contains_key(path) {
+            let report = verify_download(config, track, format_info, Path::new(path)).await;
+            verification.insert(path.clone(), report);
+        }
+    }
+    
+    // Loudness, waveform and spectrogram of the best file, to spot fake lossless uploads
+    let mut analysis = None;
+    if config.analyze_audio {
+        let best_file = downloaded_files.iter()
+            .min_by_key(|(format_info, _)| Format::parse(format_info).priority());
+        
+        match best_file {
+            Some(_) if !ffmpeg_available() => warn!("Skipping audio analysis, ffmpeg not found in PATH"),
+            Some((format_info, path)) => {
+                info!("Analysing {} format of track {}", format_info, track.id);
+                analysis = Some(crate::analysis::analyze_audio(Path::new(path), &work_dir, &sanitized_title).await);
+            },
+            None => debug!("No audio files to analyse for track {}", track.id),
+        }
+    }
+    
+    // The local archive keeps the images next to its audio, Discord gets them attached
+    if let Some(analysis) = &analysis {
+        let images = export_analysis_images(&exported_paths, analysis, &sanitized_title, dry_run).await;
+        exported_paths.extend(images);
+    }
+    
+    // Save raw track data as JSON, including the manifest of downloaded files
+    let mut json_result = None;
+    let json_path = work_dir.join(format!("{}_data.json", sanitized_title));
+    let manifest = build_manifest(config, &downloaded_files, &rejected_files, &verification, analysis.as_ref());
+    
+    match save_track_json(track, &json_path, &manifest).await {
+        Ok(()) => {
+            let file_size = match fs::metadata(&json_path) {
+                Ok(metadata) => metadata.len(),
+                Err(_) => 0,
+            };
+            
+            json_result = Some(json_path.to_string_lossy().to_string());
+            info!("Saved track data as JSON: {} ({} bytes)", json_path.display(), file_size);
+        },
+        Err(e) => {
+            warn!("Failed to save track data as JSON: {}", e);
         }
     }
     
@@ -298,8 +480,8 @@ pub async fn process_track_audio(
     // Sort files by preference for primary/secondary output
     downloaded_files.sort_by(|(format_a, _), (format_b, _)| {
         // Prioritize formats based on quality/preference
-        let priority_a = get_format_priority(format_a);
-        let priority_b = get_format_priority(format_b);
+        let priority_a = Format::parse(format_a).priority();
+        let priority_b = Format::parse(format_b).priority();
         priority_a.cmp(&priority_b)
     });
     
@@ -307,11 +489,77 @@ pub async fn process_track_audio(
     info!("Processing completed for track '{}' (ID: {})", track.title, track.id);
     debug!("Downloaded {} audio files", downloaded_files.len());
     
-    Ok((downloaded_files, artwork_result, json_result))
+    Ok(ProcessedAudio {
+        audio_files: downloaded_files,
+        artwork: artwork_result,
+        json: json_result,
+        media_files,
+        analysis,
+        audio_hashes,
+        local_exports: exported_paths,
+        work_dir,
+    })
+}
+
+/// Copy the analysis images next to every file exported to a local sink
+/// 
+/// `<title> [<id>].flac` gets `<title> [<id>]_waveform.png` and `<title> [<id>]_spectrogram.png`.
+/// Returns the paths of the copies, or in a dry run the paths they would be copied to.
+async fn export_analysis_images(
+    exported_files: &[String],
+    analysis: &AudioAnalysis,
+    stem: &str,
+    dry_run: bool
+) -> Vec<String> {
+    let mut copies: Vec<String> = Vec::new();
+    
+    for exported in exported_files {
+        let exported = Path::new(exported);
+        let exported_stem = match exported.file_stem() {
+            Some(s) => s.to_string_lossy().to_string(),
+            None => continue,
+        };
+        
+        for image in analysis.images() {
+            let file_name = Path::new(&image)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let suffix = file_name.strip_prefix(stem).unwrap_or(&file_name);
+            let target = exported.with_file_name(format!("{}{}", exported_stem, suffix));
+            let target = target.to_string_lossy().to_string();
+            
+            // Profiles exporting to the same directory share one copy
+            if copies.contains(&target) {
+                continue;
+            }
+            
+            if !dry_run {
+                if let Err(e) = tokio::fs::copy(&image, &target).await {
+                    warn!("Failed to copy {} to {}: {}", image, target, e);
+                    continue;
+                }
+                debug!("Copied analysis image to {}", target);
+            }
+            copies.push(target);
+        }
+    }
+    
+    copies
+}
+
+/// Formats `process_track_audio` would try to download, in the order it tries them
+///
+/// Depending on the format policy, it stops after the first ones that download.
+pub fn planned_formats(track: &Track, config: &Config) -> Vec<Format> {
+    config.format_policy.candidates(extract_available_formats(track))
+        .into_iter()
+        .map(|(format, _)| format)
+        .collect()
 }
 
 /// Extract all available streaming formats from track data
-fn extract_available_formats(track: &Track) -> Vec<(String, String)> {
+fn extract_available_formats(track: &Track) -> Vec<(Format, String)> {
     let mut formats = Vec::new();
     
     if let Some(raw_data) = &track.raw_data {
@@ -320,81 +568,28 @@ fn extract_available_formats(track: &Track) -> Vec<(String, String)> {
                 debug!("Found {} total transcodings for track {}", transcodings.len(), track.id);
                 
                 for transcoding in transcodings {
-                    // Get format info
-                    let mime_type = transcoding.get("format")
-                        .and_then(|f| f.get("mime_type"))
-                        .and_then(Value::as_str)
-                        .unwrap_or("unknown");
-                    
-                    let protocol = transcoding.get("format")
-                        .and_then(|f| f.get("protocol"))
-                        .and_then(Value::as_str)
-                        .unwrap_or("unknown");
-                    
-                    let quality = transcoding.get("quality")
-                        .and_then(Value::as_str)
-                        .unwrap_or("sq");
-                    
-                    // Format string with additional info to help debugging
-                    let format_string = format!("{}/{}/{}", protocol, mime_type, quality);
+                    let format = Format::from_transcoding(transcoding);
                     
                     // Skip certain formats that are known to cause issues
-                    if format_string.contains("audio/mpegurl") && protocol == "hls" {
+                    if format.protocol == Protocol::Hls && format.mime_type.contains("audio/mpegurl") {
                         // This is an old/deprecated format specification that often 404s
-                        debug!("Skipping known problematic format: {}", format_string);
+                        debug!("Skipping known problematic format: {}", format);
                         continue;
                     }
                     
                     // Get URL
                     if let Some(url) = transcoding.get("url").and_then(Value::as_str) {
-                        debug!("Found format: {} at URL: {}", format_string, url);
-                        formats.push((format_string, url.to_string()));
+                        debug!("Found format: {} ({:?}) at URL: {}", format, format.codec, url);
+                        formats.push((format, url.to_string()));
                     }
                 }
             }
         }
     }
     
-    // Sort formats by priority, so we try the best ones first
-    formats.sort_by(|(format_a, _), (format_b, _)| {
-        let priority_a = get_format_priority(format_a);
-        let priority_b = get_format_priority(format_b);
-        priority_a.cmp(&priority_b)
-    });
-    
-    debug!("Sorted formats by priority: {}", 
-           formats.iter()
-                 .map(|(fmt, _)| fmt.as_str())
-                 .collect::<Vec<&str>>()
-                 .join(", "));
-    
     formats
 }
 
-/// Determine file extension based on format info
-fn determine_extension_from_format(format_info: &str) -> String {
-    if format_info.contains("audio/mpeg") {
-        "mp3".to_string()
-    } else if format_info.contains("audio/ogg") {
-        if format_info.contains("codecs=\"opus\"") {
-            "opus".to_string()
-        } else {
-            "ogg".to_string()
-        }
-    } else if format_info.contains("audio/mp4") || format_info.contains("aac") {
-        "m4a".to_string()
-    } else if format_info.contains("audio/x-wav") {
-        "wav".to_string()
-    } else if format_info.contains("flac") {
-        "flac".to_string()
-    } else if format_info.contains("hls") {
-        "m4a".to_string()  // HLS usually contains AAC in MP4 container
-    } else {
-        // Default fallback
-        "audio".to_string()
-    }
-}
-
 /// Sanitize format string for use in filenames
 fn sanitize_format_string(format_info: &str) -> String {
     // Replace characters that are problematic in filenames
@@ -424,49 +619,28 @@ fn sanitize_format_string(format_info: &str) -> String {
     }
 }
 
-/// Get priority for format sorting (lower number = higher priority)
-fn get_format_priority(format_info: &str) -> i32 {
-    if format_info.contains("hq") {
-        // High quality gets priority
-        if format_info.contains("flac") {
-            1  // FLAC HQ (rare)
-        } else if format_info.contains("opus") {
-            2  // Opus HQ
-        } else if format_info.contains("mp3") {
-            3  // MP3 HQ
-        } else if format_info.contains("aac") || format_info.contains("mp4") {
-            4  // AAC HQ
-        } else {
-            5  // Other HQ
-        }
-    } else {
-        // Regular quality
-        if format_info.contains("progressive") && format_info.contains("mp3") {
-            10  // Progressive MP3 (common format)
-        } else if format_info.contains("opus") {
-            11  // Opus standard quality
-        } else if format_info.contains("mp3") {
-            12  // MP3 standard quality
-        } else if format_info.contains("aac") || format_info.contains("mp4") {
-            13  // AAC standard quality
-        } else if format_info.contains("hls") {
-            15  // HLS (can contain various formats, often AAC)
-        } else if format_info.contains("transcoded") {
-            50  // Fallback transcoded files (lowest priority)
-        } else {
-            20  // Other formats
-        }
-    }
-}
-
 /// Download a stream directly
 async fn download_stream(url: &str, output_path: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    // For streaming URLs, direct downloads often produce incomplete files
-    // Instead, always use ffmpeg to properly download and process streams
-    debug!("Using ffmpeg to download stream from {}", url);
+    // HLS playlists are fetched natively, so a missing ffmpeg doesn't stop the archive
+    if crate::hls::is_hls_url(url) {
+        debug!("Downloading HLS stream natively");
+        match crate::hls::download_hls(url, output_path).await {
+            Ok(()) => return Ok(()),
+            Err(e) if ffmpeg_available() => {
+                // ffmpeg also handles what the native downloader can't (MPEG-TS remux, encryption)
+                warn!("Native HLS download failed ({}), falling back to ffmpeg", e);
+                return ffmpeg_stream_copy(url, output_path).await;
+            },
+            Err(e) => return Err(e),
+        }
+    }
     
-    // Use ffmpeg with stream copy to preserve original quality
-    ffmpeg_stream_copy(url, output_path).await
+    // Progressive transcodings are plain HTTP files, so download them directly.
+    // The downloader resumes dropped connections and verifies the Content-Length.
+    debug!("Downloading progressive stream directly");
+    let bytes = crate::progressive::download_progressive(url, output_path).await?;
+    debug!("Progressive stream verified: {} bytes", bytes);
+    Ok(())
 }
 
 /// Use ffmpeg to copy the stream without transcoding
@@ -475,10 +649,7 @@ async fn ffmpeg_stream_copy(url: &str, output_path: &Path) -> Result<(), Box<dyn
     let mut cmd = TokioCommand::new("ffmpeg");
     
     // Check if we should show ffmpeg output
-    let show_output = match crate::config::Config::show_ffmpeg_output() {
-        Some(true) => true,
-        _ => false,
-    };
+    let show_output = matches!(crate::config::Config::show_ffmpeg_output(), Some(true));
     
     cmd.arg("-i")
         .arg(url)
@@ -543,48 +714,9 @@ async fn ffmpeg_stream_copy(url: &str, output_path: &Path) -> Result<(), Box<dyn
 
 /// Transcode a URL to MP3 using ffmpeg (fallback method)
 async fn transcode_to_mp3(url: &str, output_path: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    debug!("Executing ffmpeg MP3 transcoding command");
-    let mut cmd = TokioCommand::new("ffmpeg");
-    
-    // Check if we should show ffmpeg output
-    let show_output = match crate::config::Config::show_ffmpeg_output() {
-        Some(true) => true,
-        _ => false,
-    };
-    
-    cmd.arg("-i")
-        .arg(url)
-        .arg("-c:a")
-        .arg("libmp3lame")
-        .arg("-q:a")
-        .arg("2") // High quality (0-9, lower is better)
-        .arg("-y") // Overwrite output
-        .kill_on_drop(true); // Ensure process is killed if parent process exits
-    
-    // Configure stdout/stderr redirection based on config
-    if !show_output {
-        // Silence ffmpeg output
-        cmd.stdout(std::process::Stdio::null())
-           .stderr(std::process::Stdio::null());
-    }
-    
-    // Add output path
-    cmd.arg(output_path);
-    
-    // Log command (without full URL for privacy/security)
-    debug!("ffmpeg command: -i [url] -c:a libmp3lame -q:a 2 -y {}", 
-          output_path.display());
-    
-    // Execute command
-    let status = cmd.status().await?;
-    
-    if !status.success() {
-        error!("ffmpeg MP3 transcoding failed with exit code: {}", status);
-        return Err(format!("ffmpeg failed with exit code: {}", status).into());
-    }
-    
-    debug!("ffmpeg MP3 transcoding completed successfully");
-    Ok(())
+    let args = ["-c:a", "libmp3lame", "-q:a", "2"] // High quality (0-9, lower is better)
+        .map(String::from);
+    crate::transcode::ffmpeg_transcode(url, output_path, &args).await
 }
 
 /// Clean up temporary files after processing
@@ -617,7 +749,7 @@ pub async fn delete_temp_file(path: &str) -> Result<(), Box<dyn std::error::Erro
 }
 
 /// Sanitize a filename to be safe for the file system
-fn sanitize_filename(filename: &str) -> String {
+pub fn sanitize_filename(filename: &str) -> String {
     // Replace invalid characters with underscores
     let sanitized = filename
         .chars()
@@ -635,6 +767,15 @@ fn sanitize_filename(filename: &str) -> String {
     }
 }
 
+/// Check if ffmpeg is available, caching the result (doesn't log)
+pub fn ffmpeg_available() -> bool {
+    lazy_static::lazy_static! {
+        static ref FFMPEG_AVAILABLE: bool = Command::new("ffmpeg").arg("-version").output().is_ok();
+    }
+    
+    *FFMPEG_AVAILABLE
+}
+
 /// Check if ffmpeg is available
 pub fn check_ffmpeg() -> bool {
     match Command::new("ffmpeg").arg("-version").output() {
@@ -646,9 +787,9 @@ pub fn check_ffmpeg() -> bool {
     }
 }
 
-/// Download artwork from URL
+/// Download artwork (or any other media file) from URL
 async fn download_artwork(url: &str, output_path: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    debug!("Downloading artwork from URL");
+    debug!("Downloading media from URL");
     
     // Create reqwest client
     let client = &HTTP_CLIENT;
@@ -660,22 +801,359 @@ async fn download_artwork(url: &str, output_path: &Path) -> Result<(), Box<dyn s
         .await?;
     
     if !response.status().is_success() {
-        return Err(format!("Failed to download artwork: HTTP {}", response.status()).into());
+        return Err(format!("HTTP {}", response.status()).into());
     }
     
-    // Get the image data
-    let image_data = response.bytes().await?;
+    // Get the file data
+    let data = response.bytes().await?;
     
     // Save to file
     let mut file = TokioFile::create(output_path).await?;
-    file.write_all(&image_data).await?;
+    file.write_all(&data).await?;
     
-    debug!("Artwork downloaded successfully to {}", output_path.display());
+    debug!("Media downloaded successfully to {}", output_path.display());
     Ok(())
 }
 
-/// Save track data as JSON
-async fn save_track_json(track: &Track, output_path: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// Download artwork or other track media, returning the path on success
+async fn save_media(url: &str, output_path: &Path, label: &str) -> Option<String> {
+    match download_artwork(url, output_path).await {
+        Ok(()) => {
+            let file_size = match fs::metadata(output_path) {
+                Ok(metadata) => metadata.len(),
+                Err(_) => 0,
+            };
+            
+            info!("Successfully downloaded {}: {} ({} bytes)", label, output_path.display(), file_size);
+            Some(output_path.to_string_lossy().to_string())
+        },
+        Err(e) => {
+            warn!("Failed to download {}: {}", label, e);
+            None
+        }
+    }
+}
+
+/// File extension for an image URL (SoundCloud serves JPEG, default avatars are PNG)
+fn image_extension(url: &str) -> &'static str {
+    let path = url.split('?').next().unwrap_or(url).to_lowercase();
+    if path.ends_with(".png") {
+        "png"
+    } else {
+        "jpg"
+    }
+}
+
+/// Subfolder of the work directory that holds the original upload
+pub const ORIGINAL_UPLOAD_DIR: &str = "original";
+
+/// Check whether a file path is an original upload downloaded by `download_original`
+pub fn is_original_upload(path: &str) -> bool {
+    Path::new(path)
+        .parent()
+        .and_then(|p| p.file_name())
+        .map(|name| name == ORIGINAL_UPLOAD_DIR)
+        .unwrap_or(false)
+}
+
+/// Download the original uploaded file of a downloadable track
+///
+/// The file keeps the name and extension from the Content-Disposition header. It goes
+/// into its own subfolder so the uploader's filename can't clash with our own files.
+/// Returns the format info (e.g. "original/wav") and the output path.
+async fn download_original(
+    track: &Track,
+    work_dir: &Path
+) -> Result<(String, PathBuf), Box<dyn std::error::Error + Send + Sync>> {
+    let download_url = get_original_download_url(&track.id, track.secret_token.as_deref()).await?;
+    
+    let headers = crate::progressive::fetch_headers(&download_url)
+        .await
+        .map_err(|e| format!("Failed to download original: {}", e))?;
+    
+    // Work out the real filename, falling back to the title and the content type
+    let header_name = headers
+        .get(reqwest::header::CONTENT_DISPOSITION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_content_disposition_filename);
+    
+    let filename = match header_name {
+        Some(name) => {
+            debug!("Original upload filename from Content-Disposition: {}", name);
+            name
+        },
+        None => {
+            let extension = headers
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .map(extension_from_mime)
+                .unwrap_or("audio");
+            debug!("No filename in Content-Disposition, using track title with .{}", extension);
+            format!("{}.{}", track.title, extension)
+        }
+    };
+    
+    // Sanitize stem and extension separately so truncation never eats the extension
+    let name_path = Path::new(&filename);
+    let extension = name_path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_else(|| "audio".to_string());
+    let stem = name_path.file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| track.title.clone());
+    
+    let original_dir = work_dir.join(ORIGINAL_UPLOAD_DIR);
+    fs::create_dir_all(&original_dir)?;
+    let output_path = original_dir.join(format!("{}.{}", sanitize_filename(&stem), sanitize_filename(&extension)));
+    
+    // Originals can be hundreds of megabytes, so stream to disk with resume and length checks
+    if let Err(e) = crate::progressive::download_progressive(&download_url, &output_path).await {
+        let _ = fs::remove_file(&output_path);
+        return Err(format!("Failed to download original: {}", e).into());
+    }
+    
+    Ok((format!("original/{}", extension), output_path))
+}
+
+/// Extract the filename from a Content-Disposition header value
+///
+/// Prefers the RFC 5987 `filename*=UTF-8''...` form over the plain `filename="..."` form.
+fn parse_content_disposition_filename(header: &str) -> Option<String> {
+    let mut plain_name = None;
+    
+    for param in header.split(';').map(str::trim) {
+        if let Some((key, value)) = param.split_once('=') {
+            match key.trim().to_lowercase().as_str() {
+                "filename*" => {
+                    // Format: charset'language'percent-encoded-name
+                    let encoded = value.trim().splitn(3, '\'').nth(2).unwrap_or(value.trim());
+                    let decoded = percent_decode(encoded);
+                    if !decoded.is_empty() {
+                        return Some(decoded);
+                    }
+                },
+                "filename" => {
+                    let name = value.trim().trim_matches('"').to_string();
+                    if !name.is_empty() {
+                        plain_name = Some(name);
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+    
+    plain_name
+}
+
+/// Decode a percent-encoded UTF-8 string (invalid escapes are kept as-is)
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(byte) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Map a Content-Type to a file extension for original uploads
+fn extension_from_mime(mime: &str) -> &'static str {
+    let mime = mime.split(';').next().unwrap_or("").trim().to_lowercase();
+    match mime.as_str() {
+        "audio/wav" | "audio/x-wav" | "audio/wave" => "wav",
+        "audio/flac" | "audio/x-flac" => "flac",
+        "audio/aiff" | "audio/x-aiff" => "aiff",
+        "audio/mpeg" | "audio/mp3" => "mp3",
+        "audio/mp4" | "audio/x-m4a" | "audio/aac" => "m4a",
+        "audio/ogg" => "ogg",
+        _ => "audio",
+    }
+}
+
+/// Embed tags and cover art into every downloaded file except the original upload
+///
+/// The original upload is left byte-for-byte as the uploader provided it.
+/// Tagging failures are logged and never fail the track.
+async fn embed_metadata_in_files(
+    track: &Track,
+    downloaded_files: &[(String, String)],
+    artwork_path: Option<&str>
+) {
+    let cover = match artwork_path {
+        Some(path) => match tokio::fs::read(path).await {
+            Ok(data) => Some(data),
+            Err(e) => {
+                warn!("Failed to read artwork for embedding: {}", e);
+                None
+            }
+        },
+        None => None,
+    };
+    
+    for (format_info, path) in downloaded_files {
+        if is_original_upload(path) {
+            debug!("Leaving original upload untagged: {}", path);
+            continue;
+        }
+        
+        match crate::tags::embed_metadata(Path::new(path), track, cover.clone()).await {
+            Ok(()) => debug!("Tagged {} format: {}", format_info, path),
+            Err(e) => warn!("Failed to embed metadata into {} format: {}", format_info, e),
+        }
+    }
+}
+
+/// Download a file and verify it, downloading it again while it fails verification
+///
+/// `download` runs once per attempt and returns the format info and path of the file.
+/// After `verify_max_retries` extra attempts the last download is returned with its
+/// failed report, and the caller decides what to do with it.
+async fn download_verified<F, Fut>(
+    config: &Config,
+    track: &Track,
+    label: &str,
+    mut download: F
+) -> Result<(String, PathBuf, VerificationReport), Box<dyn std::error::Error + Send + Sync>>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<(String, PathBuf), Box<dyn std::error::Error + Send + Sync>>>,
+{
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let (format_info, path) = download().await?;
+        
+        let mut report = verify_download(config, track, &format_info, &path).await;
+        report.attempts = attempts;
+        
+        if report.failed() && attempts <= config.verify_max_retries {
+            warn!("{} failed verification (attempt {}/{}), downloading again",
+                  label, attempts, config.verify_max_retries + 1);
+            continue;
+        }
+        
+        return Ok((format_info, path, report));
+    }
+}
+
+/// Add a verified download to the files to post, or reject it if it still fails verification
+///
+/// Rejected files are deleted so they can't end up in Discord or the archive, and are
+/// listed with their verification report in the manifest. Returns whether it was accepted.
+fn accept_download(
+    downloaded_files: &mut Vec<(String, String)>,
+    verification: &mut HashMap<String, VerificationReport>,
+    rejected_files: &mut Vec<(String, String)>,
+    format_info: String,
+    path: &Path,
+    report: VerificationReport
+) -> bool {
+    let path_string = path.to_string_lossy().to_string();
+    let accepted = !report.failed();
+    
+    if accepted {
+        downloaded_files.push((format_info, path_string.clone()));
+    } else {
+        warn!("Leaving {} format out of the post, it failed verification {} times: {}",
+              format_info, report.attempts, report.problems.join("; "));
+        if let Err(e) = fs::remove_file(path) {
+            warn!("Failed to remove rejected file {}: {}", path.display(), e);
+        }
+        rejected_files.push((format_info, path_string.clone()));
+    }
+    
+    verification.insert(path_string, report);
+    accepted
+}
+
+/// Verify a downloaded file against the track, unless verification is disabled
+async fn verify_download(
+    config: &Config,
+    track: &Track,
+    format_info: &str,
+    path: &Path
+) -> VerificationReport {
+    if !config.verify_downloads {
+        return VerificationReport::skipped(track.duration, "verification disabled");
+    }
+    
+    crate::verify::verify_audio_file(
+        path,
+        format_info,
+        track.duration,
+        config.verify_duration_tolerance_sec * 1000
+    ).await
+}
+
+/// Build the manifest of downloaded audio files for the JSON snapshot
+fn build_manifest(
+    config: &Config,
+    downloaded_files: &[(String, String)],
+    rejected_files: &[(String, String)],
+    verification: &HashMap<String, VerificationReport>,
+    analysis: Option<&AudioAnalysis>
+) -> Value {
+    let file_name_of = |path: &str| Path::new(path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    
+    let files: Vec<Value> = downloaded_files.iter()
+        .map(|(format_info, path)| {
+            let file_name = file_name_of(path);
+            let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+            
+            serde_json::json!({
+                "file": file_name,
+                "format": format_info,
+                "size_bytes": size,
+                "verification": verification.get(path),
+            })
+        })
+        .collect();
+    
+    let rejected: Vec<Value> = rejected_files.iter()
+        .map(|(format_info, path)| serde_json::json!({
+            "file": file_name_of(path),
+            "format": format_info,
+            "verification": verification.get(path),
+        }))
+        .collect();
+    
+    let analysis = analysis.map(|analysis| serde_json::json!({
+        "file": file_name_of(&analysis.source),
+        "loudness": analysis.loudness,
+        "waveform": analysis.waveform.as_deref().map(file_name_of),
+        "spectrogram": analysis.spectrogram.as_deref().map(file_name_of),
+    }));
+    
+    serde_json::json!({
+        "verification_enabled": config.verify_downloads,
+        "duration_tolerance_sec": config.verify_duration_tolerance_sec,
+        "files": files,
+        "rejected_files": rejected,
+        "analysis": analysis,
+    })
+}
+
+/// Save track data as JSON, with the archive manifest
+async fn save_track_json(
+    track: &Track,
+    output_path: &Path,
+    manifest: &Value
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     debug!("Saving track data as JSON to {}", output_path.display());
     
     // Create a serializable structure with all available data
@@ -683,9 +1161,23 @@ async fn save_track_json(track: &Track, output_path: &Path) -> Result<(), Box<dy
     
     // Add raw_data if available
     if let Some(raw_data) = &track.raw_data {
-        json_data["raw_data"] = raw_data.clone();
+        let mut raw_data = raw_data.clone();
+        
+        // Never archive the share token of private tracks
+        if let Some(obj) = raw_data.as_object_mut() {
+            if obj.remove("secret_token").is_some() {
+                debug!("Removed secret token from track data snapshot");
+            }
+        }
+        
+        json_data["raw_data"] = raw_data;
     }
     
+    // Share links and stream URLs of private tracks carry the token too
+    redact_urls(&mut json_data);
+    
+    json_data["archive_manifest"] = manifest.clone();
+    
     // Serialize to pretty JSON
     let json_string = serde_json::to_string_pretty(&json_data)?;
     
@@ -697,29 +1189,37 @@ async fn save_track_json(track: &Track, output_path: &Path) -> Result<(), Box<dy
     Ok(())
 }
 
+/// Hide the secret tokens in every URL of a JSON snapshot
+fn redact_urls(value: &mut Value) {
+    match value {
+        Value::String(s) if s.starts_with("http") => *s = crate::soundcloud::redact_url(s),
+        Value::Array(items) => items.iter_mut().for_each(redact_urls),
+        Value::Object(obj) => obj.values_mut().for_each(redact_urls),
+        _ => {},
+    }
+}
+
 /// Resolve the stream URL
 async fn resolve_and_download_format(
     format_info: &str, 
     url: &str, 
+    secret_token: Option<&str>,
     output_path: &Path
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     debug!("Resolving and downloading format: {}", format_info);
     
-    match get_stream_url(url).await {
+    match get_stream_url(url, secret_token).await {
         Ok(resolved_url) => {
             // Download the stream
+            // Completeness is checked by the downloaders themselves (Content-Length for
+            // progressive streams, every playlist segment for HLS)
             match download_stream(&resolved_url, output_path).await {
                 Ok(()) => {
-                    // Check if file is large enough to be a valid audio file
                     let file_size = match fs::metadata(output_path) {
                         Ok(metadata) => metadata.len(),
                         Err(_) => 0,
                     };
                     
-                    if file_size < 1024 { // Less than 1KB is suspicious
-                        return Err(format!("Downloaded file too small ({} bytes)", file_size).into());
-                    }
-                    
                     debug!("Successfully downloaded {} format: {} bytes", format_info, file_size);
                     Ok(())
                 },
@@ -731,8 +1231,14 @@ async fn resolve_and_download_format(
             let err_string = e.to_string();
             
             if err_string.contains("HTTP error 401") || err_string.contains("HTTP error 403") {
+                if crate::soundcloud::has_oauth_token() {
+                    // We are authenticated, so the session itself lacks access (e.g. no Go+ plan)
+                    warn!("Format {} is not available to the configured SoundCloud account", format_info);
+                    return Err(format!("Access denied for {} with the configured OAuth token", format_info).into());
+                }
+                
                 // Authentication errors - this is likely a premium-only format
-                warn!("Format {} requires authentication (premium only)", format_info);
+                warn!("Format {} requires authentication (premium only, set soundcloud_oauth_token to unlock)", format_info);
                 return Err(format!("Authentication required for {}", format_info).into());
             } else if err_string.contains("HTTP error 404") {
                 // Resource not found
@@ -746,7 +1252,11 @@ async fn resolve_and_download_format(
     }
 }
 
-// Add a lazy_static HTTP client
+// Add a lazy_static HTTP client (artwork and other small media files)
 lazy_static::lazy_static! {
-    static ref HTTP_CLIENT: reqwest::Client = reqwest::Client::new();
+    static ref HTTP_CLIENT: reqwest::Client = reqwest::Client::builder()
+        .connect_timeout(std::time::Duration::from_secs(15))
+        .timeout(std::time::Duration::from_secs(60))
+        .build()
+        .unwrap();
 } 
\ No newline at end of file
@@ -0,0 +1,165 @@
+use std::collections::HashSet;
+use std::time::Duration;
+use log::{info, warn, debug};
+use serde_json::Value;
+use crate::config::Config;
+use crate::db::{JobState, TrackDatabase};
+use crate::loghandler::{increment_error_count, increment_new_tracks, increment_total_tracks};
+use crate::scheduler::parse_created_at;
+use crate::soundcloud::{self, Track};
+
+/// Which part of a user's history a backfill archives
+#[derive(Debug, Clone)]
+pub struct BackfillOptions {
+    /// Also archive the tracks the user liked, dated by when they were liked
+    pub likes: bool,
+    /// Only tracks from this Unix timestamp on
+    pub since: Option<i64>,
+    /// Only tracks before this Unix timestamp
+    pub until: Option<i64>,
+    /// Shortest time between two Discord posts
+    pub delay: Duration,
+}
+
+/// What a backfill did
+#[derive(Debug, Default)]
+pub struct BackfillSummary {
+    /// Tracks in the date range
+    pub found: usize,
+    /// Tracks that were archived or dead-lettered before
+    pub skipped: usize,
+    pub archived: usize,
+}
+
+/// Archive a user's existing tracks, oldest first
+///
+/// Every track of the range gets a job in the tracks database before the first post, so an
+/// interrupted backfill is finished by running it again. The watcher leaves these jobs alone,
+/// it would post them out of order. Tracks are posted one at a time, at least `delay` apart,
+/// to stay within Discord's webhook rate limits.
+/// A failed download or post stops the backfill, so later tracks aren't posted before it.
+pub async fn backfill_user(
+    config: &Config,
+    db: &mut TrackDatabase,
+    user_id: &str,
+    options: &BackfillOptions,
+) -> Result<BackfillSummary, Box<dyn std::error::Error + Send + Sync>> {
+    let tracks = fetch_history(config, user_id, options).await?;
+    let mut summary = BackfillSummary {
+        found: tracks.len(),
+        ..Default::default()
+    };
+
+    // Left over by an interrupted backfill or watcher, they start over from the download
+    let interrupted = db.reset_interrupted_jobs();
+    if interrupted > 0 {
+        info!("Reset {} jobs that were interrupted by the last shutdown", interrupted);
+    }
+
+    // Queued up front, so the state store knows the whole backfill from the start
+    let mut pending = Vec::new();
+    for track in tracks {
+        if db.has_track(&track.id) || db.is_dead_letter(&track.id) {
+            summary.skipped += 1;
+            continue;
+        }
+        db.enqueue_backfill_job(&track.id, Some(user_id), track.secret_token.as_deref());
+        pending.push(track);
+    }
+    db.save()?;
+
+    info!("Backfilling {} tracks of user {}, {} already archived", pending.len(), user_id, summary.skipped);
+
+    let mut next_post = tokio::time::Instant::now();
+    for (index, track) in pending.iter().enumerate() {
+        info!("Backfill {}/{}: '{}' ({}) from {}", index + 1, pending.len(), track.title, track.id, track.created_at);
+
+        db.set_job_state(&track.id, JobState::Downloading);
+        let archive_index = db.archive_index();
+        let prepared = match soundcloud::prepare_track_with_secret(&track.id, track.secret_token.as_deref(), config, Some(&archive_index), false).await {
+            Ok(prepared) => prepared,
+            Err(e) => {
+                increment_error_count();
+                db.fail_job(&track.id, &e.to_string(), config);
+                db.save()?;
+                return Err(format!(
+                    "Failed to process track {}: {}. {} of {} tracks are archived, run the backfill again to resume",
+                    track.id, e, summary.archived, pending.len()
+                ).into());
+            }
+        };
+
+        db.set_job_state(&track.id, JobState::Posting);
+        db.save()?;
+
+        tokio::time::sleep_until(next_post).await;
+        let result = soundcloud::post_prepared_track(prepared, config, None).await;
+        next_post = tokio::time::Instant::now() + options.delay;
+
+        match result {
+            Ok((_track_id, _uploader_id, response, record)) => {
+                db.finish_job(&track.id, &response, &record);
+                db.save()?;
+                increment_new_tracks(1);
+                increment_total_tracks(1);
+                summary.archived += 1;
+            },
+            Err(e) => {
+                increment_error_count();
+                db.fail_job(&track.id, &e.to_string(), config);
+                db.save()?;
+                return Err(format!(
+                    "Failed to post track {}: {}. {} of {} tracks are archived, run the backfill again to resume",
+                    track.id, e, summary.archived, pending.len()
+                ).into());
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+/// A user's uploads, and likes if asked for, in the date range and oldest first
+async fn fetch_history(
+    config: &Config,
+    user_id: &str,
+    options: &BackfillOptions,
+) -> Result<Vec<Track>, Box<dyn std::error::Error + Send + Sync>> {
+    // The whole history is fetched, not just the configured number of recent tracks
+    let details = soundcloud::get_user_details(user_id).await?;
+    let count = |key: &str, default: usize| {
+        details.get(key).and_then(Value::as_u64).map(|c| c as usize).unwrap_or(default).max(1)
+    };
+
+    let uploads = soundcloud::get_user_tracks(user_id, count("track_count", config.max_tracks_per_user), config.pagination_size).await?;
+    info!("Fetched {} uploads of user {}", uploads.len(), user_id);
+    let mut dated: Vec<(String, Track)> = uploads.into_iter()
+        .map(|track| (track.created_at.clone(), track))
+        .collect();
+
+    if options.likes {
+        let likes = soundcloud::get_user_likes(user_id, count("likes_count", config.max_likes_per_user), config.pagination_size).await?;
+        info!("Fetched {} likes of user {}", likes.len(), user_id);
+        dated.extend(likes.into_iter().map(|like| (like.created_at, like.track)));
+    }
+
+    let mut tracks: Vec<(i64, Track)> = dated.into_iter()
+        .filter_map(|(date, track)| match parse_created_at(&date) {
+            Some(at) => Some((at, track)),
+            None => {
+                warn!("Skipping track {} with unknown date '{}'", track.id, date);
+                None
+            }
+        })
+        .filter(|(at, _)| options.since.is_none_or(|since| *at >= since))
+        .filter(|(at, _)| options.until.is_none_or(|until| *at < until))
+        .collect();
+    tracks.sort_by_key(|(at, _)| *at);
+
+    // A track the user both uploaded and liked is archived once, at the earlier date
+    let mut seen = HashSet::new();
+    tracks.retain(|(_, track)| seen.insert(track.id.clone()));
+    debug!("{} tracks of user {} in the backfill range", tracks.len(), user_id);
+
+    Ok(tracks.into_iter().map(|(_, track)| track).collect())
+}
@@ -1,32 +1,497 @@
 use std::io::{self, Write, BufRead};
 use log::{info, warn, error, debug};
 use std::sync::Arc;
+use clap::{CommandFactory, Parser, Subcommand};
 
+use crate::backfill::{self, BackfillOptions};
 use crate::config::{Config, Users};
-use crate::db::TrackDatabase;
+use crate::db::{DatabaseLock, TrackDatabase};
 use crate::soundcloud;
 use crate::loghandler::update_log_level;
+use crate::scheduler::{JobSchedule, ScheduleConfig};
 
-/// Display help information to the console
-pub fn show_help() {
-    println!("Usage:");
-    println!("  archiver_webhook                 - Run in watcher mode");
-    println!("  archiver_webhook --resolve URL   - Resolve a SoundCloud URL and display info");
-    println!("  archiver_webhook --init-tracks   - Initialize tracks database with existing tracks");
-    println!("  archiver_webhook --post-track ID - Post a specific track to webhook (bypass database)");
-    println!("                               - Can be a track ID or a SoundCloud URL");
-    println!("  archiver_webhook --lookup-discord-id ID - Look up a track by Discord message ID");
-    println!("  archiver_webhook --generate-config URL - Generate config.json and users.json files");
-    println!("                               - URL should be a SoundCloud user profile");
-    println!("  archiver_webhook --help          - Show this help");
+/// Command line of the archiver
+#[derive(Debug, Parser)]
+#[command(name = "archiver_webhook", version, about = "Archives SoundCloud tracks of watched users to a Discord webhook")]
+pub struct Cli {
+    /// Config file (JSON, TOML or YAML), config.json by default
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<String>,
+    
+    /// Log level, overrides log_level from the config
+    #[arg(long, global = true, value_name = "LEVEL", value_parser = ["trace", "debug", "info", "warn", "error"])]
+    pub log_level: Option<String>,
+    
+    /// Override a config setting, e.g. --set schedule.auto_follow=600
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE")]
+    pub overrides: Vec<String>,
+    
+    /// Print what `watch` or `post` would archive without posting or saving anything
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+    
+    /// Download the tracks in a dry run to show the attachments
+    #[arg(long, global = true, requires = "dry_run")]
+    pub download: bool,
+    
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Watch the users and archive their new tracks (the default)
+    Watch,
+    /// Resolve a SoundCloud URL and display info
+    Resolve {
+        url: String,
+    },
+    /// Post a track to the webhook, bypassing the database
+    Post {
+        /// Track ID or SoundCloud URL
+        track: String,
+    },
+    /// Look up a track by its Discord message ID
+    Lookup {
+        message_id: String,
+    },
+    /// Mark the current tracks of all users as known without posting them
+    Init,
+    /// Archive all existing tracks of a user, oldest first
+    Backfill {
+        /// User ID, profile URL or permalink
+        user: String,
+        /// Also archive the tracks the user liked
+        #[arg(long)]
+        likes: bool,
+        /// Only tracks uploaded (or liked) on or after this date, e.g. 2023-01-01
+        #[arg(long, value_name = "DATE", value_parser = parse_since)]
+        since: Option<i64>,
+        /// Only tracks uploaded (or liked) on or before this date
+        #[arg(long, value_name = "DATE", value_parser = parse_until)]
+        until: Option<i64>,
+        /// Seconds between two Discord posts
+        #[arg(long, value_name = "SECONDS", default_value_t = 2)]
+        delay: u64,
+    },
+    /// Manage the watched users
+    #[command(subcommand)]
+    Users(UsersCommand),
+    /// Manage the tracks database
+    #[command(subcommand)]
+    Db(DbCommand),
+    /// Create, check and convert the config
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Print a shell completion script
+    Completions {
+        shell: clap_complete::Shell,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum UsersCommand {
+    /// List the watched users with their usernames and track counts
+    List {
+        /// Only print the IDs, without looking the users up on SoundCloud
+        #[arg(long)]
+        ids: bool,
+    },
+    /// Watch users, given by ID, profile URL or permalink
+    Add {
+        #[arg(required = true)]
+        users: Vec<String>,
+    },
+    /// Stop watching users
+    Remove {
+        #[arg(required = true)]
+        users: Vec<String>,
+    },
+    /// Stop polling users but keep them in the list
+    Pause {
+        #[arg(required = true)]
+        users: Vec<String>,
+    },
+    /// Poll paused users again
+    Resume {
+        #[arg(required = true)]
+        users: Vec<String>,
+    },
+    /// Watch the users listed in a file, one ID or profile URL per line
+    Import {
+        file: String,
+    },
+    /// Write the watched users as CSV
+    Export {
+        /// File to write, stdout by default
+        #[arg(long, short)]
+        output: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DbCommand {
+    /// List tracks that failed too often to be retried
+    DeadLetters,
+    /// Queue a dead-lettered track again
+    Retry {
+        /// Track ID or "all"
+        track_id: String,
+    },
+    /// Give up on a dead-lettered track
+    Discard {
+        /// Track ID or "all"
+        track_id: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Validate the config and print the effective settings
+    Check,
+    /// Write the config file as JSON, TOML or YAML, picked by the extension of PATH
+    Convert {
+        output: String,
+    },
+    /// Create a config and users file from a SoundCloud user's followings
+    Generate {
+        /// SoundCloud profile URL
+        url: String,
+    },
+}
+
+/// Commands of older versions, used as `archiver_webhook --resolve URL`
+const LEGACY_COMMANDS: [(&str, &[&str]); 10] = [
+    ("--resolve", &["resolve"]),
+    ("--init-tracks", &["init"]),
+    ("--post-track", &["post"]),
+    ("--lookup-discord-id", &["lookup"]),
+    ("--dead-letters", &["db", "dead-letters"]),
+    ("--retry-dead-letter", &["db", "retry"]),
+    ("--discard-dead-letter", &["db", "discard"]),
+    ("--check-config", &["config", "check"]),
+    ("--convert-config", &["config", "convert"]),
+    ("--generate-config", &["config", "generate"]),
+];
+
+/// Rewrite a command of an older version to its subcommand, with a warning
+/// 
+/// Runs before the logger is set up, so the warning goes to stderr.
+pub fn translate_legacy_args(mut args: Vec<String>) -> Vec<String> {
+    let position = args.iter().skip(1).position(|arg| LEGACY_COMMANDS.iter().any(|(old, _)| arg == old));
+    if let Some(position) = position {
+        let index = position + 1;
+        let (old, new) = LEGACY_COMMANDS.iter().find(|(old, _)| *old == args[index]).unwrap();
+        eprintln!("{} is deprecated, use `{}` instead", old, new.join(" "));
+        args.splice(index..=index, new.iter().map(|arg| arg.to_string()));
+    }
+    args
+}
+
+/// Print a completion script for the shell to stdout
+pub fn print_completions(shell: clap_complete::Shell) {
+    let mut command = Cli::command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(shell, &mut command, name, &mut io::stdout());
+}
+
+/// Start of a `--since` date, a day (UTC) or an RFC 3339 time
+fn parse_since(value: &str) -> Result<i64, String> {
+    parse_date(value, false)
+}
+
+/// End of an `--until` date, a day includes all of it
+fn parse_until(value: &str) -> Result<i64, String> {
+    parse_date(value, true)
+}
+
+/// Unix timestamp of a date like 2023-01-31 or 2023-01-31T12:00:00Z, the end of a day if `end_of_day`
+fn parse_date(value: &str, end_of_day: bool) -> Result<i64, String> {
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Ok(time.timestamp());
+    }
+    
+    let day = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| format!("invalid date '{}', expected YYYY-MM-DD or an RFC 3339 time", value))?;
+    let day = if end_of_day { day.succ_opt().ok_or("date out of range")? } else { day };
+    Ok(day.and_time(chrono::NaiveTime::MIN).and_utc().timestamp())
+}
+
+/// A looked-up track, user or message doesn't exist
+#[derive(Debug)]
+pub struct NotFound(pub String);
+
+impl std::fmt::Display for NotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for NotFound {}
+
+/// Load the config and users file for the users commands
+/// 
+/// The SoundCloud client is only initialized when `online` is set.
+async fn load_users(online: bool) -> Result<(Config, Users), Box<dyn std::error::Error + Send + Sync>> {
+    let config = Config::load(&Config::path())?;
+    update_log_level(&config.log_level);
+    let users = Users::load(&config.users_file)?;
+    
+    soundcloud::set_oauth_token(config.soundcloud_oauth_token.clone());
+    if online {
+        soundcloud::initialize().await?;
+    }
+    
+    Ok((config, users))
+}
+
+/// ID of a user given by ID, profile URL or permalink, looked up only if it isn't an ID
+async fn user_id_of(input: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let input = input.trim();
+    if !input.is_empty() && input.chars().all(|c| c.is_ascii_digit()) {
+        return Ok(input.to_string());
+    }
+    
+    if soundcloud::get_client_id().is_none() {
+        soundcloud::initialize().await?;
+    }
+    let user = soundcloud::resolve_user(input).await?;
+    user.get("id")
+        .and_then(|v| v.as_u64())
+        .map(|id| id.to_string())
+        .ok_or_else(|| format!("No user ID found for {}", input).into())
+}
+
+/// Profile details of a watched user, empty if the lookup failed
+#[derive(Debug, Default)]
+struct UserDetails {
+    username: String,
+    permalink_url: String,
+    track_count: Option<u64>,
+    followers_count: Option<u64>,
+}
+
+async fn fetch_user_details(user_id: &str) -> UserDetails {
+    match soundcloud::get_user_details(user_id).await {
+        Ok(user) => UserDetails {
+            username: user.get("username").and_then(|v| v.as_str()).unwrap_or_default().to_string(),
+            permalink_url: user.get("permalink_url").and_then(|v| v.as_str()).unwrap_or_default().to_string(),
+            track_count: user.get("track_count").and_then(|v| v.as_u64()),
+            followers_count: user.get("followers_count").and_then(|v| v.as_u64()),
+        },
+        Err(e) => {
+            warn!("Failed to look up user {}: {}", user_id, e);
+            UserDetails::default()
+        }
+    }
+}
+
+/// List the watched users with their usernames and track counts
+pub async fn list_users(ids_only: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (config, users) = load_users(!ids_only).await?;
+    
+    if ids_only {
+        for user_id in &users.users {
+            println!("{}", user_id);
+        }
+        return Ok(());
+    }
+    
+    println!("{} watched user(s) in {}, {} paused:", users.users.len(), config.users_file, users.paused.len());
+    println!("{:<12} {:<32} {:>8}  Status", "ID", "Username", "Tracks");
+    for user_id in &users.users {
+        let details = fetch_user_details(user_id).await;
+        let tracks = details.track_count.map(|c| c.to_string()).unwrap_or_else(|| "?".to_string());
+        let status = if users.paused.contains(user_id) { "paused" } else { "active" };
+        println!("{:<12} {:<32} {:>8}  {}", user_id, details.username, tracks, status);
+    }
+    
+    Ok(())
+}
+
+/// Watch users, given by ID, profile URL or permalink
+/// 
+/// Every user is looked up first, so only existing users are added.
+pub async fn add_users(inputs: &[String]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (config, mut users) = load_users(true).await?;
+    let (added, failed) = add_resolved_users(&mut users, inputs).await;
+    
+    if added > 0 {
+        users.save(&config.users_file)?;
+    }
+    println!("Added {} user(s), {} watched.", added, users.users.len());
+    
+    if failed > 0 {
+        return Err(format!("{} user(s) could not be added", failed).into());
+    }
+    Ok(())
+}
+
+/// Look up and add users, printing the result for each
+/// 
+/// Returns the number of users added and the number that couldn't be looked up.
+async fn add_resolved_users(users: &mut Users, inputs: &[String]) -> (usize, usize) {
+    let mut added = 0;
+    let mut failed = 0;
+    for input in inputs {
+        let user = match soundcloud::resolve_user(input).await {
+            Ok(user) => user,
+            Err(e) => {
+                println!("Skipped {}: {}", input, e);
+                failed += 1;
+                continue;
+            }
+        };
+        
+        let user_id = match user.get("id").and_then(|v| v.as_u64()) {
+            Some(id) => id.to_string(),
+            None => {
+                println!("Skipped {}: no user ID in the response", input);
+                failed += 1;
+                continue;
+            }
+        };
+        let username = user.get("username").and_then(|v| v.as_str()).unwrap_or("Unknown");
+        
+        if users.users.contains(&user_id) {
+            println!("{} ({}) is already watched", username, user_id);
+            continue;
+        }
+        
+        users.users.push(user_id.clone());
+        println!("Added {} ({})", username, user_id);
+        added += 1;
+    }
+    (added, failed)
+}
+
+/// Stop watching users
+pub async fn remove_users(inputs: &[String]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (config, mut users) = load_users(false).await?;
+    
+    let mut missing = Vec::new();
+    let mut removed = 0;
+    for input in inputs {
+        let user_id = user_id_of(input).await?;
+        if !users.users.contains(&user_id) {
+            missing.push(input.clone());
+            continue;
+        }
+        
+        users.users.retain(|id| *id != user_id);
+        users.paused.retain(|id| *id != user_id);
+        println!("Removed {}", user_id);
+        removed += 1;
+    }
+    
+    if removed > 0 {
+        users.save(&config.users_file)?;
+    }
+    println!("Removed {} user(s), {} watched.", removed, users.users.len());
+    
+    if !missing.is_empty() {
+        return Err(NotFound(format!("Not watched: {}", missing.join(", "))).into());
+    }
+    Ok(())
+}
+
+/// Pause or resume polling of watched users
+pub async fn set_users_paused(inputs: &[String], paused: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (config, mut users) = load_users(false).await?;
+    
+    let mut missing = Vec::new();
+    let mut changed = 0;
+    for input in inputs {
+        let user_id = user_id_of(input).await?;
+        if !users.users.contains(&user_id) {
+            missing.push(input.clone());
+            continue;
+        }
+        
+        let is_paused = users.paused.contains(&user_id);
+        if paused && !is_paused {
+            users.paused.push(user_id.clone());
+            println!("Paused {}", user_id);
+            changed += 1;
+        } else if !paused && is_paused {
+            users.paused.retain(|id| *id != user_id);
+            println!("Resumed {}", user_id);
+            changed += 1;
+        }
+    }
+    
+    if changed > 0 {
+        users.save(&config.users_file)?;
+    }
+    println!("{} of {} user(s) paused.", users.paused.len(), users.users.len());
+    
+    if !missing.is_empty() {
+        return Err(NotFound(format!("Not watched: {}", missing.join(", "))).into());
+    }
+    Ok(())
+}
+
+/// Watch the users listed in a file, one ID or profile URL per line
+/// 
+/// Blank lines and lines starting with `#` are skipped.
+pub async fn import_users(file: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let text = std::fs::read_to_string(file)
+        .map_err(|e| format!("Failed to read {}: {}", file, e))?;
+    let inputs: Vec<String> = text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect();
+    
+    println!("Importing {} user(s) from {}", inputs.len(), file);
+    add_users(&inputs).await
+}
+
+/// Write the watched users as CSV, to a file or stdout
+pub async fn export_users(output: Option<&str>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (_config, users) = load_users(true).await?;
+    
+    let mut csv = String::from("user_id,username,permalink_url,track_count,followers_count,paused\n");
+    for user_id in &users.users {
+        let details = fetch_user_details(user_id).await;
+        let row = [
+            user_id.clone(),
+            details.username,
+            details.permalink_url,
+            details.track_count.map(|c| c.to_string()).unwrap_or_default(),
+            details.followers_count.map(|c| c.to_string()).unwrap_or_default(),
+            users.paused.contains(user_id).to_string(),
+        ];
+        let row: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    
+    match output {
+        Some(path) => {
+            std::fs::write(path, csv)?;
+            println!("Exported {} user(s) to {}", users.users.len(), path);
+        },
+        None => print!("{}", csv),
+    }
+    Ok(())
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
 }
 
 /// Resolve a SoundCloud URL and display information
 pub async fn resolve_soundcloud_url(url: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Load config to get log level
-    let config_path = "config.json";
+    let config_path = Config::path();
     debug!("Loading configuration from {}", config_path);
-    match Config::load(config_path) {
+    let config = match Config::load(&config_path) {
         Ok(c) => {
             debug!("Configuration loaded successfully");
             debug!("Log level: {}", c.log_level);
@@ -41,6 +506,7 @@ pub async fn resolve_soundcloud_url(url: &str) -> Result<(), Box<dyn std::error:
     };
     
     // Initialize SoundCloud client
+    soundcloud::set_oauth_token(config.soundcloud_oauth_token.clone());
     match soundcloud::initialize().await {
         Ok(_) => info!("SoundCloud client initialized successfully"),
         Err(e) => {
@@ -56,9 +522,9 @@ pub async fn resolve_soundcloud_url(url: &str) -> Result<(), Box<dyn std::error:
 /// Initialize tracks database with all existing tracks from all users
 pub async fn initialize_tracks_database() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Load config
-    let config_path = "config.json";
+    let config_path = Config::path();
     info!("Loading configuration from {}", config_path);
-    let config = match Config::load(config_path) {
+    let config = match Config::load(&config_path) {
         Ok(c) => {
             debug!("Configuration loaded successfully");
             debug!("Log level: {}", c.log_level);
@@ -92,6 +558,15 @@ pub async fn initialize_tracks_database() -> Result<(), Box<dyn std::error::Erro
         return Err("No users found".into());
     }
     
+    // A running watcher would overwrite our changes with its own copy of the database
+    let _db_lock = match DatabaseLock::acquire(&config.tracks_file) {
+        Ok(lock) => lock,
+        Err(e) => {
+            error!("Failed to lock tracks database: {}", e);
+            return Err(e);
+        }
+    };
+    
     // Initialize database
     let tracks_db_path = config.tracks_file.clone();
     let mut db = match TrackDatabase::load_or_create(tracks_db_path) {
@@ -107,6 +582,7 @@ pub async fn initialize_tracks_database() -> Result<(), Box<dyn std::error::Erro
     
     // Initialize SoundCloud client
     info!("Initializing SoundCloud client");
+    soundcloud::set_oauth_token(config.soundcloud_oauth_token.clone());
     match soundcloud::initialize().await {
         Ok(_) => info!("SoundCloud client initialized successfully"),
         Err(e) => {
@@ -148,9 +624,9 @@ pub async fn initialize_tracks_database() -> Result<(), Box<dyn std::error::Erro
 /// Post a single track to the webhook without checking the database
 pub async fn post_single_track(id_or_url: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Load config
-    let config_path = "config.json";
+    let config_path = Config::path();
     info!("Loading configuration from {}", config_path);
-    let config = match Config::load(config_path) {
+    let config = match Config::load(&config_path) {
         Ok(c) => {
             debug!("Configuration loaded successfully");
             debug!("Log level: {}", c.log_level);
@@ -165,6 +641,15 @@ pub async fn post_single_track(id_or_url: &str) -> Result<(), Box<dyn std::error
         }
     };
     
+    // A running watcher would overwrite our changes with its own copy of the database
+    let _db_lock = match DatabaseLock::acquire(&config.tracks_file) {
+        Ok(lock) => lock,
+        Err(e) => {
+            error!("Failed to lock tracks database: {}", e);
+            return Err(e);
+        }
+    };
+    
     // Initialize database to store the Discord message ID
     let tracks_db_path = config.tracks_file.clone();
     let mut db = match TrackDatabase::load_or_create(tracks_db_path) {
@@ -180,6 +665,7 @@ pub async fn post_single_track(id_or_url: &str) -> Result<(), Box<dyn std::error
     
     // Initialize SoundCloud client
     info!("Initializing SoundCloud client");
+    soundcloud::set_oauth_token(config.soundcloud_oauth_token.clone());
     match soundcloud::initialize().await {
         Ok(_) => info!("SoundCloud client initialized successfully"),
         Err(e) => {
@@ -192,13 +678,14 @@ pub async fn post_single_track(id_or_url: &str) -> Result<(), Box<dyn std::error
     let discord_semaphore = Arc::new(tokio::sync::Semaphore::new(config.max_discord_parallelism));
     
     // Use our modularized function to process and post the track
+    let archive_index = db.archive_index();
     let result = match soundcloud::process_and_post_track(
         id_or_url, 
-        &config.discord_webhook_url, 
-        config.temp_dir.as_deref(),
+        &config,
+        Some(&archive_index),
         Some(&discord_semaphore)
     ).await {
-        Ok((track_id, user_id, webhook_response)) => {
+        Ok((track_id, user_id, webhook_response, record)) => {
             // Store the Discord message ID in the database
             db.add_track_with_discord_info(
                 &track_id,
@@ -206,6 +693,7 @@ pub async fn post_single_track(id_or_url: &str) -> Result<(), Box<dyn std::error
                 webhook_response.channel_id.clone(),
                 Some(user_id)
             );
+            db.add_archive_record(&track_id, &record, &webhook_response.attached_files);
             
             // Save the database
             if let Err(e) = db.save() {
@@ -223,6 +711,29 @@ pub async fn post_single_track(id_or_url: &str) -> Result<(), Box<dyn std::error
     result
 }
 
+/// Archive the existing tracks of a user, given by ID, profile URL or permalink
+pub async fn backfill(input: &str, options: &BackfillOptions) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let config = load_config()?;
+    Config::set_show_ffmpeg_output(config.show_ffmpeg_output);
+    
+    // The watcher would overwrite the jobs we queue, so don't run next to it
+    let _db_lock = DatabaseLock::acquire(&config.tracks_file)?;
+    let mut db = load_database(&config)?;
+    
+    soundcloud::set_oauth_token(config.soundcloud_oauth_token.clone());
+    soundcloud::initialize().await?;
+    
+    let user_id = user_id_of(input).await?;
+    let summary = backfill::backfill_user(&config, &mut db, &user_id, options).await?;
+    
+    println!("Backfill of user {} complete!", user_id);
+    println!("Tracks in range: {}", summary.found);
+    println!("Archived: {}", summary.archived);
+    println!("Already archived: {}", summary.skipped);
+    
+    Ok(())
+}
+
 /// Generate config.json and users.json files interactively based on a SoundCloud user's followings
 pub async fn generate_config(url: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     println!("Generating configuration based on SoundCloud user: {}", url);
@@ -321,8 +832,8 @@ pub async fn generate_config(url: &str) -> Result<(), Box<dyn std::error::Error
         }
     }
     
-    // Generate the config.json file
-    println!("\nGenerating config.json and users.json files...");
+    // Generate the config file
+    println!("\nGenerating {} and users.json files...", Config::path());
     
     // Ask for config values
     println!("\nEnter Discord webhook URL [required]: ");
@@ -398,20 +909,12 @@ pub async fn generate_config(url: &str) -> Result<(), Box<dyn std::error::Error
         Some(auto_follow_input)
     };
     
-    println!("\nHow often to check for new followings (in poll cycles) [24]: ");
-    let auto_follow_interval = read_line_with_default("24")
-        .parse::<usize>()
-        .unwrap_or(24);
-    
-    println!("\nHow often to save the database (in poll cycles) [1]: ");
-    let db_save_interval = read_line_with_default("1")
-        .parse::<usize>()
-        .unwrap_or(1);
-
-    println!("\nNumber of tracks to process before saving database [5]: ");
-    let db_save_tracks = read_line_with_default("5")
-        .parse::<usize>()
-        .unwrap_or(5);
+    println!("\nHow often to check for new followings (seconds or cron expression) [3600]: ");
+    let auto_follow_input = read_line_with_default("3600");
+    let auto_follow_schedule = auto_follow_input.parse::<JobSchedule>().unwrap_or_else(|e| {
+        println!("{}, checking every hour instead", e);
+        JobSchedule::Every(std::time::Duration::from_secs(3600))
+    });
     
     println!("\nShow ffmpeg output in console? (true/false) [false]: ");
     let show_ffmpeg_output = read_line_with_default("false")
@@ -421,6 +924,14 @@ pub async fn generate_config(url: &str) -> Result<(), Box<dyn std::error::Error
     println!("\nEnter log file path [latest.log]: ");
     let log_file = read_line_with_default("latest.log");
     
+    println!("\nEnter SoundCloud OAuth token (optional, unlocks Go+ and private tracks): ");
+    let oauth_input = read_line_with_default("");
+    let soundcloud_oauth_token = if oauth_input.trim().is_empty() {
+        None
+    } else {
+        Some(oauth_input)
+    };
+    
     // Create the config
     let config = Config {
         discord_webhook_url,
@@ -437,28 +948,33 @@ pub async fn generate_config(url: &str) -> Result<(), Box<dyn std::error::Error
         scrape_user_likes,
         max_likes_per_user,
         auto_follow_source,
-        auto_follow_interval,
-        db_save_interval,
-        db_save_tracks,
+        schedule: ScheduleConfig {
+            auto_follow: Some(auto_follow_schedule),
+            ..ScheduleConfig::default()
+        },
         show_ffmpeg_output,
         log_file,
+        soundcloud_oauth_token,
+        // Settings without a prompt keep their defaults
+        ..Config::default()
     };
     
     // Create the users
     let users = Users {
         users: user_ids,
+        ..Users::default()
     };
     
-    // Save config.json
-    let config_json = serde_json::to_string_pretty(&config)?;
-    std::fs::write("config.json", config_json)?;
+    // Save the config file
+    let config_path = Config::path();
+    let config_text = crate::configformat::config_to_string(&config, &config_path)?;
+    std::fs::write(&config_path, config_text)?;
     
-    // Save users.json
-    let users_json = serde_json::to_string_pretty(&users)?;
-    std::fs::write(&users_file, users_json)?;
+    // Save the users file
+    users.save(&users_file)?;
     
     println!("\nConfiguration completed!");
-    println!("- Created config.json file");
+    println!("- Created {} file", config_path);
     println!("- Created {} file with {} users", users_file, users.users.len());
     println!("\nYou can now run the application in watcher mode:\n  ./archiver_webhook");
     
@@ -501,9 +1017,9 @@ pub fn read_line_with_default(default: &str) -> String {
 /// Look up a track by its Discord message ID
 pub async fn lookup_by_discord_id(discord_id: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Load config
-    let config_path = "config.json";
+    let config_path = Config::path();
     info!("Loading configuration from {}", config_path);
-    let config = match Config::load(config_path) {
+    let config = match Config::load(&config_path) {
         Ok(c) => {
             debug!("Configuration loaded successfully");
             debug!("Log level: {}", c.log_level);
@@ -549,6 +1065,7 @@ pub async fn lookup_by_discord_id(discord_id: &str) -> Result<(), Box<dyn std::e
         
         // Initialize SoundCloud client to get track details
         info!("Initializing SoundCloud client to get track details");
+        soundcloud::set_oauth_token(config.soundcloud_oauth_token.clone());
         match soundcloud::initialize().await {
             Ok(_) => info!("SoundCloud client initialized successfully"),
             Err(e) => {
@@ -601,7 +1118,269 @@ pub async fn lookup_by_discord_id(discord_id: &str) -> Result<(), Box<dyn std::e
         
         Ok(())
     } else {
-        println!("No track found with Discord message ID: {}", discord_id);
-        Ok(())
+        Err(NotFound(format!("No track found with Discord message ID: {}", discord_id)).into())
+    }
+} 
+/// Load config and tracks database for the dead-letter commands
+fn load_config_and_database() -> Result<(Config, TrackDatabase), Box<dyn std::error::Error + Send + Sync>> {
+    let config = load_config()?;
+    let db = load_database(&config)?;
+    Ok((config, db))
+}
+
+/// Load the config and apply its log level
+fn load_config() -> Result<Config, Box<dyn std::error::Error + Send + Sync>> {
+    let config_path = Config::path();
+    info!("Loading configuration from {}", config_path);
+    let config = match Config::load(&config_path) {
+        Ok(c) => {
+            debug!("Configuration loaded successfully");
+            update_log_level(&c.log_level);
+            c
+        },
+        Err(e) => {
+            error!("Failed to load config: {}", e);
+            return Err(e);
+        }
+    };
+    
+    Ok(config)
+}
+
+/// Load the tracks database named in the config
+fn load_database(config: &Config) -> Result<TrackDatabase, Box<dyn std::error::Error + Send + Sync>> {
+    let db = match TrackDatabase::load_or_create(config.tracks_file.clone()) {
+        Ok(d) => {
+            debug!("Tracks database loaded from {}", d.db_path);
+            d
+        },
+        Err(e) => {
+            error!("Failed to load tracks database: {}", e);
+            return Err(e);
+        }
+    };
+    
+    Ok(db)
+}
+
+/// List tracks that failed too often to be retried automatically
+pub fn list_dead_letters() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (_config, db) = load_config_and_database()?;
+    
+    let jobs = db.dead_letters();
+    if jobs.is_empty() {
+        println!("No dead-lettered tracks.");
+        return Ok(());
+    }
+    
+    println!("{} dead-lettered track(s):", jobs.len());
+    for job in jobs {
+        let failed_at = chrono::DateTime::from_timestamp(job.updated_at, 0)
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+            .unwrap_or_else(|| job.updated_at.to_string());
+        
+        println!("\n- Track ID: {}", job.track_id);
+        if let Some(user_id) = &job.user_id {
+            println!("  User ID: {}", user_id);
+        }
+        println!("  Attempts: {}", job.attempts);
+        println!("  Last failure: {}", failed_at);
+        if let Some(error) = &job.last_error {
+            println!("  Last error: {}", error);
+        }
+    }
+    
+    Ok(())
+}
+
+/// Retry or discard dead-lettered tracks, `track_id` may be "all"
+pub fn resolve_dead_letters(track_id: &str, retry: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let config = load_config()?;
+    
+    // The watcher would overwrite the change with its own copy of the database
+    let _db_lock = DatabaseLock::acquire(&config.tracks_file)?;
+    let mut db = load_database(&config)?;
+    
+    let track_ids: Vec<String> = if track_id == "all" {
+        db.dead_letters().into_iter().map(|job| job.track_id).collect()
+    } else {
+        vec![track_id.to_string()]
+    };
+    
+    let mut changed = 0;
+    for id in &track_ids {
+        let found = if retry {
+            db.retry_dead_letter(id)
+        } else {
+            db.discard_dead_letter(id)
+        };
+        
+        if found {
+            changed += 1;
+        }
+    }
+    
+    if changed == 0 {
+        // Only a single track can be missing, "all" takes the IDs from the list
+        if track_id != "all" {
+            return Err(NotFound(format!("Track {} is not on the dead-letter list", track_id)).into());
+        }
+        println!("No dead-lettered tracks changed.");
+        return Ok(());
+    }
+    
+    db.save()?;
+    
+    if retry {
+        println!("Queued {} track(s) again, they will be archived on the next poll or startup.", changed);
+    } else {
+        println!("Discarded {} track(s), they will not be archived.", changed);
+    }
+    
+    Ok(())
+}
+
+/// Validate the config and users files and print the effective configuration
+/// 
+/// Defaults are filled in and secrets are redacted.
+pub fn check_config() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let config_path = Config::path();
+    let config = match Config::load(&config_path) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Config check failed: {}", e);
+            return Err(e);
+        }
+    };
+    
+    if std::path::Path::new(&config_path).exists() {
+        println!("{} is valid.", config_path);
+    } else {
+        println!("No {} found, the configuration is valid with defaults and overrides.", config_path);
+    }
+    
+    let overrides = Config::overrides()?;
+    if !overrides.is_empty() {
+        println!("Overridden settings:");
+        for o in &overrides {
+            println!("  {} (from {})", o.key, o.source);
+        }
+    }
+    
+    // Users::load creates a missing file, a check shouldn't write anything
+    if std::path::Path::new(&config.users_file).exists() {
+        let users = match Users::load(&config.users_file) {
+            Ok(u) => u,
+            Err(e) => {
+                error!("Failed to load users from {}: {}", config.users_file, e);
+                return Err(e);
+            }
+        };
+        println!("{} lists {} user(s).", config.users_file, users.users.len());
+    } else {
+        println!("{} does not exist yet, it is created empty on startup.", config.users_file);
+    }
+    println!("\nEffective configuration (secrets redacted):");
+    println!("{}", serde_json::to_string_pretty(&config.redacted())?);
+    
+    Ok(())
+}
+
+/// Write the config file in another format, picked by the extension of `output`
+/// 
+/// Only the config file is converted, settings from the environment or `--set` are left out.
+pub fn convert_config(output: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let config_path = Config::path();
+    if std::path::Path::new(output).exists() {
+        return Err(format!("{} already exists, not overwriting it", output).into());
     }
-} 
\ No newline at end of file
+    
+    let config = match Config::load_file(&config_path) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Failed to load config: {}", e);
+            return Err(e);
+        }
+    };
+    
+    let config_text = crate::configformat::config_to_string(&config, output)?;
+    std::fs::write(output, config_text)?;
+    
+    println!("Converted {} to {} ({}).", config_path, output, crate::configformat::ConfigFormat::from_path(output));
+    println!("Use it with --config {}.", output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+    
+    #[test]
+    fn since_is_the_start_and_until_the_end_of_a_day() {
+        // 2023-01-31T00:00:00Z and 2023-02-01T00:00:00Z
+        assert_eq!(parse_since("2023-01-31"), Ok(1_675_123_200));
+        assert_eq!(parse_until("2023-01-31"), Ok(1_675_209_600));
+        assert_eq!(parse_until("2023-12-31"), Ok(1_704_067_200));
+    }
+    
+    #[test]
+    fn times_are_taken_as_they_are() {
+        assert_eq!(parse_since("2023-01-31T12:00:00Z"), Ok(1_675_166_400));
+        assert_eq!(parse_until("2023-01-31T12:00:00Z"), Ok(1_675_166_400));
+        assert_eq!(parse_until("2023-01-31T13:00:00+01:00"), Ok(1_675_166_400));
+    }
+    
+    #[test]
+    fn invalid_dates_are_rejected() {
+        for value in ["2023-02-30", "31.01.2023", "2023-01-31 12:00", ""] {
+            let error = parse_date(value, false).unwrap_err();
+            assert!(error.starts_with("invalid date"), "{}: {}", value, error);
+        }
+        assert_eq!(parse_date(&chrono::NaiveDate::MAX.format("%Y-%m-%d").to_string(), true),
+                   Err("date out of range".to_string()));
+    }
+    
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("123456"), "123456");
+        assert_eq!(csv_field("DJ Name"), "DJ Name");
+        assert_eq!(csv_field("Artist, The"), "\"Artist, The\"");
+        assert_eq!(csv_field("The \"Artist\""), "\"The \"\"Artist\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field(""), "");
+    }
+    
+    #[test]
+    fn legacy_commands_become_subcommands() {
+        assert_eq!(translate_legacy_args(args(&["archiver_webhook", "--resolve", "https://soundcloud.com/artist"])),
+                   args(&["archiver_webhook", "resolve", "https://soundcloud.com/artist"]));
+        assert_eq!(translate_legacy_args(args(&["archiver_webhook", "--config", "c.toml", "--retry-dead-letter", "all"])),
+                   args(&["archiver_webhook", "--config", "c.toml", "db", "retry", "all"]));
+    }
+    
+    #[test]
+    fn current_commands_are_left_alone() {
+        let current = args(&["archiver_webhook", "db", "retry", "all"]);
+        assert_eq!(translate_legacy_args(current.clone()), current);
+        
+        // The program name is never a command
+        let named = args(&["--resolve", "users", "list"]);
+        assert_eq!(translate_legacy_args(named.clone()), named);
+    }
+    
+    #[test]
+    fn legacy_commands_map_to_existing_subcommands() {
+        let command = Cli::command();
+        for (old, new) in LEGACY_COMMANDS {
+            let mut subcommand = &command;
+            for name in new {
+                subcommand = subcommand.find_subcommand(name)
+                    .unwrap_or_else(|| panic!("{} maps to unknown command {}", old, new.join(" ")));
+            }
+        }
+    }
+}
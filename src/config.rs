@@ -1,13 +1,35 @@
 use serde::{Deserialize, Serialize};
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::{BufWriter, Write};
 use std::path::Path;
 use log::{info, warn, debug, error};
 use serde_json::Value;
 use std::fs;
-use lazy_static;
+use crate::configformat::ConfigFormat;
+use crate::formats::FormatPolicy;
+use crate::scheduler::{JobSchedule, ScheduleConfig};
+use crate::transcode::TranscodeProfile;
+
+/// Config file used when neither `--config` nor `SCARCHIVE_CONFIG` is given and none of
+/// `DEFAULT_CONFIG_PATHS` exists
+pub const DEFAULT_CONFIG_PATH: &str = "config.json";
+
+/// Config files looked for in the working directory, in order
+const DEFAULT_CONFIG_PATHS: [&str; 4] = ["config.json", "config.toml", "config.yaml", "config.yml"];
+
+/// Prefix of the environment variables that override config settings
+const ENV_PREFIX: &str = "SCARCHIVE_";
+
+// Process-wide copy of the show_ffmpeg_output setting, read from audio.rs
+lazy_static::lazy_static! {
+    static ref SHOW_FFMPEG_OUTPUT: std::sync::Mutex<Option<bool>> = std::sync::Mutex::new(None);
+    // Set from the --config and --set command line options
+    static ref CONFIG_PATH: std::sync::Mutex<Option<String>> = std::sync::Mutex::new(None);
+    static ref CLI_OVERRIDES: std::sync::Mutex<Vec<ConfigOverride>> = std::sync::Mutex::new(Vec::new());
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     // Discord webhook URL for sending notifications
     pub discord_webhook_url: String,
@@ -17,6 +39,18 @@ pub struct Config {
     // Poll interval in seconds
     #[serde(default = "default_poll_interval")]
     pub poll_interval_sec: u64,
+    // Poll each user based on their upload activity instead of every poll_interval_sec
+    #[serde(default)]
+    pub adaptive_polling: bool,
+    // Shortest interval between polls of a user with adaptive polling
+    #[serde(default = "default_min_poll_interval")]
+    pub min_poll_interval_sec: u64,
+    // Longest interval between polls of a user with adaptive polling
+    #[serde(default = "default_max_poll_interval")]
+    pub max_poll_interval_sec: u64,
+    // Random jitter added to adaptive poll intervals, in percent of the interval
+    #[serde(default = "default_poll_jitter_percent")]
+    pub poll_jitter_percent: u64,
     // Path to the JSON file containing watchlisted user IDs
     #[serde(default = "default_users_file")]
     pub users_file: String,
@@ -48,27 +82,80 @@ pub struct Config {
     pub max_likes_per_user: usize,
     /// User ID or URL to monitor for new followings to add
     pub auto_follow_source: Option<String>,
-    /// How often to check for new followings (in poll cycles)
-    #[serde(default = "default_auto_follow_interval")]
-    pub auto_follow_interval: usize,
-    /// How often to save the database (in poll cycles)
-    #[serde(default = "default_db_save_interval")]
-    pub db_save_interval: usize,
-    /// How many new tracks to process before saving the database
-    #[serde(default = "default_db_save_tracks")]
-    pub db_save_tracks: usize,
+    /// When the poll and the background maintenance jobs run
+    #[serde(default)]
+    pub schedule: ScheduleConfig,
     /// Whether to show ffmpeg output in console
     #[serde(default = "default_show_ffmpeg_output")]
     pub show_ffmpeg_output: bool,
     /// Path to log file (defaults to latest.log)
     #[serde(default = "default_log_file")]
     pub log_file: String,
+    /// OAuth token of a logged-in SoundCloud session (optional)
+    /// Unlocks Go+ HQ transcodings and private share-link tracks. Never logged.
+    #[serde(default)]
+    pub soundcloud_oauth_token: Option<String>,
+    /// Whether to probe downloaded audio with ffprobe and retry broken files
+    #[serde(default = "default_verify_downloads")]
+    pub verify_downloads: bool,
+    /// Allowed difference between the probed and reported duration, in seconds
+    #[serde(default = "default_verify_duration_tolerance_sec")]
+    pub verify_duration_tolerance_sec: u64,
+    /// How many times a format is downloaded again after failing verification
+    /// Files that still fail are left out of the post
+    #[serde(default = "default_verify_max_retries")]
+    pub verify_max_retries: usize,
+    /// Whether to write tags and cover art into downloaded audio files
+    #[serde(default = "default_embed_metadata")]
+    pub embed_metadata: bool,
+    /// Whether to link files that were already uploaded instead of attaching them again
+    #[serde(default = "default_deduplicate_uploads")]
+    pub deduplicate_uploads: bool,
+    /// Whether to fingerprint tracks (fpcalc or ffmpeg with chromaprint) to detect re-uploads
+    #[serde(default = "default_fingerprint_tracks")]
+    pub fingerprint_tracks: bool,
+    /// Fingerprint similarity (0.0-1.0) from which a track is flagged as a possible re-upload
+    #[serde(default = "default_reupload_similarity_threshold")]
+    pub reupload_similarity_threshold: f64,
+    /// Whether to measure loudness and render waveform and spectrogram images of each track
+    #[serde(default = "default_analyze_audio")]
+    pub analyze_audio: bool,
+    /// Which transcodings to download and whether to fall back to transcoding MP3
+    #[serde(default)]
+    pub format_policy: FormatPolicy,
+    /// Extra files transcoded from the best download of each track
+    #[serde(default)]
+    pub transcode_profiles: Vec<TranscodeProfile>,
+    /// How many new tracks may wait for a download worker before polls wait
+    #[serde(default = "default_job_queue_size")]
+    pub job_queue_size: usize,
+    /// How many times a track may fail before it moves to the dead-letter list
+    #[serde(default = "default_retry_max_attempts")]
+    pub retry_max_attempts: u32,
+    /// Delay before the first retry of a failed track, doubled for every further failure
+    #[serde(default = "default_retry_base_delay_sec")]
+    pub retry_base_delay_sec: u64,
+    /// Upper limit for the retry delay
+    #[serde(default = "default_retry_max_delay_sec")]
+    pub retry_max_delay_sec: u64,
 }
 
 fn default_poll_interval() -> u64 {
     60 // Default to 1 minute
 }
 
+fn default_min_poll_interval() -> u64 {
+    60 // Active users are polled as often as with the fixed interval
+}
+
+fn default_max_poll_interval() -> u64 {
+    21600 // Dormant users are still polled every 6 hours
+}
+
+fn default_poll_jitter_percent() -> u64 {
+    10
+}
+
 fn default_users_file() -> String {
     "users.json".to_string()
 }
@@ -116,21 +203,6 @@ fn default_max_likes_per_user() -> usize {
     500 // Default to 500 likes per user (increased from 50)
 }
 
-/// Default interval for checking new follows (in poll cycles)
-fn default_auto_follow_interval() -> usize {
-    24 // Check once per day with default poll interval of 60 seconds
-}
-
-/// Default interval for saving the database (in poll cycles)
-fn default_db_save_interval() -> usize {
-    1 // Save after every poll by default
-}
-
-/// Default number of tracks to process before saving the database
-fn default_db_save_tracks() -> usize {
-    50 // Save after processing 5 new tracks
-}
-
 /// Default setting for showing ffmpeg output
 fn default_show_ffmpeg_output() -> bool {
     false // Off by default to reduce console clutter
@@ -141,12 +213,76 @@ fn default_log_file() -> String {
     "latest.log".to_string()
 }
 
+/// Default setting for verifying downloaded audio
+fn default_verify_downloads() -> bool {
+    true // Catch truncated or corrupt files before they get posted
+}
+
+/// Default duration tolerance for verification
+fn default_verify_duration_tolerance_sec() -> u64 {
+    2 // Container durations are rarely exact, allow a couple of seconds
+}
+
+/// Default number of re-downloads for files that fail verification
+fn default_verify_max_retries() -> usize {
+    2
+}
+
+/// Default setting for tagging downloaded audio
+fn default_embed_metadata() -> bool {
+    true
+}
+
+/// Default setting for skipping uploads of already archived files
+fn default_deduplicate_uploads() -> bool {
+    true
+}
+
+/// Default setting for fingerprinting tracks
+fn default_fingerprint_tracks() -> bool {
+    true
+}
+
+/// Default similarity for flagging re-uploads
+fn default_reupload_similarity_threshold() -> f64 {
+    0.85 // Unrelated tracks score around 0.5, re-encodes of the same audio above 0.9
+}
+
+/// Default size of the job queue between polls and download workers
+fn default_job_queue_size() -> usize {
+    100
+}
+
+/// Default number of failures before a track is dead-lettered
+fn default_retry_max_attempts() -> u32 {
+    5
+}
+
+/// Default delay before the first retry of a failed track
+fn default_retry_base_delay_sec() -> u64 {
+    300 // 5 minutes, then 10, 20, 40...
+}
+
+/// Default upper limit for the retry delay
+fn default_retry_max_delay_sec() -> u64 {
+    86400 // 1 day
+}
+
+/// Default setting for audio analysis
+fn default_analyze_audio() -> bool {
+    false // Decodes every track a second time, so only on request
+}
+
 impl Default for Config {
     fn default() -> Self {
         Config {
             discord_webhook_url: "".to_string(),
             log_level: default_log_level(),
             poll_interval_sec: default_poll_interval(),
+            adaptive_polling: false,
+            min_poll_interval_sec: default_min_poll_interval(),
+            max_poll_interval_sec: default_max_poll_interval(),
+            poll_jitter_percent: default_poll_jitter_percent(),
             users_file: default_users_file(),
             tracks_file: default_tracks_file(),
             max_tracks_per_user: default_max_tracks_per_user(),
@@ -158,179 +294,428 @@ impl Default for Config {
             scrape_user_likes: default_scrape_user_likes(),
             max_likes_per_user: default_max_likes_per_user(),
             auto_follow_source: None,
-            auto_follow_interval: default_auto_follow_interval(),
-            db_save_interval: default_db_save_interval(),
-            db_save_tracks: default_db_save_tracks(),
+            schedule: ScheduleConfig::default(),
             show_ffmpeg_output: default_show_ffmpeg_output(),
             log_file: default_log_file(),
+            soundcloud_oauth_token: None,
+            verify_downloads: default_verify_downloads(),
+            verify_duration_tolerance_sec: default_verify_duration_tolerance_sec(),
+            verify_max_retries: default_verify_max_retries(),
+            embed_metadata: default_embed_metadata(),
+            deduplicate_uploads: default_deduplicate_uploads(),
+            fingerprint_tracks: default_fingerprint_tracks(),
+            reupload_similarity_threshold: default_reupload_similarity_threshold(),
+            analyze_audio: default_analyze_audio(),
+            format_policy: FormatPolicy::default(),
+            transcode_profiles: Vec::new(),
+            job_queue_size: default_job_queue_size(),
+            retry_max_attempts: default_retry_max_attempts(),
+            retry_base_delay_sec: default_retry_base_delay_sec(),
+            retry_max_delay_sec: default_retry_max_delay_sec(),
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Users {
-    pub users: Vec<String>,
+/// The config is missing or invalid
+#[derive(Debug)]
+pub struct ConfigError(pub String);
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
 }
 
-impl Config {
-    pub fn load(config_path: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
-        if !Path::new(config_path).exists() {
-            warn!("Config file not found at {}, creating default config", config_path);
-            let default_config = Config::default();
-            let json = serde_json::to_string_pretty(&default_config)?;
-            std::fs::write(config_path, json)?;
-            return Ok(default_config);
+impl std::error::Error for ConfigError {}
+
+/// A setting given outside the config file, in the environment or on the command line
+#[derive(Debug, Clone)]
+pub struct ConfigOverride {
+    /// Dotted path of the setting, e.g. `schedule.auto_follow`
+    pub key: String,
+    pub value: String,
+    /// Where the value came from, e.g. `SCARCHIVE_LOG_LEVEL` or `--set`
+    pub source: String,
+}
+
+impl ConfigOverride {
+    /// Parse a `--set key=value` option
+    pub fn from_cli(option: &str) -> Result<Self, String> {
+        match option.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => Ok(ConfigOverride {
+                key: key.trim().to_string(),
+                value: value.to_string(),
+                source: "--set".to_string(),
+            }),
+            _ => Err(format!("Expected --set KEY=VALUE, got '{}'", option)),
         }
+    }
+}
 
-        // Read the file as raw JSON Value first
-        let file = File::open(config_path)?;
-        let reader = BufReader::new(file);
-        let config_json: Value = serde_json::from_reader(reader)?;
+/// Read the `SCARCHIVE_*` environment variables
+/// 
+/// `SCARCHIVE_POLL_INTERVAL_SEC` sets `poll_interval_sec`, a double underscore separates
+/// nested keys (`SCARCHIVE_SCHEDULE__AUTO_FOLLOW`). With a `_FILE` suffix, the value is read
+/// from the file at the given path, for secrets mounted into a container.
+fn env_overrides(vars: impl Iterator<Item = (String, String)>, defaults: &Value) -> Result<Vec<ConfigOverride>, String> {
+    let mut vars: Vec<(String, String)> = vars
+        .filter(|(name, _)| name.starts_with(ENV_PREFIX) && name != "SCARCHIVE_CONFIG")
+        .collect();
+    vars.sort();
+    
+    let mut overrides: Vec<ConfigOverride> = Vec::new();
+    for (name, value) in vars {
+        let key = name[ENV_PREFIX.len()..].to_lowercase().replace("__", ".");
         
-        // Start with the default config
-        let mut config = Config::default();
+        let (key, value) = if lookup(defaults, &key).is_none() && key.ends_with("_file") {
+            let key = key.trim_end_matches("_file").to_string();
+            let contents = fs::read_to_string(&value)
+                .map_err(|e| format!("Failed to read {} from {}: {}", name, value, e))?;
+            (key, contents.trim_end_matches(['\r', '\n']).to_string())
+        } else {
+            (key, value)
+        };
         
-        // Update only the fields that are present in the JSON
-        if let Some(webhook_url) = config_json.get("discord_webhook_url").and_then(|v| v.as_str()) {
-            config.discord_webhook_url = webhook_url.to_string();
+        if let Some(other) = overrides.iter().find(|o| o.key == key) {
+            return Err(format!("{} and {} both set {}, use only one of them", other.source, name, key));
         }
-        
-        if let Some(log_level) = config_json.get("log_level").and_then(|v| v.as_str()) {
-            config.log_level = log_level.to_string();
+        overrides.push(ConfigOverride { key, value, source: name });
+    }
+    
+    Ok(overrides)
+}
+
+/// Value at a dotted path
+fn lookup<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+    key.split('.').try_fold(value, |value, part| value.get(part))
+}
+
+/// Apply overrides on top of the config file
+/// 
+/// Values of text settings are taken as they are, all others are parsed as JSON
+/// (`30`, `true`, `null`, `[...]`). Schedules are either, `3600` or `"0 * * * *"`.
+fn apply_overrides(config_json: &mut Value, overrides: &[ConfigOverride], defaults: &Value) -> Result<(), String> {
+    for o in overrides {
+        let top_level = o.key.split('.').next().unwrap_or_default();
+        if defaults.get(top_level).is_none() {
+            return Err(format!("{} sets unknown setting {}", o.source, o.key));
         }
         
-        if let Some(poll_interval) = config_json.get("poll_interval_sec").and_then(|v| v.as_u64()) {
-            config.poll_interval_sec = poll_interval;
-        }
+        let value = match lookup(defaults, &o.key) {
+            // Optional settings without a default are text (paths, tokens) or schedules, which also accept text
+            Some(Value::String(_)) | Some(Value::Null) | None => {
+                Value::String(o.value.clone())
+            },
+            // Schedules default to an interval but also take a cron expression
+            Some(_) if o.key.starts_with("schedule.") && serde_json::from_str::<Value>(&o.value).is_err() => {
+                Value::String(o.value.clone())
+            },
+            _ => serde_json::from_str(&o.value)
+                .map_err(|e| format!("Invalid value for {} in {}: {}", o.key, o.source, e))?,
+        };
         
-        if let Some(users_file) = config_json.get("users_file").and_then(|v| v.as_str()) {
-            config.users_file = users_file.to_string();
+        let mut target = &mut *config_json;
+        for part in o.key.split('.') {
+            if !target.is_object() {
+                *target = Value::Object(serde_json::Map::new());
+            }
+            target = target.as_object_mut()
+                .expect("target was just made an object")
+                .entry(part)
+                .or_insert(Value::Null);
         }
+        *target = value;
         
-        if let Some(tracks_file) = config_json.get("tracks_file").and_then(|v| v.as_str()) {
-            config.tracks_file = tracks_file.to_string();
+        debug!("Setting {} from {}", o.key, o.source);
+    }
+    
+    Ok(())
+}
+
+/// Keys of older config versions that are still accepted
+#[derive(Debug, Default)]
+struct LegacyKeys {
+    auto_follow_interval: Option<u64>,
+}
+
+/// Remove keys of older config versions from the raw config, warning about each
+fn take_legacy_keys(config_json: &mut Value, config_path: &str) -> LegacyKeys {
+    let mut legacy = LegacyKeys::default();
+    let object = match config_json.as_object_mut() {
+        Some(object) => object,
+        None => return legacy,
+    };
+    
+    if let Some(interval) = object.remove("auto_follow_interval") {
+        warn!("auto_follow_interval in {} is deprecated, use schedule.auto_follow instead", config_path);
+        legacy.auto_follow_interval = interval.as_u64();
+    }
+    
+    for key in ["db_save_interval", "db_save_tracks"] {
+        if object.remove(key).is_some() {
+            warn!("{} in {} is no longer used, the database is saved after every track", key, config_path);
         }
+    }
+    
+    legacy
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Users {
+    pub users: Vec<String>,
+    /// Users that stay in the list but aren't polled
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub paused: Vec<String>,
+}
+
+impl Config {
+    /// Load and validate the config
+    /// 
+    /// Settings are layered: defaults, then the config file, then `SCARCHIVE_*` environment
+    /// variables, then `--set` options. The file may be JSON, TOML or YAML, by its extension.
+    /// Unknown keys, wrong types and out-of-range values are errors. A missing file is an
+    /// error too, `config generate` creates one, unless the default file is missing and
+    /// the settings come from overrides alone.
+    pub fn load(config_path: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Config::load_with(config_path, &Config::overrides()?)
+    }
+    
+    /// Load and validate the config file alone, without the environment and `--set` options
+    pub fn load_file(config_path: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Config::load_with(config_path, &[])
+    }
+    
+    fn load_with(config_path: &str, overrides: &[ConfigOverride]) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Config::parse_layers(config_path, overrides)
+            .map_err(|e| ConfigError(e.to_string()).into())
+    }
+    
+    fn parse_layers(config_path: &str, overrides: &[ConfigOverride]) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let defaults = serde_json::to_value(Config::default())?;
         
-        if let Some(max_tracks) = config_json.get("max_tracks_per_user").and_then(|v| v.as_u64()) {
-            config.max_tracks_per_user = max_tracks as usize;
-        }
+        let mut config_json = if Path::new(config_path).exists() {
+            let format = ConfigFormat::from_path(config_path);
+            let text = fs::read_to_string(config_path)?;
+            format.parse(&text)
+                .map_err(|e| format!("Invalid {} in {}: {}", format, config_path, e))?
+        } else if config_path == DEFAULT_CONFIG_PATH && !overrides.is_empty() {
+            debug!("No {} found, using defaults and overrides", config_path);
+            Value::Object(serde_json::Map::new())
+        } else {
+            return Err(format!("Config file {} not found, create one with `config generate`", config_path).into());
+        };
         
-        if let Some(pagination) = config_json.get("pagination_size").and_then(|v| v.as_u64()) {
-            config.pagination_size = pagination as usize;
-        }
+        // Keys of older versions are taken out before the strict parse
+        let legacy = take_legacy_keys(&mut config_json, config_path);
+        apply_overrides(&mut config_json, overrides, &defaults)?;
+        
+        let source = if overrides.is_empty() {
+            config_path.to_string()
+        } else {
+            format!("{} with {} overrides", config_path, overrides.len())
+        };
+        
+        let has_auto_follow_schedule = config_json.get("schedule")
+            .and_then(|s| s.get("auto_follow"))
+            .is_some();
         
-        if let Some(temp_dir) = config_json.get("temp_dir") {
-            if temp_dir.is_null() {
-                config.temp_dir = None;
-            } else if let Some(dir) = temp_dir.as_str() {
-                config.temp_dir = Some(dir.to_string());
+        let mut config: Config = serde_json::from_value(config_json)
+            .map_err(|e| format!("Invalid config in {}: {}", source, e))?;
+        
+        // Counted in poll cycles before the scheduler existed, converted for old config files
+        if let (Some(interval), false) = (legacy.auto_follow_interval, has_auto_follow_schedule) {
+            if interval > 0 {
+                config.schedule.auto_follow = Some(JobSchedule::Every(
+                    std::time::Duration::from_secs(interval * config.poll_interval_sec.max(1))));
             }
         }
         
-        if let Some(soundcloud_parallelism) = config_json.get("max_soundcloud_parallelism").and_then(|v| v.as_u64()) {
-            config.max_soundcloud_parallelism = soundcloud_parallelism as usize;
+        // Treat an empty token the same as no token
+        config.soundcloud_oauth_token = config.soundcloud_oauth_token.filter(|t| !t.trim().is_empty());
+        
+        if let Err(problems) = config.validate() {
+            return Err(format!("Invalid config in {}:\n  - {}", source, problems.join("\n  - ")).into());
         }
         
-        if let Some(discord_parallelism) = config_json.get("max_discord_parallelism").and_then(|v| v.as_u64()) {
-            config.max_discord_parallelism = discord_parallelism as usize;
+        info!("Loaded configuration from {}", source);
+        debug!("Config: log_level={}, poll_interval={}s, max_tracks={}, scrape_likes={}, max_concurrent_processing={}, oauth_token={}",
+               config.log_level, config.poll_interval_sec, config.max_tracks_per_user, 
+               config.scrape_user_likes, config.max_processing_parallelism,
+               if config.soundcloud_oauth_token.is_some() { "set" } else { "not set" });
+        Ok(config)
+    }
+    
+    /// Check the values that the types alone don't rule out
+    /// 
+    /// Returns every problem found, not just the first.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+        
+        if self.discord_webhook_url.trim().is_empty() {
+            problems.push("discord_webhook_url is required".to_string());
+        } else if !self.discord_webhook_url.starts_with("https://") {
+            problems.push("discord_webhook_url must be an https:// URL".to_string());
         }
         
-        if let Some(processing_parallelism) = config_json.get("max_processing_parallelism").and_then(|v| v.as_u64()) {
-            config.max_processing_parallelism = processing_parallelism as usize;
+        if !["trace", "debug", "info", "warn", "error"].contains(&self.log_level.to_lowercase().as_str()) {
+            problems.push(format!("log_level must be one of trace, debug, info, warn, error (got '{}')", self.log_level));
         }
         
-        if let Some(scrape_likes) = config_json.get("scrape_user_likes").and_then(|v| v.as_bool()) {
-            config.scrape_user_likes = scrape_likes;
+        let positive = [
+            ("poll_interval_sec", self.poll_interval_sec),
+            ("min_poll_interval_sec", self.min_poll_interval_sec),
+            ("max_poll_interval_sec", self.max_poll_interval_sec),
+            ("max_tracks_per_user", self.max_tracks_per_user as u64),
+            ("pagination_size", self.pagination_size as u64),
+            ("max_soundcloud_parallelism", self.max_soundcloud_parallelism as u64),
+            ("max_discord_parallelism", self.max_discord_parallelism as u64),
+            ("max_processing_parallelism", self.max_processing_parallelism as u64),
+            ("job_queue_size", self.job_queue_size as u64),
+            ("retry_max_attempts", self.retry_max_attempts as u64),
+            ("schedule.batch_size", self.schedule.batch_size as u64),
+        ];
+        for (key, value) in positive {
+            if value == 0 {
+                problems.push(format!("{} must be greater than 0", key));
+            }
         }
         
-        if let Some(max_likes) = config_json.get("max_likes_per_user").and_then(|v| v.as_u64()) {
-            config.max_likes_per_user = max_likes as usize;
+        if self.max_poll_interval_sec < self.min_poll_interval_sec {
+            problems.push(format!("max_poll_interval_sec ({}) must not be less than min_poll_interval_sec ({})",
+                                  self.max_poll_interval_sec, self.min_poll_interval_sec));
         }
         
-        if let Some(auto_follow) = config_json.get("auto_follow_source") {
-            if auto_follow.is_null() {
-                config.auto_follow_source = None;
-            } else if let Some(source) = auto_follow.as_str() {
-                config.auto_follow_source = Some(source.to_string());
-            }
+        if self.poll_jitter_percent > 100 {
+            problems.push(format!("poll_jitter_percent must be at most 100 (got {})", self.poll_jitter_percent));
         }
         
-        if let Some(interval) = config_json.get("auto_follow_interval").and_then(|v| v.as_u64()) {
-            config.auto_follow_interval = interval as usize;
+        if self.retry_max_delay_sec < self.retry_base_delay_sec {
+            problems.push(format!("retry_max_delay_sec ({}) must not be less than retry_base_delay_sec ({})",
+                                  self.retry_max_delay_sec, self.retry_base_delay_sec));
         }
         
-        if let Some(save_interval) = config_json.get("db_save_interval").and_then(|v| v.as_u64()) {
-            config.db_save_interval = save_interval as usize;
+        if !(0.0..=1.0).contains(&self.reupload_similarity_threshold) {
+            problems.push(format!("reupload_similarity_threshold must be between 0.0 and 1.0 (got {})",
+                                  self.reupload_similarity_threshold));
         }
         
-        if let Some(save_tracks) = config_json.get("db_save_tracks").and_then(|v| v.as_u64()) {
-            config.db_save_tracks = save_tracks as usize;
+        if let crate::formats::FormatSelection::Best { count: 0 } = self.format_policy.selection {
+            problems.push("format_policy count must be greater than 0".to_string());
         }
         
-        if let Some(show_ffmpeg) = config_json.get("show_ffmpeg_output").and_then(|v| v.as_bool()) {
-            config.show_ffmpeg_output = show_ffmpeg;
+        for profile in &self.transcode_profiles {
+            if let Err(e) = profile.validate() {
+                problems.push(e);
+            }
         }
         
-        if let Some(log_file) = config_json.get("log_file").and_then(|v| v.as_str()) {
-            config.log_file = log_file.to_string();
+        let mut names: Vec<&str> = self.transcode_profiles.iter().map(|p| p.name.as_str()).collect();
+        names.sort_unstable();
+        if let Some(pair) = names.windows(2).find(|pair| pair[0] == pair[1]) {
+            problems.push(format!("transcode profile name '{}' is used twice", pair[0]));
         }
         
-        // Validate required fields
-        if config.discord_webhook_url.is_empty() {
-            return Err("discord_webhook_url is required in config.json".into());
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
         }
+    }
+    
+    /// Copy of the config that is safe to print, with the webhook token and OAuth token hidden
+    pub fn redacted(&self) -> Config {
+        let mut config = self.clone();
         
-        info!("Loaded configuration from {}", config_path);
-        debug!("Config: log_level={}, poll_interval={}s, max_tracks={}, scrape_likes={}, max_concurrent_processing={}",
-               config.log_level, config.poll_interval_sec, config.max_tracks_per_user, 
-               config.scrape_user_likes, config.max_processing_parallelism);
-        Ok(config)
+        // The last path segment of a webhook URL is its token
+        if let Some((base, _token)) = config.discord_webhook_url.rsplit_once('/') {
+            config.discord_webhook_url = format!("{}/[redacted]", base);
+        }
+        if config.soundcloud_oauth_token.is_some() {
+            config.soundcloud_oauth_token = Some("[redacted]".to_string());
+        }
+        
+        config
+    }
+    
+    /// Path of the config file, from `--config`, `SCARCHIVE_CONFIG` or the first default file that exists
+    pub fn path() -> String {
+        let lock = CONFIG_PATH.lock().unwrap();
+        lock.clone()
+            .or_else(|| std::env::var("SCARCHIVE_CONFIG").ok())
+            .or_else(|| DEFAULT_CONFIG_PATHS.iter().find(|p| Path::new(p).exists()).map(|p| p.to_string()))
+            .unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string())
+    }
+    
+    /// Set the config file path given with `--config`
+    pub fn set_path(path: &str) {
+        let mut lock = CONFIG_PATH.lock().unwrap();
+        *lock = Some(path.to_string());
+    }
+    
+    /// Set the `--set` options, applied on every load on top of the file and environment
+    pub fn set_cli_overrides(overrides: Vec<ConfigOverride>) {
+        let mut lock = CLI_OVERRIDES.lock().unwrap();
+        *lock = overrides;
+    }
+    
+    /// All overrides that apply to the next load, environment first
+    pub fn overrides() -> Result<Vec<ConfigOverride>, Box<dyn std::error::Error + Send + Sync>> {
+        let defaults = serde_json::to_value(Config::default())?;
+        let mut overrides = env_overrides(std::env::vars(), &defaults)?;
+        overrides.extend(CLI_OVERRIDES.lock().unwrap().iter().cloned());
+        Ok(overrides)
     }
     
     /// Static access to show_ffmpeg_output setting
     /// Used in audio.rs to check if ffmpeg output should be shown
     pub fn show_ffmpeg_output() -> Option<bool> {
-        lazy_static::lazy_static! {
-            static ref CONFIG_VALUE: std::sync::Mutex<Option<bool>> = std::sync::Mutex::new(None);
-        }
-        
-        let lock = CONFIG_VALUE.lock().unwrap();
+        let lock = SHOW_FFMPEG_OUTPUT.lock().unwrap();
         *lock
     }
     
     /// Set the value for the static show_ffmpeg_output access
     pub fn set_show_ffmpeg_output(value: bool) {
-        lazy_static::lazy_static! {
-            static ref CONFIG_VALUE: std::sync::Mutex<Option<bool>> = std::sync::Mutex::new(None);
-        }
-        
-        let mut lock = CONFIG_VALUE.lock().unwrap();
+        let mut lock = SHOW_FFMPEG_OUTPUT.lock().unwrap();
         *lock = Some(value);
     }
 }
 
 impl Users {
+    /// Load the users file, JSON, TOML or YAML by its extension
     pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let format = ConfigFormat::from_path(path);
         if !Path::new(path).exists() {
             warn!("Users file not found at {}, creating empty list", path);
-            let empty_users = Users { users: Vec::new() };
-            let json = serde_json::to_string_pretty(&empty_users)?;
-            std::fs::write(path, json)?;
+            let empty_users = Users::default();
+            std::fs::write(path, format.to_string(&empty_users)?)?;
             return Ok(empty_users);
         }
 
-        let file = File::open(path)?;
-        let reader = BufReader::new(file);
-        let users: Users = serde_json::from_reader(reader)?;
+        let text = fs::read_to_string(path)?;
+        let users_json = format.parse(&text)
+            .map_err(|e| format!("Invalid {} in {}: {}", format, path, e))?;
+        let users: Users = serde_json::from_value(users_json)
+            .map_err(|e| format!("Invalid users file {}: {}", path, e))?;
         
         info!("Loaded {} users from {}", users.users.len(), path);
         Ok(users)
     }
 
+    /// Users that are polled, i.e. not paused
+    pub fn active(&self) -> Vec<String> {
+        self.users.iter()
+            .filter(|user_id| !self.paused.contains(user_id))
+            .cloned()
+            .collect()
+    }
+
     /// Save users list to a file
     pub fn save(&self, path: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         debug!("Saving {} users to file: {}", self.users.len(), path);
         
+        // Serialized before the file is touched, in the file's format
+        let contents = ConfigFormat::from_path(path).to_string(self)?;
+        
         // First, create a backup of the existing file if it exists
         let backup_path = format!("{}.bak", path);
         if Path::new(path).exists() {
@@ -350,10 +735,10 @@ impl Users {
             }
         };
         
-        let writer = BufWriter::new(file);
+        let mut writer = BufWriter::new(file);
         
-        // Serialize to the file
-        if let Err(e) = serde_json::to_writer_pretty(writer, self) {
+        // Write to the file
+        if let Err(e) = writer.write_all(contents.as_bytes()).and_then(|_| writer.flush()) {
             error!("Failed to write users to file: {}", e);
             
             // Try to restore from backup if it exists
@@ -488,4 +873,292 @@ impl Users {
         
         Ok(count)
     }
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn valid_config() -> Config {
+        Config {
+            discord_webhook_url: "https://discord.com/api/webhooks/1/token".to_string(),
+            ..Config::default()
+        }
+    }
+    
+    /// Write a config file to the temp dir, removed again by the caller
+    fn write_config(extension: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("scarchive-config-{}.{}", uuid::Uuid::new_v4(), extension));
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().to_string()
+    }
+    
+    fn every_secs(schedule: &Option<JobSchedule>) -> Option<u64> {
+        match schedule {
+            Some(JobSchedule::Every(interval)) => Some(interval.as_secs()),
+            _ => None,
+        }
+    }
+    
+    #[test]
+    fn validate_accepts_defaults_with_webhook() {
+        assert!(valid_config().validate().is_ok());
+    }
+    
+    #[test]
+    fn validate_requires_https_webhook() {
+        let problems = Config::default().validate().unwrap_err();
+        assert_eq!(problems, vec!["discord_webhook_url is required"]);
+        
+        let config = Config {
+            discord_webhook_url: "http://discord.com/api/webhooks/1/token".to_string(),
+            ..Config::default()
+        };
+        assert_eq!(config.validate().unwrap_err(), vec!["discord_webhook_url must be an https:// URL"]);
+    }
+    
+    #[test]
+    fn validate_reports_every_problem() {
+        let config = Config {
+            log_level: "loud".to_string(),
+            poll_interval_sec: 0,
+            job_queue_size: 0,
+            min_poll_interval_sec: 600,
+            max_poll_interval_sec: 60,
+            poll_jitter_percent: 101,
+            retry_base_delay_sec: 100,
+            retry_max_delay_sec: 10,
+            reupload_similarity_threshold: 1.5,
+            ..valid_config()
+        };
+        
+        let problems = config.validate().unwrap_err();
+        assert_eq!(problems.len(), 7, "{:?}", problems);
+        assert!(problems.iter().any(|p| p.starts_with("log_level must be one of")));
+        assert!(problems.contains(&"poll_interval_sec must be greater than 0".to_string()));
+        assert!(problems.contains(&"job_queue_size must be greater than 0".to_string()));
+        assert!(problems.iter().any(|p| p.starts_with("max_poll_interval_sec (60)")));
+        assert!(problems.iter().any(|p| p.starts_with("poll_jitter_percent")));
+        assert!(problems.iter().any(|p| p.starts_with("retry_max_delay_sec (10)")));
+        assert!(problems.iter().any(|p| p.starts_with("reupload_similarity_threshold")));
+    }
+    
+    #[test]
+    fn validate_accepts_log_level_in_any_case() {
+        let config = Config { log_level: "DEBUG".to_string(), ..valid_config() };
+        assert!(config.validate().is_ok());
+    }
+    
+    #[test]
+    fn validate_rejects_duplicate_profile_names() {
+        let profile: TranscodeProfile = serde_json::from_value(serde_json::json!({
+            "name": "opus", "codec": "libopus", "bitrate": "160k", "extension": "opus"
+        })).unwrap();
+        let config = Config {
+            transcode_profiles: vec![profile.clone(), profile],
+            ..valid_config()
+        };
+        
+        assert_eq!(config.validate().unwrap_err(), vec!["transcode profile name 'opus' is used twice"]);
+    }
+    
+    #[test]
+    fn load_rejects_invalid_values_with_the_file_name() {
+        let path = write_config("json", r#"{"discord_webhook_url": "https://example.com/hook", "poll_interval_sec": 0}"#);
+        let result = Config::load_file(&path);
+        fs::remove_file(&path).unwrap();
+        
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains(&path), "{}", message);
+        assert!(message.contains("poll_interval_sec must be greater than 0"), "{}", message);
+    }
+    
+    #[test]
+    fn load_rejects_unknown_keys() {
+        let path = write_config("json", r#"{"discord_webhook_url": "https://example.com/hook", "pol_interval_sec": 30}"#);
+        let result = Config::load_file(&path);
+        fs::remove_file(&path).unwrap();
+        
+        assert!(result.unwrap_err().to_string().contains("unknown field `pol_interval_sec`"));
+    }
+    
+    #[test]
+    fn legacy_auto_follow_interval_is_converted_from_poll_cycles() {
+        let path = write_config("json", r#"{
+            "discord_webhook_url": "https://example.com/hook",
+            "poll_interval_sec": 30,
+            "auto_follow_interval": 10,
+            "db_save_interval": 5
+        }"#);
+        let config = Config::load_file(&path);
+        fs::remove_file(&path).unwrap();
+        
+        assert_eq!(every_secs(&config.unwrap().schedule.auto_follow), Some(300));
+    }
+    
+    #[test]
+    fn legacy_auto_follow_interval_yields_to_schedule() {
+        let path = write_config("toml", r#"
+            discord_webhook_url = "https://example.com/hook"
+            auto_follow_interval = 10
+            
+            [schedule]
+            auto_follow = 900
+        "#);
+        let config = Config::load_file(&path);
+        fs::remove_file(&path).unwrap();
+        
+        assert_eq!(every_secs(&config.unwrap().schedule.auto_follow), Some(900));
+    }
+    
+    #[test]
+    fn legacy_auto_follow_interval_of_zero_keeps_default() {
+        let path = write_config("json", r#"{"discord_webhook_url": "https://example.com/hook", "auto_follow_interval": 0}"#);
+        let config = Config::load_file(&path);
+        fs::remove_file(&path).unwrap();
+        
+        assert_eq!(every_secs(&config.unwrap().schedule.auto_follow), Some(3600));
+    }
+    
+    fn defaults() -> Value {
+        serde_json::to_value(Config::default()).unwrap()
+    }
+    
+    fn vars(pairs: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+        pairs.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+    
+    fn set(key: &str, value: &str) -> ConfigOverride {
+        ConfigOverride::from_cli(&format!("{}={}", key, value)).unwrap()
+    }
+    
+    #[test]
+    fn env_overrides_map_variable_names_to_keys() {
+        let overrides = env_overrides(vars(&[
+            ("SCARCHIVE_SCHEDULE__AUTO_FOLLOW", "900"),
+            ("SCARCHIVE_POLL_INTERVAL_SEC", "30"),
+            ("SCARCHIVE_CONFIG", "other.json"),
+            ("HOME", "/root"),
+        ]), &defaults()).unwrap();
+        
+        let found: Vec<(&str, &str, &str)> = overrides.iter()
+            .map(|o| (o.key.as_str(), o.value.as_str(), o.source.as_str()))
+            .collect();
+        assert_eq!(found, vec![
+            ("poll_interval_sec", "30", "SCARCHIVE_POLL_INTERVAL_SEC"),
+            ("schedule.auto_follow", "900", "SCARCHIVE_SCHEDULE__AUTO_FOLLOW"),
+        ]);
+    }
+    
+    #[test]
+    fn env_overrides_read_secrets_from_files() {
+        let path = write_config("secret", "oauth-token\r\n");
+        let overrides = env_overrides(vars(&[
+            ("SCARCHIVE_SOUNDCLOUD_OAUTH_TOKEN_FILE", path.as_str()),
+            // A setting whose own name ends in _file is not a secret file
+            ("SCARCHIVE_LOG_FILE", "archive.log"),
+        ]), &defaults());
+        fs::remove_file(&path).unwrap();
+        
+        let overrides = overrides.unwrap();
+        assert_eq!(overrides[0].key, "log_file");
+        assert_eq!(overrides[0].value, "archive.log");
+        assert_eq!(overrides[1].key, "soundcloud_oauth_token");
+        assert_eq!(overrides[1].value, "oauth-token");
+        assert_eq!(overrides[1].source, "SCARCHIVE_SOUNDCLOUD_OAUTH_TOKEN_FILE");
+    }
+    
+    #[test]
+    fn env_overrides_reject_missing_secret_file() {
+        let path = std::env::temp_dir().join(format!("scarchive-missing-{}", uuid::Uuid::new_v4()));
+        let path = path.to_string_lossy().to_string();
+        let error = env_overrides(vars(&[("SCARCHIVE_DISCORD_WEBHOOK_URL_FILE", path.as_str())]), &defaults())
+            .unwrap_err();
+        
+        assert!(error.starts_with("Failed to read SCARCHIVE_DISCORD_WEBHOOK_URL_FILE"), "{}", error);
+    }
+    
+    #[test]
+    fn env_overrides_reject_value_and_file_for_the_same_key() {
+        let path = write_config("secret", "from-file");
+        let result = env_overrides(vars(&[
+            ("SCARCHIVE_SOUNDCLOUD_OAUTH_TOKEN", "from-env"),
+            ("SCARCHIVE_SOUNDCLOUD_OAUTH_TOKEN_FILE", path.as_str()),
+        ]), &defaults());
+        fs::remove_file(&path).unwrap();
+        
+        assert_eq!(result.unwrap_err(),
+                   "SCARCHIVE_SOUNDCLOUD_OAUTH_TOKEN and SCARCHIVE_SOUNDCLOUD_OAUTH_TOKEN_FILE both set soundcloud_oauth_token, use only one of them");
+    }
+    
+    #[test]
+    fn from_cli_requires_key_and_value() {
+        let o = ConfigOverride::from_cli("log_level=debug=1").unwrap();
+        assert_eq!((o.key.as_str(), o.value.as_str()), ("log_level", "debug=1"));
+        
+        assert!(ConfigOverride::from_cli("log_level").is_err());
+        assert!(ConfigOverride::from_cli("=debug").is_err());
+    }
+    
+    #[test]
+    fn apply_overrides_parse_values_by_setting_type() {
+        let mut config_json = serde_json::json!({"discord_webhook_url": "https://example.com/hook"});
+        apply_overrides(&mut config_json, &[
+            set("log_level", "debug"),
+            set("poll_interval_sec", "30"),
+            set("scrape_user_likes", "true"),
+            set("temp_dir", "/tmp/archive"),
+            set("soundcloud_oauth_token", "12345"),
+            set("format_policy", r#"{"mode": "best", "count": 2}"#),
+            set("schedule.auto_follow", "900"),
+            set("schedule.deletion_sweep", "0 3 * * *"),
+            set("schedule.embed_refresh", "false"),
+        ], &defaults()).unwrap();
+        
+        assert_eq!(config_json["log_level"], "debug");
+        assert_eq!(config_json["poll_interval_sec"], 30);
+        assert_eq!(config_json["scrape_user_likes"], true);
+        assert_eq!(config_json["temp_dir"], "/tmp/archive");
+        // Text settings keep numbers as text
+        assert_eq!(config_json["soundcloud_oauth_token"], "12345");
+        assert_eq!(config_json["format_policy"]["count"], 2);
+        assert_eq!(config_json["schedule"]["auto_follow"], 900);
+        assert_eq!(config_json["schedule"]["deletion_sweep"], "0 3 * * *");
+        assert_eq!(config_json["schedule"]["embed_refresh"], false);
+        
+        let config: Config = serde_json::from_value(config_json).unwrap();
+        assert_eq!(config.poll_interval_sec, 30);
+        assert_eq!(every_secs(&config.schedule.auto_follow), Some(900));
+        assert!(matches!(config.schedule.deletion_sweep, Some(JobSchedule::Cron(_))));
+        assert!(config.schedule.embed_refresh.is_none());
+    }
+    
+    #[test]
+    fn apply_overrides_reject_unknown_settings_and_bad_values() {
+        let mut config_json = serde_json::json!({});
+        
+        let error = apply_overrides(&mut config_json, &[set("pol_interval_sec", "30")], &defaults()).unwrap_err();
+        assert_eq!(error, "--set sets unknown setting pol_interval_sec");
+        
+        let error = apply_overrides(&mut config_json, &[set("poll_interval_sec", "soon")], &defaults()).unwrap_err();
+        assert!(error.starts_with("Invalid value for poll_interval_sec in --set"), "{}", error);
+    }
+    
+    #[test]
+    fn overrides_apply_on_top_of_the_file() {
+        let path = write_config("yaml", "discord_webhook_url: https://example.com/hook\npoll_interval_sec: 120\n");
+        let overrides = [ConfigOverride {
+            key: "poll_interval_sec".to_string(),
+            value: "45".to_string(),
+            source: "SCARCHIVE_POLL_INTERVAL_SEC".to_string(),
+        }];
+        let config = Config::load_with(&path, &overrides);
+        let file_only = Config::load_with(&path, &[]);
+        fs::remove_file(&path).unwrap();
+        
+        assert_eq!(config.unwrap().poll_interval_sec, 45);
+        assert_eq!(file_only.unwrap().poll_interval_sec, 120);
+    }
+}
@@ -0,0 +1,346 @@
+use std::fmt;
+use std::path::Path;
+use serde::Serialize;
+use serde_json::Value;
+use crate::config::Config;
+
+/// File format of a config or users file, picked by its extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Format of a file, JSON unless the extension is `.toml`, `.yaml` or `.yml`
+    pub fn from_path(path: &str) -> ConfigFormat {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+
+        match extension.as_deref() {
+            Some("toml") => ConfigFormat::Toml,
+            Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+            _ => ConfigFormat::Json,
+        }
+    }
+
+    /// Parse a file's contents into a JSON value, which the config is read from
+    pub fn parse(self, text: &str) -> Result<Value, String> {
+        match self {
+            ConfigFormat::Json => serde_json::from_str(text).map_err(|e| e.to_string()),
+            ConfigFormat::Toml => toml::from_str(text).map_err(|e| e.to_string()),
+            ConfigFormat::Yaml => serde_norway::from_str(text).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Write a value in this format
+    pub fn to_string<T: Serialize>(self, value: &T) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(match self {
+            ConfigFormat::Json => serde_json::to_string_pretty(value)?,
+            ConfigFormat::Toml => toml::to_string_pretty(value)?,
+            ConfigFormat::Yaml => serde_norway::to_string(value)?,
+        })
+    }
+}
+
+impl fmt::Display for ConfigFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigFormat::Json => write!(f, "JSON"),
+            ConfigFormat::Toml => write!(f, "TOML"),
+            ConfigFormat::Yaml => write!(f, "YAML"),
+        }
+    }
+}
+
+/// Write a config for the given file, TOML gets a comment on every setting
+pub fn config_to_string(config: &Config, path: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    match ConfigFormat::from_path(path) {
+        ConfigFormat::Toml => commented_toml(config),
+        format => format.to_string(config),
+    }
+}
+
+/// Comments of the settings in a TOML template, by dotted key
+const SETTING_DOCS: &[(&str, &str)] = &[
+    ("discord_webhook_url", "Discord webhook the tracks are posted to (required)"),
+    ("log_level", "trace, debug, info, warn or error"),
+    ("poll_interval_sec", "Seconds between polls of all watched users"),
+    ("adaptive_polling", "Poll each user based on how often they upload instead of every poll_interval_sec"),
+    ("min_poll_interval_sec", "Shortest interval between polls of a user with adaptive polling"),
+    ("max_poll_interval_sec", "Longest interval between polls of a user with adaptive polling"),
+    ("poll_jitter_percent", "Random jitter added to adaptive poll intervals, in percent"),
+    ("users_file", "File with the IDs of the watched users (.json, .toml or .yaml)"),
+    ("tracks_file", "Tracks database"),
+    ("max_tracks_per_user", "Most tracks fetched per user"),
+    ("pagination_size", "Tracks or likes fetched per API request"),
+    ("temp_dir", "Directory for downloads, the system temp directory if not set"),
+    ("max_soundcloud_parallelism", "Parallel SoundCloud API requests, keep this low to avoid rate limiting"),
+    ("max_discord_parallelism", "Posting workers, i.e. parallel Discord webhook requests"),
+    ("max_processing_parallelism", "Download workers, i.e. tracks downloaded and processed in parallel"),
+    ("scrape_user_likes", "Archive the tracks the watched users like"),
+    ("max_likes_per_user", "Most likes fetched per user with scrape_user_likes"),
+    ("auto_follow_source", "User ID or URL whose followings are added to the watched users"),
+    ("show_ffmpeg_output", "Show ffmpeg output in the console"),
+    ("log_file", "Log file"),
+    ("soundcloud_oauth_token", "OAuth token of a logged-in SoundCloud session, unlocks Go+ HQ and private tracks"),
+    ("verify_downloads", "Probe downloaded audio with ffprobe and download broken files again"),
+    ("verify_duration_tolerance_sec", "Allowed difference between the probed and reported duration, in seconds"),
+    ("verify_max_retries", "Downloads of a format after it failed verification"),
+    ("embed_metadata", "Write tags and cover art into the downloaded files"),
+    ("deduplicate_uploads", "Link files that were already posted instead of uploading them again"),
+    ("fingerprint_tracks", "Fingerprint tracks to flag possible re-uploads"),
+    ("reupload_similarity_threshold", "Fingerprint similarity (0.0-1.0) from which a track is flagged as a re-upload"),
+    ("analyze_audio", "Measure loudness and render waveform and spectrogram images"),
+    ("transcode_profiles", "Extra ffmpeg outputs of each track, see the README"),
+    ("job_queue_size", "New tracks that may wait for a download worker before polls wait"),
+    ("retry_max_attempts", "Failures before a track moves to the dead-letter list"),
+    ("retry_base_delay_sec", "Seconds before the first retry of a failed track, doubled for every further failure"),
+    ("retry_max_delay_sec", "Upper limit for the retry delay"),
+    ("schedule", "When the jobs run: seconds, a cron expression in UTC, or false to disable"),
+    ("schedule.poll", "Polling all watched users, every poll_interval_sec if not set"),
+    ("schedule.auto_follow", "Checking auto_follow_source for new followings"),
+    ("schedule.deletion_sweep", "Checking archived tracks for deletion from SoundCloud"),
+    ("schedule.embed_refresh", "Updating posted embeds with the current track metadata"),
+    ("schedule.db_compaction", "Dropping finished jobs from the tracks database"),
+    ("schedule.retry", "Queueing failed tracks whose retry delay has passed"),
+    ("schedule.batch_size", "Tracks checked by one deletion sweep or embed refresh run"),
+    ("format_policy", "Which transcodings are downloaded, see the README"),
+    ("format_policy.mode", "all, best (with count) or preferred (with codecs)"),
+    ("format_policy.allow_mp3_fallback", "Transcode the stream to MP3 when nothing else downloads"),
+];
+
+/// Examples of optional settings, written commented out when they aren't set
+const SETTING_EXAMPLES: &[(&str, &str)] = &[
+    ("temp_dir", "temp_dir = \"/app/temp\""),
+    ("auto_follow_source", "auto_follow_source = \"https://soundcloud.com/user\""),
+    ("soundcloud_oauth_token", "soundcloud_oauth_token = \"\""),
+    ("schedule.poll", "poll = \"*/5 * * * *\""),
+];
+
+/// TOML of a config with a comment above every setting
+///
+/// Optional settings that aren't set are added as commented-out examples.
+fn commented_toml(config: &Config) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let toml = toml::to_string_pretty(config)?;
+
+    let mut out = String::from("# archiver_webhook configuration\n");
+    let mut section = String::new();
+    let mut seen: Vec<String> = Vec::new();
+
+    for line in toml.lines() {
+        let trimmed = line.trim();
+
+        if let Some(name) = trimmed.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+            // The examples go right below the section's settings, one blank line before the next section
+            end_section(&mut out);
+            add_missing_examples(&mut out, &section, &seen);
+            out.push('\n');
+            section = name.trim_matches(['[', ']']).to_string();
+
+            // Array entries repeat their header, the comment goes above the first
+            if !seen.contains(&section) {
+                push_doc(&mut out, &section);
+                seen.push(section.clone());
+            }
+            out.push_str(line);
+            out.push('\n');
+            continue;
+        }
+
+        if let Some((key, _)) = trimmed.split_once(" = ") {
+            let key = if section.is_empty() { key.to_string() } else { format!("{}.{}", section, key) };
+            push_doc(&mut out, &key);
+            seen.push(key);
+        }
+
+        out.push_str(line);
+        out.push('\n');
+    }
+    end_section(&mut out);
+    add_missing_examples(&mut out, &section, &seen);
+
+    Ok(out)
+}
+
+/// Drop the blank lines at the end of a section
+fn end_section(out: &mut String) {
+    while out.ends_with("\n\n") {
+        out.pop();
+    }
+}
+
+/// Comment of a setting, if it has one
+fn push_doc(out: &mut String, key: &str) {
+    if let Some((_, doc)) = SETTING_DOCS.iter().find(|(k, _)| *k == key) {
+        out.push_str("# ");
+        out.push_str(doc);
+        out.push('\n');
+    }
+}
+
+/// Commented-out examples of the unset optional settings of a section
+fn add_missing_examples(out: &mut String, section: &str, seen: &[String]) {
+    for (key, example) in SETTING_EXAMPLES {
+        let key_section = key.rsplit_once('.').map(|(s, _)| s).unwrap_or("");
+        if key_section != section || seen.iter().any(|k| k == key) {
+            continue;
+        }
+
+        push_doc(out, key);
+        out.push_str("# ");
+        out.push_str(example);
+        out.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const CONFIG_JSON: &str = r#"{
+        "discord_webhook_url": "https://discord.com/api/webhooks/1/abc",
+        "log_level": "debug",
+        "poll_interval_sec": 120,
+        "adaptive_polling": true,
+        "temp_dir": "/tmp/archiver",
+        "reupload_similarity_threshold": 0.85,
+        "format_policy": { "mode": "best", "count": 2, "allow_mp3_fallback": false },
+        "transcode_profiles": [
+            { "name": "opus-160", "codec": "libopus", "bitrate": "160k", "extension": "opus" },
+            {
+                "name": "flac",
+                "codec": "flac",
+                "extension": "flac",
+                "extra_args": ["-compression_level", "8"],
+                "sink": "local",
+                "output_dir": "/archive/flac"
+            }
+        ],
+        "schedule": {
+            "poll": "*/5 * * * *",
+            "auto_follow": false,
+            "deletion_sweep": 86400,
+            "embed_refresh": null,
+            "db_compaction": "0 0 4 * * *",
+            "batch_size": 25
+        }
+    }"#;
+
+    /// Directory for the files of one test, removed when the test is done
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> TempDir {
+            let dir = std::env::temp_dir().join(format!("configformat_test_{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        fn write(&self, name: &str, contents: &str) -> String {
+            let path = self.0.join(name);
+            std::fs::write(&path, contents).unwrap();
+            path.to_string_lossy().to_string()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Load a config file through the same path as the watcher
+    fn load(dir: &TempDir, name: &str, contents: &str) -> Config {
+        let path = dir.write(name, contents);
+        Config::load_file(&path).unwrap_or_else(|e| panic!("failed to load {}: {}\n{}", name, e, contents))
+    }
+
+    fn as_value(config: &Config) -> Value {
+        serde_json::to_value(config).unwrap()
+    }
+
+    /// Convert the JSON config to `name` and load it again
+    fn round_trip(name: &str) -> (Config, Config, String) {
+        let dir = TempDir::new();
+        let original = load(&dir, "config.json", CONFIG_JSON);
+        let converted = config_to_string(&original, name).unwrap();
+        let reloaded = load(&dir, name, &converted);
+        (original, reloaded, converted)
+    }
+
+    #[test]
+    fn json_to_toml_round_trip_keeps_every_setting() {
+        let (original, reloaded, toml) = round_trip("config.toml");
+
+        assert_eq!(as_value(&reloaded), as_value(&original));
+        assert!(toml.contains("[[transcode_profiles]]"), "{}", toml);
+        assert!(toml.contains("[schedule]"), "{}", toml);
+        assert_eq!(reloaded.transcode_profiles, original.transcode_profiles);
+        assert_eq!(reloaded.transcode_profiles.len(), 2);
+        assert_eq!(reloaded.transcode_profiles[1].output_dir.as_deref(), Some("/archive/flac"));
+    }
+
+    #[test]
+    fn json_to_toml_round_trip_keeps_schedule() {
+        let (_, reloaded, toml) = round_trip("config.toml");
+        let schedule = &reloaded.schedule;
+
+        assert_eq!(schedule.poll.as_ref().map(|s| s.to_string()), Some("cron '0 */5 * * * *'".to_string()));
+        // TOML has no null, disabled jobs are written as false
+        assert!(schedule.auto_follow.is_none());
+        assert!(schedule.embed_refresh.is_none());
+        assert!(toml.contains("auto_follow = false"), "{}", toml);
+        assert_eq!(schedule.deletion_sweep.as_ref().map(|s| s.to_string()), Some("every 86400 seconds".to_string()));
+        assert_eq!(schedule.db_compaction.as_ref().map(|s| s.to_string()), Some("cron '0 0 4 * * *'".to_string()));
+        assert_eq!(schedule.batch_size, 25);
+    }
+
+    #[test]
+    fn commented_toml_documents_settings_and_sections() {
+        let (_, _, toml) = round_trip("config.toml");
+
+        assert!(toml.starts_with("# archiver_webhook configuration\n"));
+        assert!(toml.contains("# Discord webhook the tracks are posted to (required)\ndiscord_webhook_url = "), "{}", toml);
+        assert!(toml.contains("# Extra ffmpeg outputs of each track, see the README\n[[transcode_profiles]]"), "{}", toml);
+        // Only the first entry of an array of tables gets the comment
+        assert_eq!(toml.matches("# Extra ffmpeg outputs").count(), 1);
+        // Unset optional settings are added as commented-out examples
+        assert!(toml.contains("# auto_follow_source = \"https://soundcloud.com/user\""), "{}", toml);
+        assert!(!toml.contains("# temp_dir = \"/app/temp\""), "{}", toml);
+    }
+
+    #[test]
+    fn json_to_yaml_round_trip_keeps_every_setting() {
+        let (original, reloaded, _) = round_trip("config.yaml");
+
+        assert_eq!(as_value(&reloaded), as_value(&original));
+        assert_eq!(reloaded.transcode_profiles, original.transcode_profiles);
+        assert!(reloaded.schedule.auto_follow.is_none());
+        assert_eq!(reloaded.schedule.batch_size, 25);
+    }
+
+    #[test]
+    fn parse_reads_every_format_into_the_same_value() {
+        let json = ConfigFormat::Json.parse(r#"{ "a": 1, "b": { "c": [true, "x"] } }"#).unwrap();
+        let toml = ConfigFormat::Toml.parse("a = 1\n[b]\nc = [true, \"x\"]\n").unwrap();
+        let yaml = ConfigFormat::Yaml.parse("a: 1\nb:\n  c: [true, x]\n").unwrap();
+
+        assert_eq!(toml, json);
+        assert_eq!(yaml, json);
+        assert!(ConfigFormat::Toml.parse("a = ").is_err());
+    }
+
+    #[test]
+    fn format_is_picked_by_extension() {
+        assert_eq!(ConfigFormat::from_path("config.toml"), ConfigFormat::Toml);
+        assert_eq!(ConfigFormat::from_path("dir/config.YAML"), ConfigFormat::Yaml);
+        assert_eq!(ConfigFormat::from_path("config.yml"), ConfigFormat::Yaml);
+        assert_eq!(ConfigFormat::from_path("config.json"), ConfigFormat::Json);
+        assert_eq!(ConfigFormat::from_path("config"), ConfigFormat::Json);
+    }
+}
@@ -1,11 +1,11 @@
 use std::collections::HashMap;
-use std::fs::{File, copy, remove_file};
-use std::io::{BufReader, BufWriter};
-use std::path::Path;
+use std::fs::{File, OpenOptions, copy, remove_file};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 use log::{info, debug, trace, error, warn};
 use serde::{Deserialize, Serialize};
-use std::sync::{Arc, Mutex};
 use serde_json::Value;
+use crate::hashing::FileHash;
 
 /// Discord message information
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,6 +16,174 @@ pub struct DiscordMessage {
     pub channel_id: Option<String>,
     /// User who originally posted the track
     pub user_id: Option<String>,
+    /// SHA-256 hashes of every file archived for the track
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub file_hashes: Vec<String>,
+    /// Unix timestamp of when a deletion sweep found the track gone from SoundCloud
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<i64>,
+    /// Share token of a private track, needed to look it up again later
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_token: Option<String>,
+}
+
+/// A file uploaded to Discord, indexed by its SHA-256 hash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedFile {
+    /// Track the file was first archived for
+    pub track_id: String,
+    pub file_name: String,
+    pub size: u64,
+    /// Discord message the file is attached to
+    pub message_id: String,
+    pub channel_id: Option<String>,
+}
+
+/// Acoustic fingerprint of an archived track, used to spot re-uploads
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FingerprintRecord {
+    pub title: String,
+    pub permalink_url: String,
+    pub duration_ms: u64,
+    /// Base64 raw Chromaprint fingerprint of the first two minutes
+    pub fingerprint: String,
+    /// Discord message the track was archived in
+    #[serde(default)]
+    pub message_id: Option<String>,
+    #[serde(default)]
+    pub channel_id: Option<String>,
+}
+
+/// An archived track that sounds like the one being processed
+#[derive(Debug, Clone)]
+pub struct ReuploadMatch {
+    pub track_id: String,
+    pub record: FingerprintRecord,
+    /// Fingerprint similarity between 0.0 and 1.0
+    pub similarity: f64,
+}
+
+/// Hashes and fingerprint produced while processing a track
+#[derive(Debug, Clone, Default)]
+pub struct ArchiveRecord {
+    pub file_hashes: Vec<FileHash>,
+    pub fingerprint: Option<FingerprintRecord>,
+    /// Share token of a private track
+    pub secret_token: Option<String>,
+}
+
+/// Copy of the hash and fingerprint indexes for tasks that can't hold the database
+#[derive(Debug, Clone, Default)]
+pub struct ArchiveIndex {
+    /// SHA-256 hash to first upload
+    pub files: HashMap<String, ArchivedFile>,
+    /// Track ID to fingerprint
+    pub fingerprints: HashMap<String, FingerprintRecord>,
+}
+
+impl ArchiveIndex {
+    /// Find the archived track most similar to a fingerprint, if it reaches `threshold`
+    /// 
+    /// Only tracks of roughly the same length are compared, which keeps this fast
+    /// enough to run against the whole archive for every new track.
+    pub fn find_reupload(
+        &self,
+        track_id: &str,
+        duration_ms: u64,
+        fingerprint: &[u32],
+        threshold: f64
+    ) -> Option<ReuploadMatch> {
+        let mut best: Option<ReuploadMatch> = None;
+        
+        for (other_id, record) in &self.fingerprints {
+            if other_id == track_id {
+                continue;
+            }
+            
+            // Allow 20% (at least 30 seconds) of difference for edits and trimmed intros
+            if duration_ms > 0 && record.duration_ms > 0 {
+                let tolerance = std::cmp::max(duration_ms / 5, 30_000);
+                if duration_ms.abs_diff(record.duration_ms) > tolerance {
+                    continue;
+                }
+            }
+            
+            let other = match crate::fingerprint::decode(&record.fingerprint) {
+                Some(other) => other,
+                None => continue,
+            };
+            
+            let similarity = crate::fingerprint::similarity(fingerprint, &other);
+            if similarity >= threshold && best.as_ref().map(|b| similarity > b.similarity).unwrap_or(true) {
+                best = Some(ReuploadMatch {
+                    track_id: other_id.clone(),
+                    record: record.clone(),
+                    similarity,
+                });
+            }
+        }
+        
+        best
+    }
+}
+
+/// State of a track in the job queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    /// Waiting to be downloaded
+    Pending,
+    /// Handed to the download workers, waiting for a free one
+    Queued,
+    /// Being downloaded and processed
+    Downloading,
+    /// Processed, being posted to Discord
+    Posting,
+    /// Posted and recorded in the tracks map
+    Done,
+    /// The last attempt failed, see `last_error`, and is retried after `next_retry_at`
+    Failed,
+}
+
+/// A new track waiting to be archived
+/// 
+/// Jobs are stored in the tracks database, so tracks that were found but not yet
+/// posted are picked up again after a crash or restart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackJob {
+    pub track_id: String,
+    /// Watched user whose poll found the track
+    pub user_id: Option<String>,
+    pub state: JobState,
+    /// Unix timestamp of when the job was queued
+    pub created_at: i64,
+    /// Unix timestamp of the last state change
+    pub updated_at: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Number of failed attempts
+    #[serde(default)]
+    pub attempts: u32,
+    /// Unix timestamp before which a failed job is not retried
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_retry_at: Option<i64>,
+    /// Share token of a private track, passed on to the download
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_token: Option<String>,
+    /// Queued by a backfill, which posts its tracks in order, so the watcher leaves it alone
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub backfill: bool,
+}
+
+impl TrackJob {
+    /// Whether the job should run now
+    pub fn is_due(&self, now: i64) -> bool {
+        match self.state {
+            JobState::Pending => true,
+            JobState::Failed => self.next_retry_at.is_none_or(|at| at <= now),
+            _ => false,
+        }
+    }
 }
 
 /// Simple database to store known track IDs
@@ -24,6 +192,18 @@ pub struct TrackDatabase {
     // Map of track_ids to Discord message info
     #[serde(default)]
     tracks: HashMap<String, Option<DiscordMessage>>,
+    // Map of SHA-256 hashes to the first Discord upload of that file
+    #[serde(default)]
+    files: HashMap<String, ArchivedFile>,
+    // Map of track IDs to their acoustic fingerprint
+    #[serde(default)]
+    fingerprints: HashMap<String, FingerprintRecord>,
+    // Map of track IDs to jobs for tracks that are found but not yet archived
+    #[serde(default)]
+    jobs: HashMap<String, TrackJob>,
+    // Map of track IDs to jobs that failed too often and are no longer retried
+    #[serde(default)]
+    dead_letters: HashMap<String, TrackJob>,
     // Path to the database file (if persistent)
     #[serde(skip)]
     pub db_path: String,
@@ -34,6 +214,10 @@ impl TrackDatabase {
     pub fn new(db_path: String) -> Self {
         TrackDatabase {
             tracks: HashMap::new(),
+            files: HashMap::new(),
+            fingerprints: HashMap::new(),
+            jobs: HashMap::new(),
+            dead_letters: HashMap::new(),
             db_path,
         }
     }
@@ -44,18 +228,12 @@ impl TrackDatabase {
     /// It's called during database loading to ensure backward compatibility.
     fn migrate_from_old_format(file_path: &str) -> Result<Option<Self>, Box<dyn std::error::Error + Send + Sync>> {
         // Try to open the file
-        let file = match File::open(file_path) {
-            Ok(f) => f,
-            Err(e) => return Err(e.into()),
-        };
+        let file = File::open(file_path)?;
         
         let reader = BufReader::new(file);
         
         // First, try to parse as a raw JSON Value to check the structure
-        let json_value: Value = match serde_json::from_reader(reader) {
-            Ok(v) => v,
-            Err(e) => return Err(e.into()),
-        };
+        let json_value: Value = serde_json::from_reader(reader)?;
         
         // Check if this is the old format (array of track IDs)
         if let Some(tracks_array) = json_value.get("tracks").and_then(|t| t.as_array()) {
@@ -85,7 +263,7 @@ impl TrackDatabase {
             // Save the new format
             if let Err(e) = new_db.save() {
                 error!("Failed to save migrated database: {}", e);
-                return Err(e.into());
+                return Err(e);
             }
             
             return Ok(Some(new_db));
@@ -114,6 +292,12 @@ impl TrackDatabase {
                     db.db_path = db_path;
                     let track_count = db.tracks.len();
                     info!("Loaded tracks database with {} tracks", track_count);
+                    
+                    // Finished jobs are only kept for the run that finished them
+                    db.jobs.retain(|_, job| job.state != JobState::Done);
+                    if !db.jobs.is_empty() {
+                        info!("Tracks database has {} unfinished jobs", db.jobs.len());
+                    }
                     Ok(db)
                 },
                 Err(e) => {
@@ -143,6 +327,25 @@ impl TrackDatabase {
         }
     }
     
+    /// Load the database without ever writing to it, for dry runs
+    /// 
+    /// A missing file gives an empty database. Files that `load_or_create` would migrate
+    /// or replace are an error instead.
+    pub fn load_read_only(db_path: String) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        if !Path::new(&db_path).exists() {
+            debug!("Tracks database file not found, using an empty one");
+            return Ok(TrackDatabase::new(db_path));
+        }
+        
+        let file = File::open(&db_path)?;
+        let mut db: TrackDatabase = serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| format!("Failed to read tracks database {}: {}, run the watcher once to repair it", db_path, e))?;
+        db.db_path = db_path;
+        db.jobs.retain(|_, job| job.state != JobState::Done);
+        info!("Loaded tracks database with {} tracks (read-only)", db.tracks.len());
+        Ok(db)
+    }
+    
     /// Save database to file
     /// 
     /// Uses a safe file writing pattern to prevent data corruption
@@ -221,6 +424,7 @@ impl TrackDatabase {
     /// 
     /// This method adds tracks to the in-memory database but does not automatically save to disk.
     /// To ensure persistence, call `save()` after adding tracks.
+    #[allow(dead_code)]
     pub fn add_tracks(&mut self, track_ids: &[String]) -> Vec<String> {
         debug!("Adding tracks to database: {} total to check", track_ids.len());
         
@@ -258,12 +462,287 @@ impl TrackDatabase {
             id: discord_id,
             channel_id,
             user_id,
+            file_hashes: Vec::new(),
+            deleted_at: None,
+            secret_token: None,
         };
         
         self.tracks.insert(track_id.to_string(), Some(discord_info));
         debug!("Added track {} with Discord message info", track_id);
     }
     
+    /// Record the hashes and fingerprint of the files archived for a track
+    /// 
+    /// Every hash is stored with the track. Files that were actually attached to the
+    /// Discord message go into the hash index, unless an earlier upload is already there,
+    /// so later duplicates keep pointing at the first copy.
+    pub fn add_archive_record(
+        &mut self,
+        track_id: &str,
+        record: &ArchiveRecord,
+        attached_paths: &[String]
+    ) {
+        let discord_info = match self.tracks.get_mut(track_id) {
+            Some(Some(info)) => info,
+            _ => {
+                warn!("Cannot record archived files for track {} without Discord message info", track_id);
+                return;
+            }
+        };
+        
+        if record.secret_token.is_some() {
+            discord_info.secret_token = record.secret_token.clone();
+        }
+        
+        for hash in &record.file_hashes {
+            if !discord_info.file_hashes.contains(&hash.sha256) {
+                discord_info.file_hashes.push(hash.sha256.clone());
+            }
+        }
+        
+        let message_id = discord_info.id.clone();
+        let channel_id = discord_info.channel_id.clone();
+        let mut indexed = 0;
+        
+        for hash in record.file_hashes.iter().filter(|h| attached_paths.contains(&h.path)) {
+            if self.files.contains_key(&hash.sha256) {
+                continue;
+            }
+            
+            self.files.insert(hash.sha256.clone(), ArchivedFile {
+                track_id: track_id.to_string(),
+                file_name: hash.file_name.clone(),
+                size: hash.size,
+                message_id: message_id.clone(),
+                channel_id: channel_id.clone(),
+            });
+            indexed += 1;
+        }
+        
+        debug!("Recorded {} file hashes for track {} ({} new in index)",
+               record.file_hashes.len(), track_id, indexed);
+        
+        if let Some(fingerprint) = &record.fingerprint {
+            let mut fingerprint = fingerprint.clone();
+            fingerprint.message_id = Some(message_id);
+            fingerprint.channel_id = channel_id;
+            self.fingerprints.insert(track_id.to_string(), fingerprint);
+            debug!("Recorded fingerprint for track {}", track_id);
+        }
+    }
+    
+    /// Check if a track is neither archived, queued nor dead-lettered
+    pub fn is_new(&self, track_id: &str) -> bool {
+        !self.has_track(track_id)
+            && !self.jobs.contains_key(track_id)
+            && !self.dead_letters.contains_key(track_id)
+    }
+    
+    /// Queue a new track for archiving
+    /// 
+    /// Private tracks need their `secret_token` to be downloaded.
+    /// Returns false if the track is already archived, queued or dead-lettered.
+    pub fn enqueue_job(&mut self, track_id: &str, user_id: Option<&str>, secret_token: Option<&str>) -> bool {
+        if !self.is_new(track_id) {
+            return false;
+        }
+        
+        let now = chrono::Utc::now().timestamp();
+        self.jobs.insert(track_id.to_string(), TrackJob {
+            track_id: track_id.to_string(),
+            user_id: user_id.map(String::from),
+            state: JobState::Pending,
+            created_at: now,
+            updated_at: now,
+            last_error: None,
+            attempts: 0,
+            next_retry_at: None,
+            secret_token: secret_token.map(String::from),
+            backfill: false,
+        });
+        trace!("Queued job for track {}", track_id);
+        true
+    }
+    
+    /// Queue a track for a backfill, taking over the job if the watcher queued it already
+    /// 
+    /// Backfill jobs are left out of `due_jobs`, only running the backfill again resumes them.
+    /// Returns false if the track is already archived or dead-lettered.
+    pub fn enqueue_backfill_job(&mut self, track_id: &str, user_id: Option<&str>, secret_token: Option<&str>) -> bool {
+        self.enqueue_job(track_id, user_id, secret_token);
+        match self.jobs.get_mut(track_id) {
+            Some(job) => {
+                job.backfill = true;
+                true
+            },
+            None => false,
+        }
+    }
+    
+    /// Move a job to a new state
+    pub fn set_job_state(&mut self, track_id: &str, state: JobState) {
+        if let Some(job) = self.jobs.get_mut(track_id) {
+            trace!("Job for track {}: {:?} -> {:?}", track_id, job.state, state);
+            job.state = state;
+            job.updated_at = chrono::Utc::now().timestamp();
+        }
+    }
+    
+    /// Mark a job as failed with the error that stopped it
+    /// 
+    /// The job is retried with exponential backoff (`retry_base_delay_sec` doubled for every
+    /// failure, at most `retry_max_delay_sec`). After `retry_max_attempts` failures it moves
+    /// to the dead-letter list.
+    pub fn fail_job(&mut self, track_id: &str, error: &str, config: &crate::config::Config) {
+        self.set_job_state(track_id, JobState::Failed);
+        let job = match self.jobs.get_mut(track_id) {
+            Some(job) => job,
+            None => return,
+        };
+        
+        job.last_error = Some(error.to_string());
+        job.attempts += 1;
+        
+        if job.attempts >= config.retry_max_attempts {
+            warn!("Track {} failed {} times, moving it to the dead-letter list: {}", track_id, job.attempts, error);
+            job.next_retry_at = None;
+            if let Some(job) = self.jobs.remove(track_id) {
+                self.dead_letters.insert(track_id.to_string(), job);
+            }
+            return;
+        }
+        
+        let delay = config.retry_base_delay_sec
+            .saturating_mul(1u64 << (job.attempts - 1).min(32))
+            .min(config.retry_max_delay_sec);
+        job.next_retry_at = Some(job.updated_at + delay as i64);
+        info!("Track {} failed (attempt {}/{}), retrying in {} seconds",
+              track_id, job.attempts, config.retry_max_attempts, delay);
+    }
+    
+    /// Jobs that should run now, optionally only the ones found by one watched user
+    /// 
+    /// These are pending jobs and failed jobs whose backoff has passed, without the
+    /// jobs of an unfinished backfill.
+    pub fn due_jobs(&self, user_id: Option<&str>) -> Vec<TrackJob> {
+        let now = chrono::Utc::now().timestamp();
+        let mut jobs: Vec<TrackJob> = self.jobs.values()
+            .filter(|job| job.is_due(now) && !job.backfill)
+            .filter(|job| user_id.is_none() || job.user_id.as_deref() == user_id)
+            .cloned()
+            .collect();
+        
+        // Oldest first, so a backlog is archived in the order it was found
+        jobs.sort_by_key(|job| job.created_at);
+        jobs
+    }
+    
+    /// Due jobs, marked as queued so later polls don't hand them out again
+    pub fn take_due_jobs(&mut self, user_id: Option<&str>) -> Vec<TrackJob> {
+        let jobs = self.due_jobs(user_id);
+        for job in &jobs {
+            self.set_job_state(&job.track_id, JobState::Queued);
+        }
+        jobs
+    }
+    
+    /// Record a posted track and mark its job as done
+    pub fn finish_job(
+        &mut self,
+        track_id: &str,
+        response: &crate::discord::WebhookResponse,
+        record: &ArchiveRecord
+    ) {
+        let user_id = self.jobs.get(track_id).and_then(|job| job.user_id.clone());
+        self.add_track_with_discord_info(
+            track_id,
+            response.message_id.clone(),
+            response.channel_id.clone(),
+            user_id
+        );
+        self.add_archive_record(track_id, record, &response.attached_files);
+        self.set_job_state(track_id, JobState::Done);
+    }
+    
+    /// Put jobs that were interrupted by a crash or restart back to pending
+    /// 
+    /// Their temp files are gone with the old process, so they start over from the download.
+    /// Returns the number of jobs that were reset.
+    pub fn reset_interrupted_jobs(&mut self) -> usize {
+        let interrupted: Vec<(String, JobState)> = self.jobs.values()
+            .filter(|job| matches!(job.state, JobState::Queued | JobState::Downloading | JobState::Posting))
+            .map(|job| (job.track_id.clone(), job.state))
+            .collect();
+        
+        for (track_id, state) in &interrupted {
+            if *state == JobState::Posting {
+                warn!("Track {} was interrupted while posting, it may be posted to Discord twice", track_id);
+            }
+            self.set_job_state(track_id, JobState::Pending);
+        }
+        
+        interrupted.len()
+    }
+    
+    /// Check if a track is on the dead-letter list
+    pub fn is_dead_letter(&self, track_id: &str) -> bool {
+        self.dead_letters.contains_key(track_id)
+    }
+    
+    /// Jobs on the dead-letter list, oldest failure first
+    pub fn dead_letters(&self) -> Vec<TrackJob> {
+        let mut jobs: Vec<TrackJob> = self.dead_letters.values().cloned().collect();
+        jobs.sort_by_key(|job| job.updated_at);
+        jobs
+    }
+    
+    /// Move a dead-lettered track back into the queue with a fresh attempt count
+    /// 
+    /// Returns false if the track is not on the dead-letter list.
+    pub fn retry_dead_letter(&mut self, track_id: &str) -> bool {
+        let mut job = match self.dead_letters.remove(track_id) {
+            Some(job) => job,
+            None => return false,
+        };
+        
+        // Retried by the watcher, even if a backfill queued it
+        job.state = JobState::Pending;
+        job.attempts = 0;
+        job.next_retry_at = None;
+        job.backfill = false;
+        job.updated_at = chrono::Utc::now().timestamp();
+        self.jobs.insert(track_id.to_string(), job);
+        info!("Queued dead-lettered track {} again", track_id);
+        true
+    }
+    
+    /// Give up on a dead-lettered track
+    /// 
+    /// The track is recorded as known without Discord info, so polls don't pick it up again.
+    /// Returns false if the track is not on the dead-letter list.
+    pub fn discard_dead_letter(&mut self, track_id: &str) -> bool {
+        if self.dead_letters.remove(track_id).is_none() {
+            return false;
+        }
+        
+        self.tracks.entry(track_id.to_string()).or_insert(None);
+        info!("Discarded dead-lettered track {}", track_id);
+        true
+    }
+    
+    /// Hash index of the files uploaded to Discord
+    pub fn archived_files(&self) -> &HashMap<String, ArchivedFile> {
+        &self.files
+    }
+    
+    /// Copy of the hash and fingerprint indexes, for tasks that can't hold the database
+    pub fn archive_index(&self) -> ArchiveIndex {
+        ArchiveIndex {
+            files: self.files.clone(),
+            fingerprints: self.fingerprints.clone(),
+        }
+    }
+    
     /// Get Discord message info for a track if it exists
     pub fn get_discord_info(&self, track_id: &str) -> Option<DiscordMessage> {
         match self.tracks.get(track_id) {
@@ -290,6 +769,7 @@ impl TrackDatabase {
     /// Find all tracks by a specific user ID
     /// 
     /// Returns a list of track IDs that were posted by the specified user ID
+    #[allow(dead_code)]
     pub fn find_tracks_by_user(&self, user_id: &str) -> Vec<String> {
         let mut result = Vec::new();
         
@@ -309,18 +789,41 @@ impl TrackDatabase {
     /// Get all Discord message IDs stored in the database
     /// 
     /// Returns a list of all Discord message IDs that have been stored
+    #[allow(dead_code)]
     pub fn get_all_discord_ids(&self) -> Vec<String> {
         let mut result = Vec::new();
         
-        for (_track_id, discord_info) in &self.tracks {
-            if let Some(info) = discord_info {
-                result.push(info.id.clone());
-            }
+        for info in self.tracks.values().flatten() {
+            result.push(info.id.clone());
         }
         
         result
     }
     
+    /// Posted tracks that are not known to be deleted, sorted by track ID
+    pub fn posted_tracks(&self) -> Vec<(String, DiscordMessage)> {
+        let mut result: Vec<(String, DiscordMessage)> = self.tracks.iter()
+            .filter_map(|(track_id, info)| info.as_ref().map(|info| (track_id.clone(), info.clone())))
+            .filter(|(_, info)| info.deleted_at.is_none())
+            .collect();
+        result.sort_by(|a, b| a.0.cmp(&b.0));
+        result
+    }
+    
+    /// Record that a track was deleted from SoundCloud
+    pub fn mark_deleted(&mut self, track_id: &str) {
+        if let Some(Some(info)) = self.tracks.get_mut(track_id) {
+            info.deleted_at = Some(chrono::Utc::now().timestamp());
+        }
+    }
+    
+    /// Drop finished jobs, returns the number of jobs dropped
+    pub fn compact(&mut self) -> usize {
+        let before = self.jobs.len();
+        self.jobs.retain(|_, job| job.state != JobState::Done);
+        before - self.jobs.len()
+    }
+    
     /// Initialize the database with a batch of track IDs
     pub fn initialize_with_tracks(&mut self, track_ids: &[String]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let count_before = self.tracks.len();
@@ -343,6 +846,7 @@ impl TrackDatabase {
     /// 
     /// This is a convenience method that adds tracks and then saves the database,
     /// ensuring that changes are persisted even if the application crashes.
+    #[allow(dead_code)]
     pub fn add_tracks_and_save(&mut self, track_ids: &[String]) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
         let new_tracks = self.add_tracks(track_ids);
         
@@ -429,176 +933,204 @@ impl TrackDatabase {
         
         Ok((total_users_processed, total_tracks_added))
     }
+}
 
-    /// Poll a user for new tracks and process them
-    pub async fn poll_user(
-        &mut self,
-        user_id: &str,
-        config: &crate::config::Config,
-        processing_semaphore: &Arc<tokio::sync::Semaphore>,
-        discord_semaphore: &Arc<tokio::sync::Semaphore>
-    ) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
-        // Fetch latest tracks from SoundCloud
-        let tracks = match crate::soundcloud::get_user_tracks(user_id, config.max_tracks_per_user, config.pagination_size).await {
-            Ok(t) => t,
-            Err(e) => {
-                error!("Failed to fetch tracks for user {}: {}", user_id, e);
-                return Err(e);
-            }
-        };
-        
-        debug!("Fetched {} tracks for user {}", tracks.len(), user_id);
-        
-        // If enabled, fetch user likes as well
-        let mut all_tracks = tracks.clone();
+/// Lock file that keeps two processes from writing the same tracks database
+/// 
+/// The watcher and a backfill both rewrite the whole file, so whichever saves last would
+/// drop the other's changes. The lock is released when this is dropped.
+#[derive(Debug)]
+pub struct DatabaseLock {
+    path: PathBuf,
+}
+
+impl DatabaseLock {
+    /// Lock the tracks database at `db_path` for this process
+    /// 
+    /// Fails if a running process holds the lock. A lock left behind by a crashed process
+    /// is taken over.
+    pub fn acquire(db_path: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let path = PathBuf::from(format!("{}.lock", db_path));
         
-        if config.scrape_user_likes {
-            debug!("Fetching likes for user {} (enabled in config)", user_id);
-            match crate::soundcloud::get_user_likes(user_id, config.max_likes_per_user, config.pagination_size).await {
-                Ok(likes) => {
-                    info!("Fetched {} likes for user {}", likes.len(), user_id);
-                    
-                    // Extract tracks from likes
-                    let liked_tracks = crate::soundcloud::extract_tracks_from_likes(&likes);
-                    debug!("Extracted {} tracks from user {}'s likes", liked_tracks.len(), user_id);
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    write!(file, "{}", std::process::id())?;
+                    debug!("Locked tracks database with {}", path.display());
+                    return Ok(DatabaseLock { path });
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    let owner = std::fs::read_to_string(&path).ok()
+                        .and_then(|pid| pid.trim().parse::<u32>().ok());
                     
-                    // Add liked tracks to our collection
-                    all_tracks.extend(liked_tracks);
-                    debug!("Total tracks (uploads + likes): {}", all_tracks.len());
+                    match owner {
+                        Some(pid) if process_running(pid) => {
+                            return Err(format!(
+                                "{} is in use by process {}, stop the watcher or backfill first (or delete {} if that process isn't one)",
+                                db_path, pid, path.display()
+                            ).into());
+                        },
+                        _ => {
+                            warn!("Removing stale lock file {}", path.display());
+                            remove_file(&path)?;
+                        }
+                    }
                 },
-                Err(e) => {
-                    warn!("Failed to fetch likes for user {}: {}", user_id, e);
-                    // Continue with just the user's tracks
-                }
+                Err(e) => return Err(format!("Failed to create lock file {}: {}", path.display(), e).into()),
             }
         }
         
-        // Check which tracks are new
-        let track_ids: Vec<String> = all_tracks.iter().map(|t| t.id.clone()).collect();
-        
-        // Get new track IDs without adding to database yet
-        let new_track_ids: Vec<String> = track_ids.iter()
-            .filter(|id| !self.has_track(id))
-            .cloned()
-            .collect::<Vec<String>>();
+        Err(format!("Failed to lock {}", db_path).into())
+    }
+}
+
+impl Drop for DatabaseLock {
+    fn drop(&mut self) {
+        match remove_file(&self.path) {
+            Ok(()) => debug!("Unlocked tracks database, removed {}", self.path.display()),
+            Err(e) => warn!("Failed to remove lock file {}: {}", self.path.display(), e),
+        }
+    }
+}
+
+/// Whether another process with this ID is running
+/// 
+/// Without `/proc` the process is assumed to be running. Our own ID means the lock
+/// was left by an earlier process that had the same ID, as in a restarted container.
+fn process_running(pid: u32) -> bool {
+    if pid == std::process::id() {
+        return false;
+    }
+    
+    let proc_dir = Path::new("/proc");
+    !proc_dir.join("self").exists() || proc_dir.join(pid.to_string()).exists()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    
+    fn retry_config(base: u64, max: u64, attempts: u32) -> Config {
+        Config {
+            retry_base_delay_sec: base,
+            retry_max_delay_sec: max,
+            retry_max_attempts: attempts,
+            ..Config::default()
+        }
+    }
+    
+    /// Seconds between the last failure of a job and its next retry
+    fn retry_delay(db: &TrackDatabase, track_id: &str) -> i64 {
+        let job = &db.jobs[track_id];
+        job.next_retry_at.expect("failed job has no retry time") - job.updated_at
+    }
+    
+    #[test]
+    fn fail_job_doubles_the_delay_for_every_failure() {
+        let config = retry_config(60, 3600, 10);
+        let mut db = TrackDatabase::new(String::new());
+        assert!(db.enqueue_job("1", Some("user"), None));
         
-        if new_track_ids.is_empty() {
-            return Ok(0); // No new tracks
+        let mut delays = Vec::new();
+        for _ in 0..4 {
+            db.fail_job("1", "HTTP error 500", &config);
+            delays.push(retry_delay(&db, "1"));
         }
         
-        // Process new tracks in parallel with resource limits
-        let mut tasks = Vec::new();
-        let successful_tracks: Arc<Mutex<Vec<(String, Option<String>, Option<String>)>>> = Arc::new(Mutex::new(Vec::new()));
+        assert_eq!(delays, vec![60, 120, 240, 480]);
+        let job = &db.jobs["1"];
+        assert_eq!(job.state, JobState::Failed);
+        assert_eq!(job.attempts, 4);
+        assert_eq!(job.last_error.as_deref(), Some("HTTP error 500"));
+    }
+    
+    #[test]
+    fn fail_job_caps_the_delay_at_the_maximum() {
+        let config = retry_config(60, 100, u32::MAX);
+        let mut db = TrackDatabase::new(String::new());
+        db.enqueue_job("1", None, None);
         
-        for track_id in &new_track_ids {
-            // Find the track in our collection
-            let track = match all_tracks.iter().find(|t| &t.id == track_id) {
-                Some(t) => t.clone(),
-                None => {
-                    warn!("Could not find track {} in fetched tracks - skipping", track_id);
-                    continue;
-                }
-            };
-            
-            let processing_semaphore = Arc::clone(processing_semaphore);
-            let discord_semaphore = Arc::clone(discord_semaphore);
-            let successful_tracks = Arc::clone(&successful_tracks);
-            
-            // Spawn a task to process this track
-            let webhook_url = config.discord_webhook_url.clone();
-            let temp_dir = config.temp_dir.clone();
-            let _user_id_clone = user_id.to_string();
-            let task = tokio::spawn(async move {
-                // Acquire semaphore to limit concurrent ffmpeg processes
-                let _permit = match processing_semaphore.acquire().await {
-                    Ok(permit) => permit,
-                    Err(e) => {
-                        error!("Failed to acquire processing semaphore for track {}: {}", track.id, e);
-                        return;
-                    }
-                };
-                
-                debug!("Processing new track: {} (ID: {})", track.title, track.id);
-                
-                // Get full track details
-                let track_details = match crate::soundcloud::get_track_details(&track.id).await {
-                    Ok(t) => t,
-                    Err(e) => {
-                        error!("Failed to get track details for {}: {}", track.id, e);
-                        return;
-                    }
-                };
-                
-                // Process and post the track with both semaphores
-                match crate::soundcloud::process_and_post_track(
-                    &track.id,
-                    &webhook_url,
-                    temp_dir.as_deref(),
-                    Some(&discord_semaphore)
-                ).await {
-                    Ok((_track_id, _user_id, response)) => {
-                        info!("Successfully sent webhook for track: {} by {} (Discord message ID: {})", 
-                              track_details.title, track_details.user.username, response.message_id);
-                        let mut tracks = successful_tracks.lock().unwrap();
-                        tracks.push((
-                            track.id.clone(),
-                            Some(response.message_id),
-                            response.channel_id
-                        ));
-                    },
-                    Err(e) => {
-                        error!("Failed to process and post track {}: {}", track.id, e);
-                    }
-                };
-            });
-            
-            tasks.push(task);
+        db.fail_job("1", "error", &config);
+        assert_eq!(retry_delay(&db, "1"), 60);
+        db.fail_job("1", "error", &config);
+        assert_eq!(retry_delay(&db, "1"), 100);
+        
+        // Far past the point where the doubling would overflow
+        for _ in 0..70 {
+            db.fail_job("1", "error", &config);
         }
+        assert_eq!(retry_delay(&db, "1"), 100);
+    }
+    
+    #[test]
+    fn failed_job_is_only_due_after_its_backoff() {
+        let config = retry_config(60, 3600, 10);
+        let mut db = TrackDatabase::new(String::new());
+        db.enqueue_job("1", None, None);
+        assert_eq!(db.take_due_jobs(None).len(), 1);
         
-        // Wait for all track processing tasks to complete
-        let mut new_tracks_processed = 0;
+        db.fail_job("1", "error", &config);
+        assert!(db.due_jobs(None).is_empty());
         
-        for task in tasks {
-            match task.await {
-                Ok(()) => {
-                    new_tracks_processed += 1;
-                },
-                Err(e) => {
-                    error!("Error in track processing task: {}", e);
-                    crate::loghandler::increment_error_count();
-                }
-            }
-        }
+        let retry_at = db.jobs["1"].next_retry_at.unwrap();
+        assert!(!db.jobs["1"].is_due(retry_at - 1));
+        assert!(db.jobs["1"].is_due(retry_at));
+    }
+    
+    #[test]
+    fn fail_job_moves_the_job_to_dead_letters_after_the_last_attempt() {
+        let config = retry_config(60, 3600, 3);
+        let mut db = TrackDatabase::new(String::new());
+        db.enqueue_job("1", None, None);
         
-        // Add successful tracks to database with Discord info
-        let successful_tracks_guard = successful_tracks.lock().unwrap();
-        if !successful_tracks_guard.is_empty() {
-            // Add successful tracks to the database with Discord message info
-            for (track_id, message_id, channel_id) in successful_tracks_guard.iter() {
-                if let Some(discord_id) = message_id {
-                    // Add with Discord message info
-                    self.add_track_with_discord_info(
-                        track_id, 
-                        discord_id.clone(), 
-                        channel_id.clone(),
-                        Some(user_id.to_string())
-                    );
-                } else {
-                    // Just add the track without Discord info
-                    self.tracks.insert(track_id.clone(), None);
-                }
-            }
-            
-            // Save the database
-            if let Err(e) = self.save() {
-                error!("Failed to save tracks database with Discord info: {}", e);
-            } else {
-                info!("Database saved with {} tracks including Discord message IDs", 
-                     successful_tracks_guard.len());
-                crate::loghandler::increment_total_tracks(successful_tracks_guard.len() as u64);
-            }
-        }
+        db.fail_job("1", "error", &config);
+        db.fail_job("1", "error", &config);
+        assert!(!db.is_dead_letter("1"));
+        
+        db.fail_job("1", "gone", &config);
+        assert!(db.is_dead_letter("1"));
+        assert!(!db.jobs.contains_key("1"));
+        assert_eq!(db.dead_letters["1"].attempts, 3);
+        assert_eq!(db.dead_letters["1"].next_retry_at, None);
+        
+        // Dead-lettered tracks are not queued again by later polls
+        assert!(!db.enqueue_job("1", None, None));
+    }
+    
+    #[test]
+    #[cfg(unix)]
+    fn database_lock_refuses_a_second_holder() {
+        let db_path = std::env::temp_dir().join(format!("tracks_{}.json", uuid::Uuid::new_v4()));
+        let db_path = db_path.to_string_lossy().to_string();
+        let lock_path = format!("{}.lock", db_path);
+        
+        // A lock of a process that isn't running is taken over
+        std::fs::write(&lock_path, std::process::id().to_string()).unwrap();
+        let lock = DatabaseLock::acquire(&db_path).unwrap();
         
-        Ok(new_tracks_processed)
+        // The lock of a running process is not, pretend it belongs to our parent
+        drop(lock);
+        let parent = std::os::unix::process::parent_id();
+        std::fs::write(&lock_path, parent.to_string()).unwrap();
+        assert!(DatabaseLock::acquire(&db_path).is_err());
+        
+        std::fs::remove_file(&lock_path).unwrap();
+        drop(DatabaseLock::acquire(&db_path).unwrap());
+        assert!(!Path::new(&lock_path).exists());
     }
-} 
\ No newline at end of file
+    
+    #[test]
+    fn backfill_jobs_are_not_due_for_the_watcher() {
+        let mut db = TrackDatabase::new(String::new());
+        db.enqueue_job("1", Some("user"), None);
+        assert!(db.enqueue_backfill_job("1", Some("user"), None));
+        assert!(db.enqueue_backfill_job("2", Some("user"), None));
+        
+        assert!(db.jobs["1"].backfill);
+        assert!(db.due_jobs(None).is_empty());
+        assert!(db.take_due_jobs(Some("user")).is_empty());
+        assert_eq!(db.jobs["2"].state, JobState::Pending);
+    }
+}
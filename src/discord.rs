@@ -5,6 +5,8 @@ use std::path::Path;
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
 use log::{info, warn, error, debug};
+use crate::analysis::LoudnessReport;
+use crate::db::{ArchivedFile, ReuploadMatch};
 use crate::soundcloud::Track;
 
 /// Response data from a Discord webhook
@@ -12,17 +14,43 @@ use crate::soundcloud::Track;
 pub struct WebhookResponse {
     pub message_id: String,
     pub channel_id: Option<String>,
+    /// Paths of the files that were attached to the message
+    pub attached_files: Vec<String>,
+}
+
+/// Notes about the archive shown in the track embed
+#[derive(Debug, Clone, Default)]
+pub struct ArchiveNotes {
+    /// Files already on Discord, as (file_name, first upload)
+    pub duplicate_files: Vec<(String, ArchivedFile)>,
+    /// Archived track that sounds the same
+    pub possible_reupload: Option<ReuploadMatch>,
+    /// Loudness measured by the audio analysis
+    pub loudness: Option<LoudnessReport>,
+}
+
+lazy_static::lazy_static! {
+    // Guild of the webhook, needed to build message links (looked up once)
+    static ref WEBHOOK_GUILD_ID: tokio::sync::Mutex<Option<Option<String>>> = tokio::sync::Mutex::new(None);
 }
 
 /// Send a track to Discord via webhook
 pub async fn send_track_webhook(
     webhook_url: &str, 
     track: &Track,
-    audio_files: Option<Vec<(String, String)>> // Vec of (file_path, file_name)
+    audio_files: Option<Vec<(String, String)>>, // Vec of (file_path, file_name)
+    notes: &ArchiveNotes
 ) -> Result<WebhookResponse, Box<dyn std::error::Error + Send + Sync>> {
     // Create the webhook client
     let client = Client::new();
     
+    // Links to earlier posts need the guild ID of the webhook
+    let guild_id = if notes.duplicate_files.is_empty() && notes.possible_reupload.is_none() {
+        None
+    } else {
+        get_webhook_guild_id(&client, webhook_url).await
+    };
+    
     // Add wait=true parameter to webhook URL
     let webhook_url = if webhook_url.contains('?') {
         format!("{}&wait=true", webhook_url)
@@ -32,7 +60,19 @@ pub async fn send_track_webhook(
     
     // Build the embed object
     info!("Preparing Discord webhook for track '{}' (ID: {})", track.title, track.id);
-    let embed = build_track_embed(track);
+    let mut embed = build_track_embed(track);
+    
+    if let Some(found) = &notes.possible_reupload {
+        add_reupload_field(&mut embed, found, guild_id.as_deref());
+    }
+    
+    if let Some(loudness) = &notes.loudness {
+        add_loudness_field(&mut embed, loudness);
+    }
+    
+    if !notes.duplicate_files.is_empty() {
+        add_duplicate_files_field(&mut embed, &notes.duplicate_files, guild_id.as_deref());
+    }
     
     // Check audio files
     let files_count = match &audio_files {
@@ -164,8 +204,10 @@ fn build_track_embed(track: &Track) -> Value {
     
     debug!("Created {} embed fields for Discord message", fields.len());
     
-    // Get original high-resolution artwork URL if available
+    // Get original high-resolution artwork URL if available, SoundCloud shows the avatar otherwise
     let artwork_url = track.artwork_url.clone()
+        .filter(|url| !url.is_empty())
+        .or_else(|| track.user.avatar_url.clone())
         .map(|url| crate::soundcloud::get_original_artwork_url(&url))
         .unwrap_or_default();
     
@@ -192,6 +234,248 @@ fn build_track_embed(track: &Track) -> Value {
     })
 }
 
+/// Build a link to a Discord message, if the guild and channel are known
+fn message_link(guild_id: Option<&str>, channel_id: Option<&str>, message_id: &str) -> Option<String> {
+    match (guild_id, channel_id) {
+        (Some(guild), Some(channel)) => Some(format!("https://discord.com/channels/{}/{}/{}", guild, channel, message_id)),
+        _ => None,
+    }
+}
+
+/// Add a warning field for a track that sounds like an already archived one
+fn add_reupload_field(embed: &mut Value, found: &ReuploadMatch, guild_id: Option<&str>) {
+    let mut value = format!(
+        "Possible re-upload of [{}]({}) ({:.0}% similar)",
+        found.record.title.replace(['[', ']'], ""),
+        found.record.permalink_url,
+        found.similarity * 100.0
+    );
+    
+    if let Some(message_id) = &found.record.message_id {
+        if let Some(link) = message_link(guild_id, found.record.channel_id.as_deref(), message_id) {
+            value.push_str(&format!("\n[Archived post]({})", link));
+        }
+    }
+    
+    if let Some(fields) = embed.get_mut("fields").and_then(Value::as_array_mut) {
+        // Put the warning first so it is seen before the metadata
+        fields.insert(0, json!({
+            "name": "⚠️ Possible re-upload",
+            "value": value,
+            "inline": false
+        }));
+    }
+}
+
+/// Add a field with the measured loudness and peak
+fn add_loudness_field(embed: &mut Value, loudness: &LoudnessReport) {
+    let value = format!(
+        "{} LUFS • LRA {} LU • Peak {} dBTP",
+        crate::analysis::format_measure(loudness.integrated_lufs),
+        crate::analysis::format_measure(loudness.loudness_range_lu),
+        crate::analysis::format_measure(loudness.true_peak_dbtp)
+    );
+    
+    if let Some(fields) = embed.get_mut("fields").and_then(Value::as_array_mut) {
+        fields.push(json!({
+            "name": "Loudness",
+            "value": value,
+            "inline": false
+        }));
+    }
+}
+
+/// Add a field linking files that were skipped because they are already archived
+fn add_duplicate_files_field(
+    embed: &mut Value,
+    duplicate_files: &[(String, ArchivedFile)],
+    guild_id: Option<&str>
+) {
+    // Discord limits field values to 1024 characters
+    const MAX_FIELD_LENGTH: usize = 1024;
+    
+    let mut value = String::new();
+    for (file_name, archived) in duplicate_files {
+        let line = match message_link(guild_id, archived.channel_id.as_deref(), &archived.message_id) {
+            Some(link) => format!("`{}` → [original post]({})\n", file_name, link),
+            None => format!("`{}` → message {} (track {})\n", file_name, archived.message_id, archived.track_id),
+        };
+        
+        if value.len() + line.len() > MAX_FIELD_LENGTH {
+            value.push('…');
+            break;
+        }
+        value.push_str(&line);
+    }
+    
+    if let Some(fields) = embed.get_mut("fields").and_then(Value::as_array_mut) {
+        fields.push(json!({
+            "name": "Already archived (not re-uploaded)",
+            "value": value.trim_end(),
+            "inline": false
+        }));
+    }
+}
+
+/// Look up the guild ID of the webhook, caching the result
+/// 
+/// Failed lookups aren't cached, so a network error only costs the links of one post.
+async fn get_webhook_guild_id(client: &Client, webhook_url: &str) -> Option<String> {
+    let mut cached = WEBHOOK_GUILD_ID.lock().await;
+    if let Some(guild_id) = cached.as_ref() {
+        return guild_id.clone();
+    }
+    
+    // GET on a webhook URL returns the webhook object, token included in the URL
+    let webhook = match client.get(webhook_url).send().await {
+        Ok(response) if response.status().is_success() => response.json::<Value>().await,
+        Ok(response) => {
+            warn!("Failed to look up webhook guild: HTTP {}", response.status());
+            return None;
+        },
+        Err(e) => {
+            warn!("Failed to look up webhook guild: {}", e.without_url());
+            return None;
+        }
+    };
+    
+    match webhook {
+        Ok(webhook) => {
+            let guild_id = webhook.get("guild_id").and_then(|g| g.as_str()).map(String::from);
+            *cached = Some(guild_id.clone());
+            guild_id
+        },
+        Err(e) => {
+            warn!("Failed to read webhook guild: {}", e.without_url());
+            None
+        }
+    }
+}
+
+/// Name of the embed field added to posts of tracks deleted from SoundCloud
+const DELETED_FIELD_NAME: &str = "🗑️ Deleted from SoundCloud";
+
+/// Fields that `build_track_embed` creates, everything else was added with the archive notes
+const TRACK_FIELD_NAMES: [&str; 3] = ["Duration", "Genre", "Tags"];
+
+/// Update a posted embed with the current track metadata
+/// 
+/// Fields added from the archive notes (duplicates, re-upload, loudness) are kept.
+/// The attachments of the message are not touched.
+pub async fn refresh_track_embed(
+    webhook_url: &str,
+    message_id: &str,
+    track: &Track
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = Client::new();
+    let message_url = webhook_message_url(webhook_url, message_id);
+    
+    let old_embed = get_message_embed(&client, &message_url).await?;
+    let mut embed = build_track_embed(track);
+    
+    let kept_fields: Vec<Value> = old_embed.get("fields")
+        .and_then(Value::as_array)
+        .map(|fields| fields.iter()
+            .filter(|field| {
+                let name = field.get("name").and_then(Value::as_str).unwrap_or("");
+                !TRACK_FIELD_NAMES.contains(&name)
+            })
+            .cloned()
+            .collect())
+        .unwrap_or_default();
+    
+    if let Some(fields) = embed.get_mut("fields").and_then(Value::as_array_mut) {
+        fields.extend(kept_fields);
+    }
+    
+    edit_message_embed(&client, &message_url, embed).await?;
+    debug!("Refreshed embed of track '{}' (message {})", track.title, message_id);
+    Ok(())
+}
+
+/// Flag a posted embed to show that the track was deleted from SoundCloud
+pub async fn mark_track_deleted(
+    webhook_url: &str,
+    message_id: &str
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = Client::new();
+    let message_url = webhook_message_url(webhook_url, message_id);
+    
+    let mut embed = get_message_embed(&client, &message_url).await?;
+    
+    let fields = match embed.get_mut("fields").and_then(Value::as_array_mut) {
+        Some(fields) => fields,
+        None => {
+            embed["fields"] = json!([]);
+            embed["fields"].as_array_mut().ok_or("embed fields are not an array")?
+        }
+    };
+    
+    if fields.iter().any(|field| field.get("name").and_then(Value::as_str) == Some(DELETED_FIELD_NAME)) {
+        return Ok(());
+    }
+    
+    fields.insert(0, json!({
+        "name": DELETED_FIELD_NAME,
+        "value": format!("Found missing <t:{}:R>", chrono::Utc::now().timestamp()),
+        "inline": false
+    }));
+    embed["color"] = json!(0x808080); // Grey instead of SoundCloud orange
+    
+    edit_message_embed(&client, &message_url, embed).await
+}
+
+/// URL of a message sent by a webhook, keeping query parameters like `thread_id`
+fn webhook_message_url(webhook_url: &str, message_id: &str) -> String {
+    match webhook_url.split_once('?') {
+        Some((base, query)) => format!("{}/messages/{}?{}", base.trim_end_matches('/'), message_id, query),
+        None => format!("{}/messages/{}", webhook_url.trim_end_matches('/'), message_id),
+    }
+}
+
+/// Fetch the first embed of a webhook message
+async fn get_message_embed(
+    client: &Client,
+    message_url: &str
+) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+    let response = client.get(message_url).send().await
+        .map_err(|e| e.without_url())?;
+    
+    let status = response.status();
+    if !status.is_success() {
+        let error_text = response.text().await.unwrap_or_default();
+        return Err(format!("Discord webhook error: {} - {}", status, error_text).into());
+    }
+    
+    let message: Value = response.json().await?;
+    message.get("embeds")
+        .and_then(|embeds| embeds.get(0))
+        .cloned()
+        .ok_or_else(|| "Discord message has no embed".into())
+}
+
+/// Replace the embeds of a webhook message
+async fn edit_message_embed(
+    client: &Client,
+    message_url: &str,
+    embed: Value
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let response = client.patch(message_url)
+        .json(&json!({ "embeds": [embed] }))
+        .send()
+        .await
+        .map_err(|e| e.without_url())?;
+    
+    let status = response.status();
+    if !status.is_success() {
+        let error_text = response.text().await.unwrap_or_default();
+        error!("Discord webhook error: {} - {}", status, error_text);
+        return Err(format!("Discord webhook error: {} - {}", status, error_text).into());
+    }
+    
+    Ok(())
+}
+
 /// Parse a tag list string, respecting quoted tags
 /// 
 /// Handles:
@@ -301,27 +585,30 @@ async fn send_embed_only(
         .map(|s| s.to_string());
     
     debug!("Discord webhook sent successfully, message ID: {}", message_id);
-    Ok(WebhookResponse { message_id, channel_id })
+    Ok(WebhookResponse { message_id, channel_id, attached_files: Vec::new() })
 }
 
-/// Send the embed with audio file attachments
-async fn send_with_audio_files(
-    client: Client,
-    webhook_url: &str,
-    embed: Value,
-    files: Vec<(String, String)> // Vec of (file_path, file_name)
-) -> Result<WebhookResponse, Box<dyn std::error::Error + Send + Sync>> {
-    debug!("Preparing multipart request with {} audio files", files.len());
-    
-    // Discord limits: 
-    // - Max 8MB per file for regular uploads 
+/// Files picked for a Discord message, in the order they are attached
+#[derive(Debug, Clone, Default)]
+pub struct AttachmentPlan {
+    /// (file_path, file_name, size) of the attached files
+    pub attached: Vec<(String, String, u64)>,
+    /// (file_name, size, reason) of the files left out
+    pub skipped: Vec<(String, u64, String)>,
+}
+
+/// Pick the files to attach and their order, within Discord's limits
+///
+/// Dry runs use this to report what `send_with_audio_files` would attach.
+pub fn plan_attachments(files: Vec<(String, String)>) -> AttachmentPlan {
+    // Discord limits:
+    // - Max 8MB per file for regular uploads
     // - Max 10 attachments per message
     const MAX_DISCORD_UPLOAD_SIZE: u64 = 8 * 1024 * 1024; // 8MB per file
     const MAX_ATTACHMENTS: usize = 8;
     
     // Filter files to respect Discord limits
-    let mut filtered_files = Vec::new();
-    let mut file_count = 0;
+    let mut plan = AttachmentPlan::default();
     
     // First pass: get all files and their sizes
     let mut file_sizes = Vec::new();
@@ -338,11 +625,22 @@ async fn send_with_audio_files(
     }
     
     // Sort files by priority as specified:
+    // 0. The original upload (if the track was downloadable)
     // 1. m4a and ogg files first
     // 2. JSON metadata files
-    // 3. Artwork files 
+    // 3. Artwork files (cover art first)
     // 4. MP3 files last
     file_sizes.sort_by(|(path_a, _, size_a), (path_b, _, size_b)| {
+        // Top priority: the original uploaded file
+        let is_original_a = crate::audio::is_original_upload(path_a);
+        let is_original_b = crate::audio::is_original_upload(path_b);
+        if is_original_a && !is_original_b {
+            return std::cmp::Ordering::Less;
+        }
+        if !is_original_a && is_original_b {
+            return std::cmp::Ordering::Greater;
+        }
+        
         // Get file extensions for easier comparison
         let ext_a = Path::new(path_a).extension().and_then(|e| e.to_str()).unwrap_or("");
         let ext_b = Path::new(path_b).extension().and_then(|e| e.to_str()).unwrap_or("");
@@ -377,37 +675,65 @@ async fn send_with_audio_files(
             return std::cmp::Ordering::Greater;
         }
         
+        // Among images, the cover art comes before avatar, banner and analysis images
+        let is_cover_a = is_image_a && path_a.contains("_cover.");
+        let is_cover_b = is_image_b && path_b.contains("_cover.");
+        if is_cover_a != is_cover_b {
+            return if is_cover_a { std::cmp::Ordering::Less } else { std::cmp::Ordering::Greater };
+        }
+        
         // MP3 files come last automatically
         // For files of the same type, prefer smaller files first
         size_a.cmp(size_b)
     });
     
-    // Add files until we hit the attachment limit
+    // Add files until we hit the attachment limit, the rest is reported as skipped
     let file_sizes_len = file_sizes.len();
+    let mut limit_reached = false;
     for (file_path, file_name, file_size) in file_sizes {
         // Check if we would exceed limits by adding this file
-        if file_count >= MAX_ATTACHMENTS {
-            warn!("Reached Discord attachment limit of {} files", MAX_ATTACHMENTS);
-            break;
+        if plan.attached.len() >= MAX_ATTACHMENTS {
+            if !limit_reached {
+                warn!("Reached Discord attachment limit of {} files", MAX_ATTACHMENTS);
+                limit_reached = true;
+            }
+            plan.skipped.push((file_name, file_size, format!("attachment limit of {} files", MAX_ATTACHMENTS)));
+            continue;
         }
-        
+    
         // Check each file individually against the 8MB limit
         if file_size > MAX_DISCORD_UPLOAD_SIZE {
-            warn!("File {} exceeds Discord size limit ({} > {})", 
+            warn!("File {} exceeds Discord size limit ({} > {})",
                  file_name, file_size, MAX_DISCORD_UPLOAD_SIZE);
+            plan.skipped.push((file_name, file_size, format!("over the {} MB limit", MAX_DISCORD_UPLOAD_SIZE / 1024 / 1024)));
             continue;
         }
-        
+    
         // Add the file
-        filtered_files.push((file_path, file_name));
-        file_count += 1;
+        plan.attached.push((file_path, file_name, file_size));
     }
     
-    if filtered_files.len() < file_sizes_len {
+    if plan.attached.len() < file_sizes_len {
         warn!("Some files were excluded due to Discord limits: {} of {} files included",
-             filtered_files.len(), file_sizes_len);
+             plan.attached.len(), file_sizes_len);
     }
     
+    plan
+}
+
+/// Send the embed with audio file attachments
+async fn send_with_audio_files(
+    client: Client,
+    webhook_url: &str,
+    embed: Value,
+    files: Vec<(String, String)> // Vec of (file_path, file_name)
+) -> Result<WebhookResponse, Box<dyn std::error::Error + Send + Sync>> {
+    debug!("Preparing multipart request with {} audio files", files.len());
+    
+    let filtered_files: Vec<(String, String)> = plan_attachments(files).attached.into_iter()
+        .map(|(file_path, file_name, _)| (file_path, file_name))
+        .collect();
+    
     // Create a multipart form
     let mut form = multipart::Form::new()
         .text("payload_json", json!({
@@ -455,6 +781,9 @@ async fn send_with_audio_files(
             Some(ext) if ext == "ogg" => "audio/ogg",
             Some(ext) if ext == "opus" => "audio/opus",
             Some(ext) if ext == "m4a" => "audio/mp4",
+            Some(ext) if ext == "wav" => "audio/wav",
+            Some(ext) if ext == "flac" => "audio/flac",
+            Some(ext) if ext == "aiff" || ext == "aif" => "audio/aiff",
             Some(ext) if ext == "json" => "application/json",
             Some(ext) if ext == "jpg" || ext == "jpeg" => "image/jpeg",
             Some(ext) if ext == "png" => "image/png",
@@ -518,5 +847,6 @@ async fn send_with_audio_files(
         .map(|s| s.to_string());
     
     debug!("Discord webhook with files sent successfully, message ID: {}", message_id);
-    Ok(WebhookResponse { message_id, channel_id })
+    let attached_files = filtered_files.into_iter().map(|(path, _)| path).collect();
+    Ok(WebhookResponse { message_id, channel_id, attached_files })
 } 
\ No newline at end of file
@@ -0,0 +1,206 @@
+use std::collections::HashSet;
+use std::path::Path;
+use log::{info, warn, debug};
+use crate::config::{Config, Users};
+use crate::db::{ArchiveIndex, TrackDatabase};
+use crate::discord;
+use crate::formats::FormatSelection;
+use crate::loghandler::update_log_level;
+use crate::pipeline;
+use crate::soundcloud::{self, PreparedTrack, Track};
+
+/// Poll every active user once and print what the watcher would archive
+///
+/// Nothing is posted to Discord and neither the tracks database nor the users file is
+/// written. With `download`, the new tracks are downloaded and processed to show the
+/// attachments of their messages, and the temp files are deleted again.
+pub async fn dry_run_watch(download: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (config, mut db) = load_read_only()?;
+
+    if !Path::new(&config.users_file).exists() {
+        return Err(format!("Users file {} not found", config.users_file).into());
+    }
+    let users = Users::load(&config.users_file)?;
+    let user_ids = users.active();
+
+    initialize_soundcloud(&config).await?;
+
+    println!("Dry run: nothing is posted to Discord or written to {}", config.tracks_file);
+    if !users.paused.is_empty() {
+        println!("Skipping {} paused user(s)", users.paused.len());
+    }
+    if config.auto_follow_source.is_some() {
+        println!("Not checking auto_follow_source for new followings");
+    }
+
+    // Jobs of the last run are resumed before the first poll
+    db.reset_interrupted_jobs();
+    let unfinished = db.due_jobs(None);
+    if !unfinished.is_empty() {
+        println!("\nWould resume {} unfinished job(s) from the last run:", unfinished.len());
+        for job in &unfinished {
+            println!("  {} (attempt {})", job.track_id, job.attempts + 1);
+        }
+    }
+
+    let archive_index = db.archive_index();
+    let mut seen = HashSet::new();
+    let mut total_new = 0;
+
+    for user_id in &user_ids {
+        let tracks = match pipeline::fetch_user_tracks(&config, user_id).await {
+            Ok((tracks, _activity)) => tracks,
+            Err(e) => {
+                warn!("Failed to poll user {}: {}", user_id, e);
+                println!("\nUser {}: failed to fetch tracks: {}", user_id, e);
+                continue;
+            }
+        };
+
+        // Likes can bring the same track in through several users, it's archived once
+        let new_tracks: Vec<Track> = tracks.into_iter()
+            .filter(|track| db.is_new(&track.id) && seen.insert(track.id.clone()))
+            .collect();
+
+        if new_tracks.is_empty() {
+            debug!("No new tracks for user {}", user_id);
+            continue;
+        }
+
+        println!("\nUser {}: {} new track(s)", user_id, new_tracks.len());
+        for track in &new_tracks {
+            report_track(track, &config, &archive_index, download).await;
+        }
+        total_new += new_tracks.len();
+    }
+
+    println!("\nPolled {} user(s), {} new track(s) would be archived", user_ids.len(), total_new);
+    Ok(())
+}
+
+/// Print what posting a single track would send
+///
+/// Like `post`, this doesn't check whether the track is already archived.
+pub async fn dry_run_post(id_or_url: &str, download: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (config, db) = load_read_only()?;
+    initialize_soundcloud(&config).await?;
+
+    println!("Dry run: nothing is posted to Discord or written to {}", config.tracks_file);
+
+    let (track_id, secret_token) = soundcloud::resolve_track_input(id_or_url).await?;
+    let (track, prepared) = if download {
+        let prepared = soundcloud::prepare_track_with_secret(&track_id, secret_token.as_deref(), &config,
+                                                             Some(&db.archive_index()), true).await?;
+        (prepared.track.clone(), Some(prepared))
+    } else {
+        (soundcloud::get_track_details_with_secret(&track_id, secret_token.as_deref()).await?, None)
+    };
+
+    print_track(&track, &config);
+    match prepared {
+        Some(prepared) => print_prepared(prepared).await,
+        None => println!("    Attachments: not downloaded, use --download to see them"),
+    }
+
+    Ok(())
+}
+
+/// Load the config and the tracks database without writing either
+fn load_read_only() -> Result<(Config, TrackDatabase), Box<dyn std::error::Error + Send + Sync>> {
+    let config = Config::load(&Config::path())?;
+    update_log_level(&config.log_level);
+    Config::set_show_ffmpeg_output(config.show_ffmpeg_output);
+
+    let db = TrackDatabase::load_read_only(config.tracks_file.clone())?;
+    Ok((config, db))
+}
+
+async fn initialize_soundcloud(config: &Config) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    info!("Initializing SoundCloud client");
+    soundcloud::set_oauth_token(config.soundcloud_oauth_token.clone());
+    soundcloud::initialize().await?;
+    Ok(())
+}
+
+/// Print a new track, downloading it first if asked to
+async fn report_track(track: &Track, config: &Config, archive_index: &ArchiveIndex, download: bool) {
+    print_track(track, config);
+
+    if !download {
+        return;
+    }
+
+    match soundcloud::prepare_track_with_secret(&track.id, track.secret_token.as_deref(), config, Some(archive_index), true).await {
+        Ok(prepared) => print_prepared(prepared).await,
+        Err(e) => {
+            warn!("Failed to process track {}: {}", track.id, e);
+            println!("    Download failed, the job would be retried: {}", e);
+        }
+    }
+}
+
+/// Print a track and the formats that would be downloaded
+fn print_track(track: &Track, config: &Config) {
+    println!("  {} '{}' by {}", track.id, track.title, track.user.username);
+    println!("    {}", track.permalink_url);
+
+    let mut formats: Vec<String> = Vec::new();
+    if track.downloadable.unwrap_or(false) {
+        formats.push("original upload".to_string());
+    }
+    formats.extend(crate::audio::planned_formats(track, config).iter().map(|f| f.to_string()));
+
+    if formats.is_empty() {
+        println!("    Formats: none offered");
+    } else {
+        let selection = match &config.format_policy.selection {
+            FormatSelection::All => "all of".to_string(),
+            FormatSelection::Best { count } => format!("the first {} that download of", count),
+            FormatSelection::Preferred { .. } => "the first that downloads of".to_string(),
+        };
+        println!("    Formats: {} {}", selection, formats.join(", "));
+    }
+
+    if !config.transcode_profiles.is_empty() {
+        let names: Vec<&str> = config.transcode_profiles.iter().map(|p| p.name.as_str()).collect();
+        println!("    Transcodes: {}", names.join(", "));
+    }
+}
+
+/// Print the attachments and local exports of a prepared track, then delete its work directory
+async fn print_prepared(prepared: PreparedTrack) {
+    let plan = discord::plan_attachments(prepared.upload_files.clone());
+
+    if plan.attached.is_empty() {
+        println!("    Attachments: none, the embed is posted alone");
+    } else {
+        println!("    Attachments:");
+        for (_, file_name, size) in &plan.attached {
+            println!("      {} ({})", file_name, format_size(*size));
+        }
+    }
+
+    for (file_name, size, reason) in &plan.skipped {
+        println!("    Left out: {} ({}), {}", file_name, format_size(*size), reason);
+    }
+
+    for (file_name, archived) in &prepared.notes.duplicate_files {
+        println!("    Linked: {} is already on Discord with track {}", file_name, archived.track_id);
+    }
+
+    if let Some(found) = &prepared.notes.possible_reupload {
+        println!("    Possible re-upload of {} '{}' ({:.0}% similar)",
+                 found.track_id, found.record.title, found.similarity * 100.0);
+    }
+
+    for path in &prepared.local_exports {
+        println!("    Would export to {}", path);
+    }
+
+    prepared.cleanup().await;
+}
+
+/// File size in MB for the report
+fn format_size(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+}
@@ -0,0 +1,222 @@
+use std::path::Path;
+use std::process::Command;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use log::debug;
+use serde_json::Value;
+use tokio::process::Command as TokioCommand;
+
+/// Seconds of audio fingerprinted (fpcalc's default), enough to recognise a track
+const FINGERPRINT_LENGTH_SEC: u32 = 120;
+
+/// How far (in fingerprint items, ~8 per second) two tracks may be shifted against each other
+const MAX_ALIGNMENT_OFFSET: i64 = 80;
+
+/// Minimum number of overlapping items for a comparison to count
+const MIN_OVERLAP: usize = 40;
+
+/// Check if fpcalc is available, caching the result
+fn fpcalc_available() -> bool {
+    lazy_static::lazy_static! {
+        static ref FPCALC_AVAILABLE: bool = Command::new("fpcalc").arg("-version").output().is_ok();
+    }
+
+    *FPCALC_AVAILABLE
+}
+
+/// Compute a raw Chromaprint fingerprint of an audio file
+///
+/// Uses `fpcalc` when installed, otherwise ffmpeg's chromaprint muxer (only present
+/// in ffmpeg builds with `--enable-chromaprint`).
+pub async fn compute_fingerprint(path: &Path) -> Result<Vec<u32>, Box<dyn std::error::Error + Send + Sync>> {
+    let fingerprint = if fpcalc_available() {
+        fingerprint_with_fpcalc(path).await?
+    } else {
+        fingerprint_with_ffmpeg(path).await?
+    };
+
+    if fingerprint.len() < MIN_OVERLAP {
+        return Err(format!("Fingerprint too short ({} items)", fingerprint.len()).into());
+    }
+
+    debug!("Computed fingerprint of {} items for {}", fingerprint.len(), path.display());
+    Ok(fingerprint)
+}
+
+/// Run `fpcalc -raw -json` and read the fingerprint array
+async fn fingerprint_with_fpcalc(path: &Path) -> Result<Vec<u32>, Box<dyn std::error::Error + Send + Sync>> {
+    let output = TokioCommand::new("fpcalc")
+        .arg("-raw")
+        .arg("-json")
+        .arg("-length")
+        .arg(FINGERPRINT_LENGTH_SEC.to_string())
+        .arg(path)
+        .kill_on_drop(true)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(format!("fpcalc failed with exit code: {}", output.status).into());
+    }
+
+    let json: Value = serde_json::from_slice(&output.stdout)?;
+    let fingerprint = json.get("fingerprint")
+        .and_then(Value::as_array)
+        .ok_or("fpcalc output has no fingerprint")?
+        .iter()
+        .filter_map(Value::as_u64)
+        .map(|v| v as u32)
+        .collect();
+
+    Ok(fingerprint)
+}
+
+/// Run ffmpeg's chromaprint muxer and read the raw little-endian fingerprint
+async fn fingerprint_with_ffmpeg(path: &Path) -> Result<Vec<u32>, Box<dyn std::error::Error + Send + Sync>> {
+    let output = TokioCommand::new("ffmpeg")
+        .arg("-v")
+        .arg("error")
+        .arg("-i")
+        .arg(path)
+        .arg("-t")
+        .arg(FINGERPRINT_LENGTH_SEC.to_string())
+        .arg("-f")
+        .arg("chromaprint")
+        .arg("-fp_format")
+        .arg("raw")
+        .arg("-")
+        .kill_on_drop(true)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(format!("ffmpeg chromaprint failed with exit code: {} (needs fpcalc or ffmpeg with chromaprint)",
+                           output.status).into());
+    }
+
+    Ok(output.stdout
+        .chunks_exact(4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
+}
+
+/// Encode a fingerprint for storage in the database
+pub fn encode(fingerprint: &[u32]) -> String {
+    let bytes: Vec<u8> = fingerprint.iter().flat_map(|v| v.to_le_bytes()).collect();
+    BASE64.encode(bytes)
+}
+
+/// Decode a fingerprint stored with `encode`
+pub fn decode(encoded: &str) -> Option<Vec<u32>> {
+    let bytes = BASE64.decode(encoded).ok()?;
+    Some(bytes
+        .chunks_exact(4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
+}
+
+/// Similarity of two fingerprints between 0.0 and 1.0
+///
+/// The fingerprints are slid against each other to handle a trimmed or padded start, and
+/// the best alignment wins. Unrelated audio scores around 0.5, the same recording re-encoded
+/// usually scores above 0.9.
+pub fn similarity(a: &[u32], b: &[u32]) -> f64 {
+    let mut best = 0.0f64;
+
+    for offset in -MAX_ALIGNMENT_OFFSET..=MAX_ALIGNMENT_OFFSET {
+        let (a_start, b_start) = if offset >= 0 {
+            (offset as usize, 0)
+        } else {
+            (0, (-offset) as usize)
+        };
+
+        if a_start >= a.len() || b_start >= b.len() {
+            continue;
+        }
+
+        let overlap = std::cmp::min(a.len() - a_start, b.len() - b_start);
+        if overlap < MIN_OVERLAP {
+            continue;
+        }
+
+        let differing_bits: u32 = a[a_start..a_start + overlap].iter()
+            .zip(&b[b_start..b_start + overlap])
+            .map(|(x, y)| (x ^ y).count_ones())
+            .sum();
+
+        let score = 1.0 - differing_bits as f64 / (overlap as f64 * 32.0);
+        if score > best {
+            best = score;
+        }
+    }
+
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic pseudo-random fingerprint items
+    fn fingerprint(seed: u32, len: usize) -> Vec<u32> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                state
+            })
+            .collect()
+    }
+
+    #[test]
+    fn encode_and_decode_round_trip() {
+        let original = fingerprint(1, 100);
+        assert_eq!(decode(&encode(&original)), Some(original));
+        assert_eq!(decode(&encode(&[])), Some(Vec::new()));
+    }
+
+    #[test]
+    fn decode_rejects_invalid_base64() {
+        assert_eq!(decode("not base64!"), None);
+    }
+
+    #[test]
+    fn identical_fingerprints_are_fully_similar() {
+        let a = fingerprint(1, 200);
+        assert_eq!(similarity(&a, &a), 1.0);
+    }
+
+    #[test]
+    fn unrelated_fingerprints_score_around_half() {
+        let score = similarity(&fingerprint(1, 200), &fingerprint(2, 200));
+        assert!(score > 0.4 && score < 0.65, "{}", score);
+    }
+
+    #[test]
+    fn shifted_fingerprints_are_aligned() {
+        let a = fingerprint(1, 300);
+        // The same audio with 2 seconds of silence in front, and with the first 3 seconds trimmed
+        let padded: Vec<u32> = std::iter::repeat_n(0, 16).chain(a.iter().copied()).collect();
+        let trimmed = a[24..].to_vec();
+
+        assert_eq!(similarity(&a, &padded), 1.0);
+        assert_eq!(similarity(&a, &trimmed), 1.0);
+    }
+
+    #[test]
+    fn flipped_bits_lower_the_score() {
+        let a = fingerprint(1, 200);
+        // One bit of 32 differs in every item
+        let b: Vec<u32> = a.iter().map(|v| v ^ 1).collect();
+
+        assert!((similarity(&a, &b) - 31.0 / 32.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn short_overlaps_do_not_count() {
+        let a = fingerprint(1, 200);
+
+        assert_eq!(similarity(&a[..MIN_OVERLAP - 1], &a[..MIN_OVERLAP - 1]), 0.0);
+        assert_eq!(similarity(&a, &[]), 0.0);
+    }
+}
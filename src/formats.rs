@@ -0,0 +1,478 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// How an audio format is delivered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Hls,
+    Progressive,
+    /// The file the uploader provided, downloaded as-is
+    Original,
+    /// Made locally by ffmpeg
+    Transcoded,
+    Other,
+}
+
+/// Audio codec of a format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Codec {
+    Flac,
+    Opus,
+    Vorbis,
+    Aac,
+    Mp3,
+    /// Uncompressed WAV/AIFF
+    Pcm,
+    Unknown,
+}
+
+/// SoundCloud's quality tier of a transcoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quality {
+    /// Go+ high quality, only with an OAuth token
+    Hq,
+    Sq,
+}
+
+/// An audio format of a track
+///
+/// Formats are identified by a "protocol/mime_type/quality" string (e.g.
+/// `hls/audio/mpeg/sq`) in file names, the manifest and verification, and
+/// `parse` reads those strings back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Format {
+    pub protocol: Protocol,
+    /// MIME type as SoundCloud reports it, or the file extension for originals
+    pub mime_type: String,
+    pub codec: Codec,
+    pub quality: Quality,
+}
+
+impl Format {
+    /// Build a format from one entry of a track's `media.transcodings`
+    pub fn from_transcoding(transcoding: &Value) -> Format {
+        let format = transcoding.get("format");
+
+        let protocol = format
+            .and_then(|f| f.get("protocol"))
+            .and_then(Value::as_str)
+            .unwrap_or("unknown");
+
+        let mime_type = format
+            .and_then(|f| f.get("mime_type"))
+            .and_then(Value::as_str)
+            .unwrap_or("unknown");
+
+        let quality = transcoding.get("quality")
+            .and_then(Value::as_str)
+            .unwrap_or("sq");
+
+        Format {
+            protocol: parse_protocol(protocol),
+            mime_type: mime_type.to_string(),
+            codec: codec_from_mime(mime_type),
+            quality: if quality == "hq" { Quality::Hq } else { Quality::Sq },
+        }
+    }
+
+    /// Read a format string as produced by `Display`, or one of the fixed
+    /// strings like "original/wav" and "transcoded/mp3"
+    pub fn parse(format_info: &str) -> Format {
+        let (protocol, rest) = format_info.split_once('/').unwrap_or((format_info, ""));
+        let protocol = parse_protocol(protocol);
+
+        let (mime_type, quality) = match rest.rsplit_once('/') {
+            Some((mime, "hq")) => (mime, Quality::Hq),
+            Some((mime, "sq")) => (mime, Quality::Sq),
+            _ => (rest, Quality::Sq),
+        };
+
+        let codec = if protocol == Protocol::Original {
+            codec_from_extension(mime_type)
+        } else {
+            codec_from_mime(mime_type)
+        };
+
+        Format {
+            protocol,
+            mime_type: mime_type.to_string(),
+            codec,
+            quality,
+        }
+    }
+
+    /// File extension for a download of this format
+    pub fn extension(&self) -> &str {
+        match self.codec {
+            Codec::Mp3 => "mp3",
+            Codec::Opus => "opus",
+            Codec::Vorbis => "ogg",
+            Codec::Aac => "m4a",
+            Codec::Flac => "flac",
+            Codec::Pcm => "wav",
+            // HLS usually contains AAC in an MP4 container
+            Codec::Unknown if self.protocol == Protocol::Hls => "m4a",
+            Codec::Unknown => "audio",
+        }
+    }
+
+    /// Sort key for formats (lower number = higher priority)
+    pub fn priority(&self) -> i32 {
+        if self.protocol == Protocol::Original {
+            // The untouched upload always beats any transcoding
+            return 0;
+        }
+
+        match self.quality {
+            Quality::Hq => match self.codec {
+                Codec::Flac => 1,  // FLAC HQ (rare)
+                Codec::Opus => 2,  // Opus HQ
+                Codec::Mp3 => 3,   // MP3 HQ
+                Codec::Aac => 4,   // AAC HQ
+                _ => 5,            // Other HQ
+            },
+            Quality::Sq => match (self.protocol, self.codec) {
+                (Protocol::Transcoded, _) => 50,     // Fallback transcoded files (lowest priority)
+                (Protocol::Progressive, Codec::Mp3) => 10, // Progressive MP3 (common format)
+                (_, Codec::Opus) => 11,              // Opus standard quality
+                (_, Codec::Mp3) => 12,               // MP3 standard quality
+                (_, Codec::Aac) => 13,               // AAC standard quality
+                (Protocol::Hls, _) => 15,            // HLS (can contain various formats, often AAC)
+                _ => 20,                             // Other formats
+            },
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let protocol = match self.protocol {
+            Protocol::Hls => "hls",
+            Protocol::Progressive => "progressive",
+            Protocol::Original => "original",
+            Protocol::Transcoded => "transcoded",
+            Protocol::Other => "unknown",
+        };
+
+        let quality = match self.quality {
+            Quality::Hq => "hq",
+            Quality::Sq => "sq",
+        };
+
+        // Only SoundCloud transcodings have a quality tier
+        match self.protocol {
+            Protocol::Original | Protocol::Transcoded => write!(f, "{}/{}", protocol, self.mime_type),
+            _ => write!(f, "{}/{}/{}", protocol, self.mime_type, quality),
+        }
+    }
+}
+
+fn parse_protocol(protocol: &str) -> Protocol {
+    match protocol {
+        "hls" => Protocol::Hls,
+        "progressive" => Protocol::Progressive,
+        "original" => Protocol::Original,
+        "transcoded" => Protocol::Transcoded,
+        _ => Protocol::Other,
+    }
+}
+
+/// Work out the codec from a MIME type like `audio/ogg; codecs="opus"`
+fn codec_from_mime(mime_type: &str) -> Codec {
+    let mime = mime_type.to_lowercase();
+
+    if mime.contains("opus") {
+        Codec::Opus
+    } else if mime.contains("audio/ogg") || mime.contains("vorbis") {
+        Codec::Vorbis
+    } else if mime.contains("audio/mpeg") || mime == "mp3" {
+        Codec::Mp3
+    } else if mime.contains("audio/mp4") || mime.contains("aac") || mime.contains("mp4a") {
+        Codec::Aac
+    } else if mime.contains("flac") {
+        Codec::Flac
+    } else if mime.contains("wav") || mime.contains("aiff") {
+        Codec::Pcm
+    } else {
+        Codec::Unknown
+    }
+}
+
+/// Work out the codec of an original upload from its file extension
+fn codec_from_extension(extension: &str) -> Codec {
+    match extension.to_lowercase().as_str() {
+        "wav" | "aif" | "aiff" => Codec::Pcm,
+        "flac" => Codec::Flac,
+        "mp3" => Codec::Mp3,
+        "m4a" | "mp4" | "aac" => Codec::Aac,
+        "ogg" | "oga" => Codec::Vorbis,
+        "opus" => Codec::Opus,
+        _ => Codec::Unknown,
+    }
+}
+
+/// Which of a track's transcodings get downloaded
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum FormatSelection {
+    /// Every transcoding SoundCloud offers
+    All,
+    /// The `count` best transcodings that download successfully
+    Best { count: usize },
+    /// The first codec of the list that downloads successfully, later codecs are fallbacks
+    Preferred { codecs: Vec<Codec> },
+}
+
+/// Format selection policy from the config
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "FormatPolicyFields")]
+pub struct FormatPolicy {
+    #[serde(flatten)]
+    pub selection: FormatSelection,
+    /// Whether to transcode the stream to MP3 when no format downloads
+    #[serde(default = "default_allow_mp3_fallback")]
+    pub allow_mp3_fallback: bool,
+}
+
+fn default_allow_mp3_fallback() -> bool {
+    true
+}
+
+/// `format_policy` as written in the config
+///
+/// `deny_unknown_fields` doesn't work through `flatten`, so the policy is read
+/// from these fields and checked in `TryFrom`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FormatPolicyFields {
+    mode: FormatMode,
+    count: Option<usize>,
+    codecs: Option<Vec<Codec>>,
+    #[serde(default = "default_allow_mp3_fallback")]
+    allow_mp3_fallback: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum FormatMode {
+    All,
+    Best,
+    Preferred,
+}
+
+impl TryFrom<FormatPolicyFields> for FormatPolicy {
+    type Error = String;
+
+    fn try_from(fields: FormatPolicyFields) -> Result<Self, Self::Error> {
+        let selection = match (fields.mode, fields.count, fields.codecs) {
+            (FormatMode::All, None, None) => FormatSelection::All,
+            (FormatMode::Best, Some(count), None) => FormatSelection::Best { count },
+            (FormatMode::Preferred, None, Some(codecs)) => FormatSelection::Preferred { codecs },
+            (FormatMode::Best, None, _) => return Err("mode \"best\" needs a count".to_string()),
+            (FormatMode::Preferred, _, None) => return Err("mode \"preferred\" needs codecs".to_string()),
+            (_, Some(_), _) => return Err("count only applies to mode \"best\"".to_string()),
+            (_, _, Some(_)) => return Err("codecs only applies to mode \"preferred\"".to_string()),
+        };
+
+        Ok(FormatPolicy {
+            selection,
+            allow_mp3_fallback: fields.allow_mp3_fallback,
+        })
+    }
+}
+
+impl Default for FormatPolicy {
+    fn default() -> Self {
+        FormatPolicy {
+            selection: FormatSelection::All,
+            allow_mp3_fallback: default_allow_mp3_fallback(),
+        }
+    }
+}
+
+impl FormatPolicy {
+    /// Order the available formats in the order they should be tried, dropping
+    /// the ones the policy never wants
+    pub fn candidates<T>(&self, mut formats: Vec<(Format, T)>) -> Vec<(Format, T)> {
+        formats.sort_by_key(|(format, _)| format.priority());
+
+        if let FormatSelection::Preferred { codecs } = &self.selection {
+            formats.retain(|(format, _)| codecs.contains(&format.codec));
+            // Stable sort, so the priority order is kept within a codec
+            formats.sort_by_key(|(format, _)| codecs.iter().position(|c| *c == format.codec));
+        }
+
+        formats
+    }
+
+    /// How many successful downloads are enough, `None` for all candidates
+    pub fn wanted(&self) -> Option<usize> {
+        match &self.selection {
+            FormatSelection::All => None,
+            FormatSelection::Best { count } => Some(*count),
+            FormatSelection::Preferred { .. } => Some(1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn transcoding(protocol: &str, mime_type: &str, quality: &str) -> Value {
+        json!({
+            "url": "https://api-v2.soundcloud.com/media/soundcloud:tracks:1/stream",
+            "quality": quality,
+            "format": { "protocol": protocol, "mime_type": mime_type }
+        })
+    }
+
+    fn formats(infos: &[&str]) -> Vec<(Format, ())> {
+        infos.iter().map(|info| (Format::parse(info), ())).collect()
+    }
+
+    fn names(formats: &[(Format, ())]) -> Vec<String> {
+        formats.iter().map(|(format, _)| format.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_reads_back_what_display_writes() {
+        let transcodings = [
+            transcoding("hls", "audio/mpeg", "sq"),
+            transcoding("progressive", "audio/mpeg", "sq"),
+            transcoding("hls", "audio/ogg; codecs=\"opus\"", "sq"),
+            transcoding("hls", "audio/mp4; codecs=\"mp4a.40.2\"", "hq"),
+        ];
+
+        for transcoding in &transcodings {
+            let format = Format::from_transcoding(transcoding);
+            assert_eq!(Format::parse(&format.to_string()), format);
+        }
+
+        for info in ["original/wav", "original/flac", "transcoded/mp3", "hls/audio/mpeg/hq"] {
+            assert_eq!(Format::parse(info).to_string(), info);
+        }
+    }
+
+    #[test]
+    fn codecs_come_from_the_mime_type_or_the_original_extension() {
+        let opus = Format::from_transcoding(&transcoding("hls", "audio/ogg; codecs=\"opus\"", "sq"));
+        assert_eq!(opus.codec, Codec::Opus);
+        assert_eq!(opus.extension(), "opus");
+
+        let aac = Format::from_transcoding(&transcoding("hls", "audio/mp4; codecs=\"mp4a.40.2\"", "hq"));
+        assert_eq!((aac.codec, aac.quality), (Codec::Aac, Quality::Hq));
+
+        assert_eq!(Format::parse("original/aiff").codec, Codec::Pcm);
+        assert_eq!(Format::parse("transcoded/mp3").codec, Codec::Mp3);
+    }
+
+    #[test]
+    fn unknown_mime_types_are_kept_but_have_no_codec() {
+        let format = Format::from_transcoding(&transcoding("hls", "audio/x-something", "sq"));
+        assert_eq!(format.codec, Codec::Unknown);
+        assert_eq!(format.mime_type, "audio/x-something");
+        assert_eq!(format.extension(), "m4a");
+        assert_eq!(format.priority(), 15);
+
+        let format = Format::from_transcoding(&json!({ "url": "https://example.com" }));
+        assert_eq!(format.protocol, Protocol::Other);
+        assert_eq!(format.extension(), "audio");
+        assert_eq!(Format::parse("original/xyz").codec, Codec::Unknown);
+    }
+
+    #[test]
+    fn originals_beat_hq_which_beats_sq_and_local_transcodes_come_last() {
+        let order = ["original/wav", "hls/audio/mp4/hq", "progressive/audio/mpeg/sq", "hls/audio/mpeg/sq", "transcoded/mp3"];
+        let priorities: Vec<i32> = order.iter().map(|info| Format::parse(info).priority()).collect();
+
+        let mut sorted = priorities.clone();
+        sorted.sort();
+        assert_eq!(priorities, sorted);
+    }
+
+    #[test]
+    fn best_policy_tries_formats_by_priority() {
+        let policy = FormatPolicy {
+            selection: FormatSelection::Best { count: 2 },
+            allow_mp3_fallback: true,
+        };
+        let candidates = policy.candidates(formats(&["hls/audio/mpeg/sq", "hls/audio/mp4/hq", "progressive/audio/mpeg/sq"]));
+
+        assert_eq!(names(&candidates), ["hls/audio/mp4/hq", "progressive/audio/mpeg/sq", "hls/audio/mpeg/sq"]);
+        assert_eq!(policy.wanted(), Some(2));
+    }
+
+    #[test]
+    fn preferred_policy_orders_by_codec_and_drops_the_rest() {
+        let policy = FormatPolicy {
+            selection: FormatSelection::Preferred { codecs: vec![Codec::Opus, Codec::Mp3] },
+            allow_mp3_fallback: false,
+        };
+        let candidates = policy.candidates(formats(&[
+            "hls/audio/mpeg/sq",
+            "hls/audio/mp4/hq",
+            "hls/audio/ogg; codecs=\"opus\"/sq",
+            "progressive/audio/mpeg/sq",
+        ]));
+
+        // Within a codec the priority order is kept
+        assert_eq!(names(&candidates), ["hls/audio/ogg; codecs=\"opus\"/sq", "progressive/audio/mpeg/sq", "hls/audio/mpeg/sq"]);
+        assert_eq!(policy.wanted(), Some(1));
+    }
+
+    #[test]
+    fn all_policy_wants_every_format() {
+        let policy = FormatPolicy::default();
+        let candidates = policy.candidates(formats(&["hls/audio/mpeg/sq", "hls/x-unknown/sq"]));
+
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(policy.wanted(), None);
+    }
+
+    #[test]
+    fn policy_reads_the_modes_from_the_config() {
+        let policy: FormatPolicy = serde_json::from_value(json!({ "mode": "best", "count": 3 })).unwrap();
+        assert_eq!(policy.selection, FormatSelection::Best { count: 3 });
+        assert!(policy.allow_mp3_fallback);
+
+        let policy: FormatPolicy = serde_json::from_value(json!({
+            "mode": "preferred",
+            "codecs": ["flac", "opus"],
+            "allow_mp3_fallback": false
+        })).unwrap();
+        assert_eq!(policy.selection, FormatSelection::Preferred { codecs: vec![Codec::Flac, Codec::Opus] });
+        assert!(!policy.allow_mp3_fallback);
+    }
+
+    #[test]
+    fn policy_rejects_unknown_and_misplaced_keys() {
+        let error = serde_json::from_value::<FormatPolicy>(json!({ "mode": "all", "bogus": 1 })).unwrap_err();
+        assert!(error.to_string().contains("unknown field `bogus`"), "{}", error);
+
+        for policy in [
+            json!({ "mode": "all", "count": 2 }),
+            json!({ "mode": "best" }),
+            json!({ "mode": "best", "count": 2, "codecs": ["opus"] }),
+            json!({ "mode": "preferred" }),
+            json!({ "mode": "fastest" }),
+        ] {
+            assert!(serde_json::from_value::<FormatPolicy>(policy.clone()).is_err(), "{} was accepted", policy);
+        }
+    }
+
+    #[test]
+    fn policy_serializes_to_what_it_reads() {
+        let policy = FormatPolicy {
+            selection: FormatSelection::Best { count: 2 },
+            allow_mp3_fallback: false,
+        };
+        let value = serde_json::to_value(&policy).unwrap();
+
+        assert_eq!(value, json!({ "mode": "best", "count": 2, "allow_mp3_fallback": false }));
+        assert_eq!(serde_json::from_value::<FormatPolicy>(value).unwrap(), policy);
+    }
+}
@@ -0,0 +1,63 @@
+use std::path::Path;
+use log::{debug, warn};
+use sha2::{Digest, Sha256};
+use tokio::fs::File as TokioFile;
+use tokio::io::AsyncReadExt;
+
+/// SHA-256 hash of a file produced while archiving a track
+#[derive(Debug, Clone)]
+pub struct FileHash {
+    /// Path of the file in the work directory
+    pub path: String,
+    /// File name as it is posted to Discord
+    pub file_name: String,
+    /// Lowercase hex SHA-256 of the file contents
+    pub sha256: String,
+    pub size: u64,
+}
+
+/// Compute the SHA-256 of a file, reading it in chunks
+pub async fn sha256_file(path: &Path) -> Result<(String, u64), Box<dyn std::error::Error + Send + Sync>> {
+    let mut file = TokioFile::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut size = 0u64;
+
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+
+    let hex = hasher.finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+
+    Ok((hex, size))
+}
+
+/// Hash every (file_path, file_name) pair, skipping files that can't be read
+pub async fn hash_files(files: &[(String, String)]) -> Vec<FileHash> {
+    let mut hashes = Vec::new();
+
+    for (path, file_name) in files {
+        match sha256_file(Path::new(path)).await {
+            Ok((sha256, size)) => {
+                debug!("SHA-256 of {}: {}", file_name, sha256);
+                hashes.push(FileHash {
+                    path: path.clone(),
+                    file_name: file_name.clone(),
+                    sha256,
+                    size,
+                });
+            },
+            Err(e) => warn!("Failed to hash {}: {}", path, e),
+        }
+    }
+
+    hashes
+}
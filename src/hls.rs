@@ -0,0 +1,622 @@
+use std::path::Path;
+use std::time::Duration;
+use log::{info, warn, debug};
+use reqwest::{Client, Url};
+use tokio::fs::File as TokioFile;
+use tokio::io::AsyncWriteExt;
+use tokio::time::sleep;
+
+/// Number of HLS segments downloaded at the same time
+const SEGMENT_PARALLELISM: usize = 8;
+
+/// Number of attempts per segment before the whole download fails
+const SEGMENT_MAX_RETRIES: u32 = 3;
+
+lazy_static::lazy_static! {
+    static ref HTTP_CLIENT: Client = Client::builder()
+        .timeout(Duration::from_secs(30))
+        .pool_max_idle_per_host(SEGMENT_PARALLELISM)
+        .build()
+        .unwrap();
+}
+
+/// A single media segment (or the init section) of an HLS playlist
+#[derive(Debug, Clone)]
+struct Segment {
+    url: Url,
+    /// Optional (length, offset) from #EXT-X-BYTERANGE
+    byte_range: Option<(u64, u64)>,
+}
+
+/// Parsed HLS media playlist
+#[derive(Debug, Default)]
+struct MediaPlaylist {
+    /// Init section from #EXT-X-MAP (fMP4 streams)
+    init_segment: Option<Segment>,
+    segments: Vec<Segment>,
+}
+
+/// Check whether a resolved stream URL points to an HLS playlist
+pub fn is_hls_url(url: &str) -> bool {
+    let path = url.split('?').next().unwrap_or(url);
+    path.ends_with(".m3u8") || path.ends_with(".m3u")
+}
+
+/// Download an HLS stream natively, without ffmpeg
+///
+/// Fetches the playlist (following a master playlist to its best variant), downloads
+/// the segments concurrently with retries and writes them in order to `output_path`.
+/// MP3, Ogg/Opus and ADTS segments concatenate into a playable file, and fMP4 streams
+/// become a fragmented MP4 by writing the #EXT-X-MAP init section first. MPEG-TS
+/// segments are rejected unless the output is a .ts file, since they need a remux.
+/// On error the partial output file is removed.
+pub async fn download_hls(
+    playlist_url: &str,
+    output_path: &Path
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let result = download_hls_to_file(playlist_url, output_path).await;
+    
+    if result.is_err() && output_path.exists() {
+        debug!("Removing partial HLS output {}", output_path.display());
+        if let Err(e) = tokio::fs::remove_file(output_path).await {
+            warn!("Failed to remove partial HLS output {}: {}", output_path.display(), e);
+        }
+    }
+    
+    result
+}
+
+/// Download the stream into `output_path`, leaving cleanup on error to the caller
+async fn download_hls_to_file(
+    playlist_url: &str,
+    output_path: &Path
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let playlist_url = Url::parse(playlist_url)?;
+    let playlist = fetch_media_playlist(&playlist_url).await?;
+    
+    if playlist.segments.is_empty() {
+        return Err("HLS playlist contains no segments".into());
+    }
+    
+    info!("Downloading {} HLS segments to {}", playlist.segments.len(), output_path.display());
+    
+    let mut file = TokioFile::create(output_path).await?;
+    let mut total_bytes = 0u64;
+    
+    // fMP4 streams need their init section in front of the first fragment
+    if let Some(init) = &playlist.init_segment {
+        debug!("Downloading HLS init section");
+        let data = fetch_segment_with_retry(init).await?;
+        total_bytes += data.len() as u64;
+        file.write_all(&data).await?;
+    }
+    
+    // Download in batches so only a bounded number of segments is held in memory
+    let segment_count = playlist.segments.len();
+    let mut first_segment_checked = false;
+    
+    for (batch_index, batch) in playlist.segments.chunks(SEGMENT_PARALLELISM).enumerate() {
+        let mut tasks = Vec::new();
+        
+        for segment in batch {
+            let segment = segment.clone();
+            tasks.push(tokio::spawn(async move {
+                fetch_segment_with_retry(&segment).await
+            }));
+        }
+        
+        // Write the batch in playlist order
+        for (i, task) in tasks.into_iter().enumerate() {
+            let index = batch_index * SEGMENT_PARALLELISM + i;
+            let data = match task.await {
+                Ok(Ok(data)) => data,
+                Ok(Err(e)) => {
+                    return Err(format!("Failed to download HLS segment {}/{}: {}", index + 1, segment_count, e).into());
+                },
+                Err(e) => {
+                    return Err(format!("HLS segment task {} failed: {}", index + 1, e).into());
+                }
+            };
+            
+            if !first_segment_checked {
+                first_segment_checked = true;
+                // 0x47 is the MPEG-TS sync byte; those segments can't simply go into an .m4a
+                if playlist.init_segment.is_none() && data.first() == Some(&0x47) && !is_ts_output(output_path) {
+                    return Err(format!("HLS stream uses MPEG-TS segments, which need ffmpeg to remux into {}",
+                                       output_path.display()).into());
+                }
+            }
+            
+            total_bytes += data.len() as u64;
+            file.write_all(&data).await?;
+        }
+        
+        debug!("Wrote HLS segments {}-{} of {}",
+               batch_index * SEGMENT_PARALLELISM + 1,
+               std::cmp::min((batch_index + 1) * SEGMENT_PARALLELISM, segment_count),
+               segment_count);
+    }
+    
+    file.flush().await?;
+    
+    debug!("HLS download completed: {} segments, {} bytes", segment_count, total_bytes);
+    Ok(())
+}
+
+/// Check whether the output is a transport stream file, the only place raw TS segments fit
+fn is_ts_output(output_path: &Path) -> bool {
+    output_path.extension()
+        .map(|e| e.eq_ignore_ascii_case("ts"))
+        .unwrap_or(false)
+}
+
+/// Fetch a playlist and return its media playlist, following a master playlist if needed
+async fn fetch_media_playlist(
+    playlist_url: &Url
+) -> Result<MediaPlaylist, Box<dyn std::error::Error + Send + Sync>> {
+    let body = fetch_text(playlist_url).await?;
+    
+    if !body.trim_start().starts_with("#EXTM3U") {
+        return Err("Response is not an HLS playlist (missing #EXTM3U)".into());
+    }
+    
+    if body.contains("#EXT-X-STREAM-INF") {
+        // Master playlist: pick the variant with the highest bandwidth
+        let variant_url = select_best_variant(&body, playlist_url)?;
+        debug!("HLS master playlist found, following best variant");
+        let variant_body = fetch_text(&variant_url).await?;
+        return parse_media_playlist(&variant_body, &variant_url);
+    }
+    
+    parse_media_playlist(&body, playlist_url)
+}
+
+/// Pick the variant stream with the highest BANDWIDTH from a master playlist
+fn select_best_variant(
+    body: &str,
+    base_url: &Url
+) -> Result<Url, Box<dyn std::error::Error + Send + Sync>> {
+    let mut best: Option<(u64, Url)> = None;
+    let mut pending_bandwidth: Option<u64> = None;
+    
+    for line in body.lines().map(str::trim) {
+        if let Some(attributes) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+            let bandwidth = parse_attribute(attributes, "BANDWIDTH")
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(0);
+            pending_bandwidth = Some(bandwidth);
+        } else if !line.is_empty() && !line.starts_with('#') {
+            if let Some(bandwidth) = pending_bandwidth.take() {
+                let url = base_url.join(line)?;
+                if best.as_ref().map(|(b, _)| bandwidth > *b).unwrap_or(true) {
+                    best = Some((bandwidth, url));
+                }
+            }
+        }
+    }
+    
+    match best {
+        Some((_, url)) => Ok(url),
+        None => Err("HLS master playlist contains no variants".into()),
+    }
+}
+
+/// Parse a media playlist into its init section and segments
+fn parse_media_playlist(
+    body: &str,
+    base_url: &Url
+) -> Result<MediaPlaylist, Box<dyn std::error::Error + Send + Sync>> {
+    let mut playlist = MediaPlaylist::default();
+    let mut pending_range: Option<(u64, Option<u64>)> = None;
+    // End of the previous byte range, used when a range has no explicit offset
+    let mut next_offset = 0u64;
+    
+    for line in body.lines().map(str::trim) {
+        if line.is_empty() {
+            continue;
+        }
+        
+        if let Some(attributes) = line.strip_prefix("#EXT-X-KEY:") {
+            let method = parse_attribute(attributes, "METHOD").unwrap_or_default();
+            if method != "NONE" {
+                // Encrypted streams need the key handling only ffmpeg has
+                return Err(format!("Encrypted HLS streams are not supported (METHOD={})", method).into());
+            }
+        } else if let Some(attributes) = line.strip_prefix("#EXT-X-MAP:") {
+            let uri = parse_attribute(attributes, "URI")
+                .ok_or("#EXT-X-MAP without URI")?;
+            let byte_range = parse_attribute(attributes, "BYTERANGE")
+                .and_then(|r| parse_byte_range(&r))
+                .map(|(length, offset)| (length, offset.unwrap_or(0)));
+            playlist.init_segment = Some(Segment {
+                url: base_url.join(&uri)?,
+                byte_range,
+            });
+        } else if let Some(range) = line.strip_prefix("#EXT-X-BYTERANGE:") {
+            pending_range = parse_byte_range(range);
+        } else if !line.starts_with('#') {
+            let byte_range = pending_range.take().map(|(length, offset)| {
+                let offset = offset.unwrap_or(next_offset);
+                next_offset = offset + length;
+                (length, offset)
+            });
+            playlist.segments.push(Segment {
+                url: base_url.join(line)?,
+                byte_range,
+            });
+        }
+    }
+    
+    debug!("Parsed HLS media playlist: {} segments, init section: {}",
+           playlist.segments.len(), playlist.init_segment.is_some());
+    Ok(playlist)
+}
+
+/// Parse an attribute value from an HLS attribute list (quotes are removed)
+fn parse_attribute(attributes: &str, name: &str) -> Option<String> {
+    let mut in_quotes = false;
+    let mut start = 0;
+    let mut parts = Vec::new();
+    
+    // Split on commas that are not inside quoted strings
+    for (i, c) in attributes.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                parts.push(&attributes[start..i]);
+                start = i + 1;
+            },
+            _ => {}
+        }
+    }
+    parts.push(&attributes[start..]);
+    
+    parts.into_iter()
+        .filter_map(|part| part.split_once('='))
+        .find(|(key, _)| key.trim() == name)
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+}
+
+/// Parse "<length>[@<offset>]" from #EXT-X-BYTERANGE
+fn parse_byte_range(range: &str) -> Option<(u64, Option<u64>)> {
+    let range = range.trim().trim_matches('"');
+    match range.split_once('@') {
+        Some((length, offset)) => Some((length.parse().ok()?, Some(offset.parse().ok()?))),
+        None => Some((range.parse().ok()?, None)),
+    }
+}
+
+/// Fetch a text resource (playlist)
+async fn fetch_text(url: &Url) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let response = HTTP_CLIENT.get(url.clone())
+        .send()
+        .await
+        .map_err(|e| e.without_url())?;
+    
+    if !response.status().is_success() {
+        return Err(format!("HTTP error {} when fetching HLS playlist", response.status()).into());
+    }
+    
+    Ok(response.text().await.map_err(|e| e.without_url())?)
+}
+
+/// Download a segment, retrying with a growing delay
+async fn fetch_segment_with_retry(
+    segment: &Segment
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut last_error: Option<Box<dyn std::error::Error + Send + Sync>> = None;
+    
+    for retry in 0..SEGMENT_MAX_RETRIES {
+        if retry > 0 {
+            debug!("Retrying HLS segment (attempt {}/{})", retry + 1, SEGMENT_MAX_RETRIES);
+            sleep(Duration::from_secs(2 * retry as u64)).await;
+        }
+        
+        match fetch_segment(segment).await {
+            Ok(data) => return Ok(data),
+            Err(e) => {
+                debug!("HLS segment download failed: {}", e);
+                last_error = Some(e);
+            }
+        }
+    }
+    
+    Err(last_error.unwrap_or_else(|| "HLS segment download failed".into()))
+}
+
+/// Download a single segment
+async fn fetch_segment(segment: &Segment) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut request = HTTP_CLIENT.get(segment.url.clone());
+    
+    if let Some((length, offset)) = segment.byte_range {
+        request = request.header(
+            reqwest::header::RANGE,
+            format!("bytes={}-{}", offset, offset + length.saturating_sub(1))
+        );
+    }
+    
+    let response = request.send().await.map_err(|e| e.without_url())?;
+    
+    if !response.status().is_success() {
+        return Err(format!("HTTP error {}", response.status()).into());
+    }
+    
+    // A server that ignores the Range header sends the whole file, which would corrupt the output
+    if segment.byte_range.is_some() && response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+        return Err(format!("Expected HTTP 206 for byte range request, got {}", response.status()).into());
+    }
+    
+    let data = response.bytes().await.map_err(|e| e.without_url())?;
+    if data.is_empty() {
+        return Err("Empty HLS segment".into());
+    }
+    
+    if let Some((length, _)) = segment.byte_range {
+        if data.len() as u64 != length {
+            return Err(format!("Byte range returned {} bytes, expected {}", data.len(), length).into());
+        }
+    }
+    
+    Ok(data.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+    
+    /// A file served by the fixture server
+    struct Route {
+        body: Vec<u8>,
+        /// Number of requests answered with HTTP 500 before the body is served
+        failures: u32,
+    }
+    
+    /// Minimal HTTP/1.1 server serving fixed files, with optional Range support
+    struct FixtureServer {
+        base_url: String,
+        hits: Arc<Mutex<HashMap<String, u32>>>,
+    }
+    
+    impl FixtureServer {
+        async fn start(routes: Vec<(&str, Vec<u8>, u32)>, honour_range: bool) -> FixtureServer {
+            let routes: Arc<HashMap<String, Route>> = Arc::new(routes.into_iter()
+                .map(|(path, body, failures)| (path.to_string(), Route { body, failures }))
+                .collect());
+            let hits = Arc::new(Mutex::new(HashMap::new()));
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let base_url = format!("http://{}", listener.local_addr().unwrap());
+            
+            let server_hits = hits.clone();
+            tokio::spawn(async move {
+                loop {
+                    let (socket, _) = match listener.accept().await {
+                        Ok(conn) => conn,
+                        Err(_) => return,
+                    };
+                    let routes = routes.clone();
+                    let hits = server_hits.clone();
+                    tokio::spawn(async move {
+                        handle_connection(socket, &routes, &hits, honour_range).await;
+                    });
+                }
+            });
+            
+            FixtureServer { base_url, hits }
+        }
+        
+        fn url(&self, path: &str) -> String {
+            format!("{}/{}", self.base_url, path)
+        }
+        
+        fn hits(&self, path: &str) -> u32 {
+            self.hits.lock().unwrap().get(path).copied().unwrap_or(0)
+        }
+    }
+    
+    async fn handle_connection(
+        mut socket: tokio::net::TcpStream,
+        routes: &HashMap<String, Route>,
+        hits: &Mutex<HashMap<String, u32>>,
+        honour_range: bool
+    ) {
+        // Requests carry no body, so reading up to the blank line is enough
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+            match socket.read(&mut buf).await {
+                Ok(0) | Err(_) => return,
+                Ok(n) => request.extend_from_slice(&buf[..n]),
+            }
+        }
+        let request = String::from_utf8_lossy(&request).to_string();
+        
+        let path = request.split_whitespace().nth(1).unwrap_or("/").trim_start_matches('/').to_string();
+        let range = request.lines()
+            .find_map(|line| line.split_once(':').filter(|(name, _)| name.eq_ignore_ascii_case("range")))
+            .and_then(|(_, value)| value.trim().strip_prefix("bytes="))
+            .and_then(|value| value.split_once('-'))
+            .map(|(start, end)| (start.parse::<usize>().unwrap(), end.parse::<usize>().unwrap()));
+        
+        let hit_count = {
+            let mut hits = hits.lock().unwrap();
+            let count = hits.entry(path.clone()).or_insert(0);
+            *count += 1;
+            *count
+        };
+        
+        let (status, headers, body) = match routes.get(&path) {
+            None => ("404 Not Found", String::new(), Vec::new()),
+            Some(route) if hit_count <= route.failures => ("500 Internal Server Error", String::new(), Vec::new()),
+            Some(route) => match range {
+                Some((start, end)) if honour_range => (
+                    "206 Partial Content",
+                    format!("Content-Range: bytes {}-{}/{}\r\n", start, end, route.body.len()),
+                    route.body[start..=end].to_vec()
+                ),
+                _ => ("200 OK", String::new(), route.body.clone()),
+            },
+        };
+        
+        let head = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n",
+                           status, body.len(), headers);
+        let _ = socket.write_all(head.as_bytes()).await;
+        let _ = socket.write_all(&body).await;
+        let _ = socket.shutdown().await;
+    }
+    
+    /// Unique output path in the system temp directory
+    fn temp_output(extension: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("hls_test_{}.{}", uuid::Uuid::new_v4(), extension))
+    }
+    
+    fn playlist(lines: &[&str]) -> Vec<u8> {
+        let mut body = String::from("#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-TARGETDURATION:10\n");
+        for line in lines {
+            body.push_str(line);
+            body.push('\n');
+        }
+        body.push_str("#EXT-X-ENDLIST\n");
+        body.into_bytes()
+    }
+    
+    #[tokio::test]
+    async fn master_playlist_picks_highest_bandwidth_variant() {
+        let master = b"#EXTM3U\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=64000,CODECS=\"mp4a.40.2\"\n\
+            low/index.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=256000,CODECS=\"mp4a.40.2\"\n\
+            high/index.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=128000,CODECS=\"mp4a.40.2\"\n\
+            mid/index.m3u8\n".to_vec();
+        let server = FixtureServer::start(vec![
+            ("master.m3u8", master, 0),
+            ("low/index.m3u8", playlist(&["#EXTINF:10.0,", "seg0.mp3"]), 0),
+            ("mid/index.m3u8", playlist(&["#EXTINF:10.0,", "seg0.mp3"]), 0),
+            ("high/index.m3u8", playlist(&["#EXTINF:10.0,", "seg0.mp3", "#EXTINF:10.0,", "seg1.mp3"]), 0),
+            ("low/seg0.mp3", b"low".to_vec(), 0),
+            ("mid/seg0.mp3", b"mid".to_vec(), 0),
+            ("high/seg0.mp3", b"high-0|".to_vec(), 0),
+            ("high/seg1.mp3", b"high-1".to_vec(), 0),
+        ], true).await;
+        let output = temp_output("mp3");
+        
+        download_hls(&server.url("master.m3u8"), &output).await.unwrap();
+        
+        assert_eq!(std::fs::read(&output).unwrap(), b"high-0|high-1");
+        assert_eq!(server.hits("low/index.m3u8"), 0);
+        assert_eq!(server.hits("mid/index.m3u8"), 0);
+        std::fs::remove_file(&output).unwrap();
+    }
+    
+    #[tokio::test]
+    async fn map_and_byte_ranges_are_written_in_order() {
+        let server = FixtureServer::start(vec![
+            ("media.mp4", b"INIT|SEG1|SEG2|".to_vec(), 0),
+            ("index.m3u8", playlist(&[
+                "#EXT-X-MAP:URI=\"media.mp4\",BYTERANGE=\"5@0\"",
+                "#EXTINF:10.0,",
+                "#EXT-X-BYTERANGE:5@5",
+                "media.mp4",
+                "#EXTINF:10.0,",
+                // No offset: continues right after the previous range
+                "#EXT-X-BYTERANGE:5",
+                "media.mp4",
+            ]), 0),
+        ], true).await;
+        let output = temp_output("m4a");
+        
+        download_hls(&server.url("index.m3u8"), &output).await.unwrap();
+        
+        assert_eq!(std::fs::read(&output).unwrap(), b"INIT|SEG1|SEG2|");
+        std::fs::remove_file(&output).unwrap();
+    }
+    
+    #[tokio::test]
+    async fn ignored_byte_range_fails_and_removes_output() {
+        let server = FixtureServer::start(vec![
+            ("media.mp4", b"INIT|SEG1|".to_vec(), 0),
+            ("index.m3u8", playlist(&[
+                "#EXT-X-MAP:URI=\"media.mp4\",BYTERANGE=\"5@0\"",
+                "#EXTINF:10.0,",
+                "#EXT-X-BYTERANGE:5@5",
+                "media.mp4",
+            ]), 0),
+        ], false).await;
+        let output = temp_output("m4a");
+        
+        let error = download_hls(&server.url("index.m3u8"), &output).await.unwrap_err();
+        
+        assert!(error.to_string().contains("206"), "unexpected error: {}", error);
+        assert!(!output.exists());
+    }
+    
+    #[tokio::test]
+    async fn encrypted_playlist_is_rejected() {
+        let server = FixtureServer::start(vec![
+            ("index.m3u8", playlist(&[
+                "#EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\"",
+                "#EXTINF:10.0,",
+                "seg0.ts",
+            ]), 0),
+            ("seg0.ts", vec![0x47; 188], 0),
+        ], true).await;
+        let output = temp_output("m4a");
+        
+        let error = download_hls(&server.url("index.m3u8"), &output).await.unwrap_err();
+        
+        assert!(error.to_string().contains("Encrypted"), "unexpected error: {}", error);
+        assert_eq!(server.hits("seg0.ts"), 0);
+        assert!(!output.exists());
+    }
+    
+    #[tokio::test]
+    async fn mpeg_ts_segments_are_not_written_into_m4a() {
+        let server = FixtureServer::start(vec![
+            ("index.m3u8", playlist(&["#EXTINF:10.0,", "seg0.ts"]), 0),
+            ("seg0.ts", vec![0x47; 188], 0),
+        ], true).await;
+        let output = temp_output("m4a");
+        
+        let error = download_hls(&server.url("index.m3u8"), &output).await.unwrap_err();
+        
+        assert!(error.to_string().contains("MPEG-TS"), "unexpected error: {}", error);
+        assert!(!output.exists());
+    }
+    
+    #[tokio::test]
+    async fn failed_segment_is_retried() {
+        let server = FixtureServer::start(vec![
+            ("index.m3u8", playlist(&["#EXTINF:10.0,", "seg0.mp3", "#EXTINF:10.0,", "seg1.mp3"]), 0),
+            ("seg0.mp3", b"first|".to_vec(), 0),
+            ("seg1.mp3", b"second".to_vec(), 1),
+        ], true).await;
+        let output = temp_output("mp3");
+        
+        download_hls(&server.url("index.m3u8"), &output).await.unwrap();
+        
+        assert_eq!(std::fs::read(&output).unwrap(), b"first|second");
+        assert_eq!(server.hits("seg1.mp3"), 2);
+        std::fs::remove_file(&output).unwrap();
+    }
+    
+    #[tokio::test]
+    async fn segment_failing_every_retry_fails_download_and_removes_output() {
+        let server = FixtureServer::start(vec![
+            ("index.m3u8", playlist(&["#EXTINF:10.0,", "seg0.mp3", "#EXTINF:10.0,", "seg1.mp3"]), 0),
+            ("seg0.mp3", b"first|".to_vec(), 0),
+            ("seg1.mp3", b"second".to_vec(), SEGMENT_MAX_RETRIES),
+        ], true).await;
+        let output = temp_output("mp3");
+        
+        let error = download_hls(&server.url("index.m3u8"), &output).await.unwrap_err();
+        
+        assert!(error.to_string().contains("segment 2/2"), "unexpected error: {}", error);
+        assert_eq!(server.hits("seg1.mp3"), SEGMENT_MAX_RETRIES);
+        assert!(!output.exists());
+    }
+}
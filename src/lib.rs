@@ -1,9 +1,25 @@
+pub mod analysis;
 pub mod audio;
+pub mod backfill;
 pub mod config;
+pub mod configformat;
 pub mod db;
 pub mod discord;
+pub mod dryrun;
+pub mod fingerprint;
+pub mod formats;
+pub mod hashing;
+pub mod hls;
 pub mod soundcloud;
 pub mod loghandler;
+pub mod maintenance;
+pub mod pipeline;
+pub mod progressive;
+pub mod reload;
+pub mod scheduler;
+pub mod tags;
+pub mod transcode;
+pub mod verify;
 
 // Re-export key structs for convenience
 pub use config::{Config, Users};
@@ -39,6 +55,7 @@ pub async fn initialize(config_path: &str) -> Result<(Config, Users, db::TrackDa
     let db = db::TrackDatabase::load_or_create(tracks_db_path)?;
     
     // Initialize SoundCloud client
+    soundcloud::set_oauth_token(config.soundcloud_oauth_token.clone());
     soundcloud::initialize().await?;
     
     Ok((config, users, db))
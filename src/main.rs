@@ -1,93 +1,221 @@
 use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
 use std::time::Duration;
 use std::env;
+use std::process::ExitCode;
 use log::{info, warn, error, debug};
-use tokio::sync::Mutex;
-use crate::loghandler::{increment_new_tracks, increment_error_count, setup_logging};
+use tokio::sync::{mpsc, watch, Mutex};
+use crate::loghandler::{increment_error_count, setup_logging, update_log_level};
 
+mod analysis;
 mod audio;
+mod backfill;
 mod config;
+mod configformat;
 mod db;
 mod discord;
+mod dryrun;
+mod fingerprint;
+mod formats;
+mod hashing;
+mod hls;
 mod soundcloud;
 mod loghandler;
+mod maintenance;
+mod pipeline;
+mod progressive;
+mod reload;
+mod scheduler;
+mod tags;
+mod transcode;
+mod verify;
 mod cli;
 
-use config::{Config, Users};
-use db::TrackDatabase;
+use backfill::BackfillOptions;
+use clap::Parser;
+use cli::{Cli, Command, ConfigCommand, DbCommand, UsersCommand};
+use config::{Config, ConfigError, ConfigOverride, Users};
+use db::{DatabaseLock, TrackDatabase};
+use pipeline::Pipeline;
+use scheduler::{JobSchedule, PollScheduler};
+
+/// Exit codes for scripts, 2 is used by clap for invalid arguments
+const EXIT_FAILURE: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_CONFIG: u8 = 3;
+const EXIT_NOT_FOUND: u8 = 4;
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+async fn main() -> ExitCode {
+    let cli = Cli::parse_from(cli::translate_legacy_args(env::args().collect()));
+    
+    // Config options come first, the logger is set up from the config
+    if let Err(e) = apply_config_options(&cli) {
+        eprintln!("Error: {}", e);
+        return ExitCode::from(EXIT_USAGE);
+    }
+    
+    let command = cli.command.unwrap_or(Command::Watch);
+    if cli.dry_run && !matches!(command, Command::Watch | Command::Post { .. }) {
+        eprintln!("Error: --dry-run only works with `watch` and `post`");
+        return ExitCode::from(EXIT_USAGE);
+    }
+    
+    if let Command::Completions { shell } = command {
+        // Nothing else may be written to stdout
+        cli::print_completions(shell);
+        return ExitCode::SUCCESS;
+    }
+    
     // Initialize logger
-    setup_logger();
+    setup_logger(cli.log_level.as_deref());
     info!("[archiver_webhook] Starting up v{}", env!("CARGO_PKG_VERSION"));
     
     // Log system info
     log_system_info();
-
-    // Check for command line args
-    let args: Vec<String> = env::args().collect();
-    debug!("Command line arguments: {:?}", args);
     
-    if args.len() > 1 {
-        match args[1].as_str() {
-            "--resolve" if args.len() > 2 => {
-                info!("Running in URL resolution mode");
-                return cli::resolve_soundcloud_url(&args[2]).await;
-            },
-            "--init-tracks" => {
-                info!("Running in database initialization mode");
-                return cli::initialize_tracks_database().await;
-            },
-            "--post-track" if args.len() > 2 => {
-                info!("Running in post-track mode");
-                return cli::post_single_track(&args[2]).await;
-            },
-            "--lookup-discord-id" if args.len() > 2 => {
-                info!("Running in Discord ID lookup mode");
-                return cli::lookup_by_discord_id(&args[2]).await;
-            },
-            "--generate-config" if args.len() > 2 => {
-                info!("Running in config generation mode");
-                return cli::generate_config(&args[2]).await;
-            },
-            "--help" | "-h" => {
-                info!("Showing help information");
-                cli::show_help();
-                return Ok(());
-            },
-            _ => {
-                warn!("Unknown command: {}", args[1]);
-                println!("Unknown command. Use --help to see available commands.");
-                return Ok(());
-            }
+    let result = if cli.dry_run {
+        run_dry_run(command, cli.download).await
+    } else {
+        run_command(command).await
+    };
+    
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{}", e);
+            ExitCode::from(exit_code(e.as_ref()))
         }
     }
+}
 
-    // Check for ffmpeg
-    if !audio::check_ffmpeg() {
-        warn!("ffmpeg not found in PATH, audio transcoding will not work!");
-        warn!("Please install ffmpeg and make sure it's in your PATH");
+/// Exit code of a failed command
+fn exit_code(e: &(dyn std::error::Error + Send + Sync + 'static)) -> u8 {
+    if e.is::<ConfigError>() {
+        EXIT_CONFIG
+    } else if e.is::<cli::NotFound>() {
+        EXIT_NOT_FOUND
     } else {
-        info!("ffmpeg found in PATH");
+        EXIT_FAILURE
     }
+}
 
-    // Run in watcher mode (default)
-    info!("Running in watcher mode");
-    run_watcher_mode().await
+async fn run_command(command: Command) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match command {
+        Command::Watch => {
+            // Check for ffmpeg
+            if !audio::check_ffmpeg() {
+                warn!("ffmpeg not found in PATH, audio transcoding will not work!");
+                warn!("Please install ffmpeg and make sure it's in your PATH");
+            } else {
+                info!("ffmpeg found in PATH");
+            }
+            
+            info!("Running in watcher mode");
+            run_watcher_mode().await
+        },
+        Command::Resolve { url } => {
+            info!("Running in URL resolution mode");
+            cli::resolve_soundcloud_url(&url).await
+        },
+        Command::Post { track } => {
+            info!("Running in post-track mode");
+            cli::post_single_track(&track).await
+        },
+        Command::Lookup { message_id } => {
+            info!("Running in Discord ID lookup mode");
+            cli::lookup_by_discord_id(&message_id).await
+        },
+        Command::Init => {
+            info!("Running in database initialization mode");
+            cli::initialize_tracks_database().await
+        },
+        Command::Backfill { user, likes, since, until, delay } => {
+            info!("Running in backfill mode");
+            let options = BackfillOptions { likes, since, until, delay: Duration::from_secs(delay) };
+            cli::backfill(&user, &options).await
+        },
+        Command::Users(UsersCommand::List { ids }) => cli::list_users(ids).await,
+        Command::Users(UsersCommand::Add { users }) => cli::add_users(&users).await,
+        Command::Users(UsersCommand::Remove { users }) => cli::remove_users(&users).await,
+        Command::Users(UsersCommand::Pause { users }) => cli::set_users_paused(&users, true).await,
+        Command::Users(UsersCommand::Resume { users }) => cli::set_users_paused(&users, false).await,
+        Command::Users(UsersCommand::Import { file }) => cli::import_users(&file).await,
+        Command::Users(UsersCommand::Export { output }) => cli::export_users(output.as_deref()).await,
+        Command::Db(DbCommand::DeadLetters) => {
+            info!("Listing dead-lettered tracks");
+            cli::list_dead_letters()
+        },
+        Command::Db(DbCommand::Retry { track_id }) => {
+            info!("Running in dead-letter retry mode");
+            cli::resolve_dead_letters(&track_id, true)
+        },
+        Command::Db(DbCommand::Discard { track_id }) => {
+            info!("Running in dead-letter discard mode");
+            cli::resolve_dead_letters(&track_id, false)
+        },
+        Command::Config(ConfigCommand::Check) => {
+            info!("Running in config check mode");
+            cli::check_config()
+        },
+        Command::Config(ConfigCommand::Convert { output }) => {
+            info!("Running in config conversion mode");
+            cli::convert_config(&output)
+        },
+        Command::Config(ConfigCommand::Generate { url }) => {
+            info!("Running in config generation mode");
+            cli::generate_config(&url).await
+        },
+        Command::Completions { .. } => unreachable!("handled before the logger is set up"),
+    }
+}
+
+/// Run `watch` or `post` without posting to Discord or writing the database
+async fn run_dry_run(command: Command, download: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match command {
+        Command::Watch => {
+            info!("Running in watcher mode (dry run)");
+            dryrun::dry_run_watch(download).await
+        },
+        Command::Post { track } => {
+            info!("Running in post-track mode (dry run)");
+            dryrun::dry_run_post(&track, download).await
+        },
+        _ => unreachable!("--dry-run is rejected for other commands"),
+    }
+}
+
+/// Apply `--config`, `--set` and `--log-level`
+fn apply_config_options(cli: &Cli) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(path) = &cli.config {
+        Config::set_path(path);
+    }
+    
+    let mut overrides = cli.overrides.iter()
+        .map(|option| ConfigOverride::from_cli(option))
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(level) = &cli.log_level {
+        overrides.push(ConfigOverride {
+            key: "log_level".to_string(),
+            value: level.clone(),
+            source: "--log-level".to_string(),
+        });
+    }
+    
+    Config::set_cli_overrides(overrides);
+    Ok(())
 }
 
 /// Setup logger with appropriate configuration
-fn setup_logger() {
+fn setup_logger(log_level: Option<&str>) {
     // Load config and initialize logging (console + file + console title updater)
-    let config_path = "config.json";
-    if let Ok(cfg) = Config::load(config_path) {
+    if let Ok(cfg) = Config::load(&Config::path()) {
         if let Err(e) = setup_logging(&cfg.log_file, &cfg.log_level) {
             eprintln!("Failed to initialize logger: {}", e);
         }
     } else {
         // Fallback to defaults
-        if let Err(e) = setup_logging("latest.log", "info") {
+        if let Err(e) = setup_logging("latest.log", log_level.unwrap_or("info")) {
             eprintln!("Failed to initialize logger: {}", e);
         }
     }
@@ -105,9 +233,9 @@ fn log_system_info() {
 /// Run the bot in watcher mode (continuous monitoring)
 async fn run_watcher_mode() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Load config
-    let config_path = "config.json";
+    let config_path = Config::path();
     info!("Loading configuration from {}", config_path);
-    let config = match Config::load(config_path) {
+    let mut config = match Config::load(&config_path) {
         Ok(c) => {
             // Log level is now set in setup_logger()
             debug!("Configuration loaded successfully");
@@ -125,6 +253,9 @@ async fn run_watcher_mode() -> Result<(), Box<dyn std::error::Error + Send + Syn
     
     // Log system info now that logger is configured
     log_system_info();
+    
+    // Set static ffmpeg output setting
+    Config::set_show_ffmpeg_output(config.show_ffmpeg_output);
 
     // Load users
     info!("Loading users from {}", config.users_file);
@@ -145,8 +276,15 @@ async fn run_watcher_mode() -> Result<(), Box<dyn std::error::Error + Send + Syn
         debug!("Loaded users: {:?}", users.users);
     }
     
-    // Initialize database
+    // Initialize database, a backfill must not write it while we run
     info!("Initializing tracks database");
+    let _db_lock = match DatabaseLock::acquire(&config.tracks_file) {
+        Ok(lock) => lock,
+        Err(e) => {
+            error!("Failed to lock tracks database: {}", e);
+            return Err(e);
+        }
+    };
     let tracks_db_path = config.tracks_file.clone();
     let db = Arc::new(Mutex::new(match TrackDatabase::load_or_create(tracks_db_path) {
         Ok(d) => {
@@ -162,6 +300,7 @@ async fn run_watcher_mode() -> Result<(), Box<dyn std::error::Error + Send + Syn
     
     // Initialize SoundCloud client
     info!("Initializing SoundCloud client");
+    soundcloud::set_oauth_token(config.soundcloud_oauth_token.clone());
     match soundcloud::initialize().await {
         Ok(_) => info!("SoundCloud client initialized successfully"),
         Err(e) => {
@@ -187,6 +326,27 @@ async fn run_watcher_mode() -> Result<(), Box<dyn std::error::Error + Send + Syn
         }
     }
     
+    // Workers and background jobs read the config from here, so reloads reach them
+    let (config_tx, config_rx) = watch::channel(config.clone());
+    
+    // Start the download and posting workers
+    let pipeline = Pipeline::start(&config_rx, db.clone());
+    
+    // Finish the tracks a previous run found but didn't archive
+    if pipeline.resume(&db).await == 0 {
+        debug!("No unfinished jobs from the previous run");
+    }
+    
+    // Shared with the auto-follow job, which adds users while the loop polls
+    let users = Arc::new(Mutex::new(users));
+    
+    // Start the background jobs, each on its own schedule
+    spawn_scheduled_jobs(&config, &config_rx, &db, &users, &pipeline);
+    
+    // Reload config.json and users.json when they change
+    let (reload_tx, mut reload_rx) = mpsc::channel::<()>(1);
+    reload::spawn_file_watcher(vec![config_path.clone(), config.users_file.clone()], reload_tx);
+
     // Initialize signal handlers for clean shutdown
     #[cfg(unix)]
     let mut sigint = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt())
@@ -194,60 +354,91 @@ async fn run_watcher_mode() -> Result<(), Box<dyn std::error::Error + Send + Syn
     #[cfg(unix)]
     let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
         .expect("Failed to set up SIGTERM handler");
+    #[cfg(unix)]
+    let mut sighup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        .expect("Failed to set up SIGHUP handler");
     
-    // Create scheduler interval
-    let poll_interval = Duration::from_secs(config.poll_interval_sec);
-    let mut interval = tokio::time::interval(poll_interval);
+    // Track when each user is polled next
+    let mut scheduler = PollScheduler::new();
+    let mut poll_schedule = config.schedule.poll.clone()
+        .unwrap_or(JobSchedule::Every(Duration::from_secs(config.poll_interval_sec)));
+    let mut next_poll = tokio::time::Instant::now();
     
     // Start main polling loop
-    info!("Starting polling loop with interval of {} seconds", config.poll_interval_sec);
+    if config.adaptive_polling {
+        info!("Starting polling loop with adaptive intervals between {} and {} seconds",
+              config.min_poll_interval_sec, config.max_poll_interval_sec);
+    } else {
+        info!("Starting polling loop, polling {}", poll_schedule);
+    }
     
     // Initialize counters
     let mut total_polls = 0;
-    let mut follow_check_counter = 0;
-    let mut db_save_counter = 0;
-    let mut tracks_since_last_save = 0;
-    let mut db_needs_saving = false;
 
     // Main polling loop
     loop {
-        total_polls += 1;
-        info!("Starting poll #{}", total_polls);
-        
-        // Wait for either the next tick or a shutdown signal
+        // Wait until the next poll is due or a shutdown signal arrives
+        let user_ids = users.lock().await.active();
+        let next_wakeup = if config.adaptive_polling {
+            scheduler.next_wakeup(&user_ids, &config)
+        } else {
+            next_poll
+        };
         #[cfg(unix)]
-        let should_shutdown = tokio::select! {
-            _ = interval.tick() => false,
+        let wakeup = tokio::select! {
+            _ = tokio::time::sleep_until(next_wakeup) => Wakeup::Poll,
+            Some(()) = reload_rx.recv() => Wakeup::Reload,
+            _ = sighup.recv() => {
+                info!("Received SIGHUP signal, reloading configuration");
+                Wakeup::Reload
+            },
             _ = sigint.recv() => {
                 info!("Received SIGINT signal");
-                true
+                Wakeup::Shutdown
             },
             _ = sigterm.recv() => {
                 info!("Received SIGTERM signal");
-                true
+                Wakeup::Shutdown
             },
         };
         
         #[cfg(not(unix))]
-        let should_shutdown = tokio::select! {
-            _ = interval.tick() => false,
+        let wakeup = tokio::select! {
+            _ = tokio::time::sleep_until(next_wakeup) => Wakeup::Poll,
+            Some(()) = reload_rx.recv() => Wakeup::Reload,
             result = tokio::signal::ctrl_c() => {
                 match result {
                     Ok(()) => {
                         info!("Received Ctrl+C signal");
                         // Give some breathing room for signal handling
                         tokio::time::sleep(Duration::from_millis(100)).await;
-                        true
+                        Wakeup::Shutdown
                     },
                     Err(e) => {
                         error!("Error handling Ctrl+C signal: {}", e);
-                        true
+                        Wakeup::Shutdown
                     }
                 }
             },
         };
         
-        if should_shutdown {
+        if let Wakeup::Reload = wakeup {
+            if let Some(new_config) = reload::reload_config(&config, &config_path) {
+                apply_config(&new_config);
+                poll_schedule = new_config.schedule.poll.clone()
+                    .unwrap_or(JobSchedule::Every(Duration::from_secs(new_config.poll_interval_sec)));
+                config = new_config;
+                config_tx.send_replace(config.clone());
+            }
+            
+            let mut users_guard = users.lock().await;
+            if let Some(new_users) = reload::reload_users(&users_guard, &config.users_file) {
+                *users_guard = new_users;
+            }
+            continue;
+        }
+        
+        if let Wakeup::Shutdown = wakeup {
             info!("Shutdown signal received, performing clean shutdown");
             
             // Set a reasonable timeout for shutdown operations
@@ -275,34 +466,19 @@ async fn run_watcher_mode() -> Result<(), Box<dyn std::error::Error + Send + Syn
             break;
         }
         
-        // Check if it's time to update followings
-        if config.auto_follow_source.is_some() {
-            follow_check_counter += 1;
-            
-            if follow_check_counter >= config.auto_follow_interval {
-                info!("Auto-follow interval reached ({} polls), checking for new followings", 
-                      config.auto_follow_interval);
-                
-                match update_followings_from_source(&config, &mut users).await {
-                    Ok(count) => {
-                        if count > 0 {
-                            info!("Added {} new users to watch from auto-follow source", count);
-                        } else {
-                            debug!("No new followings found from auto-follow source");
-                        }
-                    },
-                    Err(e) => {
-                        warn!("Failed to update followings from source: {}", e);
-                    }
-                }
-                
-                // Reset counter
-                follow_check_counter = 0;
-            }
-        }
+        total_polls += 1;
+        info!("Starting poll #{}", total_polls);
+        
+        // Planned from the start of this poll, so a slow poll doesn't shift the schedule
+        next_poll = poll_schedule.next_run();
         
-        // Process users in parallel batches
-        let users_vec = users.users.clone();
+        // Process the due users in parallel batches, including users auto-follow added meanwhile, without paused users
+        let user_ids = users.lock().await.active();
+        let users_vec = if config.adaptive_polling {
+            scheduler.due_users(&user_ids)
+        } else {
+            user_ids
+        };
         let mut users_processed = 0;
         let mut total_new_tracks = 0;
         
@@ -318,13 +494,14 @@ async fn run_watcher_mode() -> Result<(), Box<dyn std::error::Error + Send + Syn
                 let config = config.clone();
                 let user_id = user_id.clone();
                 let db = db.clone();
+                let pipeline = pipeline.clone();
+                
+                // Scheduled as failed up front, so a panicking poll isn't retried right away
+                scheduler.schedule(&user_id, None, &config);
                 
                 let task = tokio::spawn(async move {
-                    match poll_user(&config, &user_id, &db).await {
-                        Ok(count) => {
-                            increment_new_tracks(count as u64);
-                            (user_id, Ok(count))
-                        },
+                    match pipeline.poll_user(&config, &user_id, &db).await {
+                        Ok(outcome) => (user_id, Ok(outcome)),
                         Err(e) => {
                             error!("Error polling user {}: {}", user_id, e);
                             increment_error_count();
@@ -339,12 +516,9 @@ async fn run_watcher_mode() -> Result<(), Box<dyn std::error::Error + Send + Syn
             // Wait for all tasks in the batch to complete
             for task in tasks {
                 match task.await {
-                    Ok((_user_id, Ok(count))) => {
+                    Ok((user_id, Ok((count, activity)))) => {
+                        scheduler.schedule(&user_id, Some(&activity), &config);
                         total_new_tracks += count;
-                        tracks_since_last_save += count;
-                        if count > 0 {
-                            db_needs_saving = true;
-                        }
                     },
                     Ok((_user_id, Err(_))) => {
                         // Error already logged in poll_user
@@ -359,69 +533,115 @@ async fn run_watcher_mode() -> Result<(), Box<dyn std::error::Error + Send + Syn
             users_processed += batch_size;
         }
 
-        // Increment the database save counter
-        db_save_counter += 1;
-        
-        // Save the database if:
-        // 1. We found new tracks and reached the track threshold OR
-        // 2. It's time for a scheduled save based on poll cycles
-        let save_by_tracks = db_needs_saving && tracks_since_last_save >= config.db_save_tracks;
-        let save_by_interval = db_save_counter >= config.db_save_interval;
-        
-        if save_by_tracks || save_by_interval {
-            let save_reason = if save_by_tracks {
-                format!("processed {} new tracks (threshold: {})", 
-                       tracks_since_last_save, config.db_save_tracks)
-            } else {
-                format!("reached poll interval {} (current: {})",
-                       config.db_save_interval, db_save_counter)
-            };
-            
-            info!("Saving database: {}", save_reason);
-            
-            // Hold the mutex lock for the entire save operation
-            let db_guard = db.lock().await;
-            if let Err(e) = db_guard.save() {
-                error!("Failed to save tracks database: {}", e);
-            } else {
-                info!("Database saved successfully with {} tracks ({})", 
-                     db_guard.get_all_tracks().len(), save_reason);
-            }
-            
-            // Reset the counter and flag
-            db_save_counter = 0;
-            tracks_since_last_save = 0;
-            db_needs_saving = false;
-        }
-
         if total_new_tracks > 0 {
             info!("Poll #{} completed: {} new tracks found", total_polls, total_new_tracks);
         } else {
             debug!("Poll #{} completed: no new tracks", total_polls);
         }
-        
-        // Sleep until next poll
-        tokio::time::sleep(std::time::Duration::from_secs(config.poll_interval_sec)).await;
     }
     
     Ok(())
 }
 
-/// Poll a user for new tracks, process them, and send to Discord
-async fn poll_user(
+/// Why the main loop woke up
+enum Wakeup {
+    Poll,
+    Reload,
+    Shutdown,
+}
+
+/// Apply the settings that live in process-wide state
+fn apply_config(config: &Config) {
+    update_log_level(&config.log_level);
+    Config::set_show_ffmpeg_output(config.show_ffmpeg_output);
+    soundcloud::set_oauth_token(config.soundcloud_oauth_token.clone());
+}
+
+/// Start the scheduled background jobs that are enabled in the config
+///
+/// Every job runs in its own task, so a slow deletion sweep doesn't hold up polling.
+/// The schedules are fixed, but every run uses the config that is current when it starts.
+fn spawn_scheduled_jobs(
     config: &Config,
-    user_id: &str,
+    live_config: &watch::Receiver<Config>,
     db: &Arc<Mutex<TrackDatabase>>,
-) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
-    // Create semaphores for limiting concurrency
-    let processing_semaphore = Arc::new(tokio::sync::Semaphore::new(config.max_processing_parallelism));
-    let discord_semaphore = Arc::new(tokio::sync::Semaphore::new(config.max_discord_parallelism));
+    users: &Arc<Mutex<Users>>,
+    pipeline: &Pipeline,
+) {
+    let schedules = &config.schedule;
     
-    // Get mutable access to the database
-    let mut db_guard = db.lock().await;
+    // Spawned even without a source, a reload may add one
+    if let Some(schedule) = &schedules.auto_follow {
+        let live_config = live_config.clone();
+        let users = users.clone();
+        scheduler::spawn_job("auto-follow", schedule.clone(), move || {
+            let config = live_config.borrow().clone();
+            let users = users.clone();
+            async move {
+                // Update a copy, so polls aren't blocked while the followings are fetched
+                let mut updated = users.lock().await.clone();
+                let count = update_followings_from_source(&config, &mut updated).await?;
+                if count > 0 {
+                    info!("Added {} new users to watch from auto-follow source", count);
+                    // Merged, in case a reload changed the users meanwhile
+                    let mut users = users.lock().await;
+                    for user_id in updated.users {
+                        if !users.users.contains(&user_id) {
+                            users.users.push(user_id);
+                        }
+                    }
+                } else {
+                    debug!("No new followings found from auto-follow source");
+                }
+                Ok(())
+            }
+        });
+    }
     
-    // Use the poll_user method with both semaphores
-    db_guard.poll_user(user_id, config, &processing_semaphore, &discord_semaphore).await
+    if let Some(schedule) = &schedules.deletion_sweep {
+        let live_config = live_config.clone();
+        let db = db.clone();
+        let cursor = Arc::new(AtomicUsize::new(0));
+        scheduler::spawn_job("deletion sweep", schedule.clone(), move || {
+            let config = live_config.borrow().clone();
+            let db = db.clone();
+            let cursor = cursor.clone();
+            async move { maintenance::deletion_sweep(&config, &db, &cursor).await }
+        });
+    }
+    
+    if let Some(schedule) = &schedules.embed_refresh {
+        let live_config = live_config.clone();
+        let db = db.clone();
+        let cursor = Arc::new(AtomicUsize::new(0));
+        scheduler::spawn_job("embed refresh", schedule.clone(), move || {
+            let config = live_config.borrow().clone();
+            let db = db.clone();
+            let cursor = cursor.clone();
+            async move { maintenance::refresh_embeds(&config, &db, &cursor).await }
+        });
+    }
+    
+    if let Some(schedule) = &schedules.db_compaction {
+        let db = db.clone();
+        scheduler::spawn_job("database compaction", schedule.clone(), move || {
+            let db = db.clone();
+            async move { maintenance::compact_database(&db).await }
+        });
+    }
+    
+    if let Some(schedule) = &schedules.retry {
+        let db = db.clone();
+        let pipeline = pipeline.clone();
+        scheduler::spawn_job("retry", schedule.clone(), move || {
+            let db = db.clone();
+            let pipeline = pipeline.clone();
+            async move {
+                pipeline.dispatch_due(&db).await;
+                Ok(())
+            }
+        });
+    }
 }
 
 /// Check for new followings from a source user and add them to the watched users list
@@ -0,0 +1,102 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use log::{info, debug, warn};
+use tokio::sync::Mutex;
+use crate::config::Config;
+use crate::db::{DiscordMessage, TrackDatabase};
+
+/// Check a batch of archived tracks for deletion from SoundCloud
+///
+/// Deleted tracks are recorded in the database and their Discord posts are flagged.
+/// `cursor` remembers where the last run stopped, so consecutive runs walk the
+/// whole archive.
+pub async fn deletion_sweep(
+    config: &Config,
+    db: &Arc<Mutex<TrackDatabase>>,
+    cursor: &AtomicUsize,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let batch = next_batch(db, cursor, config.schedule.batch_size).await;
+    debug!("Deletion sweep checking {} tracks", batch.len());
+
+    let mut deleted = 0;
+    for (track_id, message) in batch {
+        match crate::soundcloud::track_exists(&track_id, message.secret_token.as_deref()).await {
+            Ok(true) => {},
+            Ok(false) => {
+                warn!("Track {} was deleted from SoundCloud", track_id);
+                {
+                    let mut db_guard = db.lock().await;
+                    db_guard.mark_deleted(&track_id);
+                    db_guard.save()?;
+                }
+                deleted += 1;
+
+                if let Err(e) = crate::discord::mark_track_deleted(&config.discord_webhook_url, &message.id).await {
+                    warn!("Failed to flag the post of deleted track {}: {}", track_id, e);
+                }
+            },
+            Err(e) => warn!("Failed to check track {} for deletion: {}", track_id, e),
+        }
+    }
+
+    if deleted > 0 {
+        info!("Deletion sweep found {} deleted tracks", deleted);
+    }
+    Ok(())
+}
+
+/// Update the posts of a batch of archived tracks with their current metadata
+pub async fn refresh_embeds(
+    config: &Config,
+    db: &Arc<Mutex<TrackDatabase>>,
+    cursor: &AtomicUsize,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let batch = next_batch(db, cursor, config.schedule.batch_size).await;
+    debug!("Refreshing embeds of {} tracks", batch.len());
+
+    let mut refreshed = 0;
+    for (track_id, message) in batch {
+        let track = match crate::soundcloud::get_track_details_with_secret(&track_id, message.secret_token.as_deref()).await {
+            Ok(track) => track,
+            Err(e) => {
+                warn!("Failed to fetch track {} for embed refresh: {}", track_id, e);
+                continue;
+            }
+        };
+
+        match crate::discord::refresh_track_embed(&config.discord_webhook_url, &message.id, &track).await {
+            Ok(()) => refreshed += 1,
+            Err(e) => warn!("Failed to refresh embed of track {}: {}", track_id, e),
+        }
+    }
+
+    debug!("Refreshed {} embeds", refreshed);
+    Ok(())
+}
+
+/// Drop finished jobs from the tracks database and save it
+pub async fn compact_database(
+    db: &Arc<Mutex<TrackDatabase>>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut db_guard = db.lock().await;
+    let dropped = db_guard.compact();
+    db_guard.save()?;
+    debug!("Compacted tracks database, dropped {} finished jobs", dropped);
+    Ok(())
+}
+
+/// Next `size` posted tracks after the cursor, wrapping around at the end
+async fn next_batch(
+    db: &Arc<Mutex<TrackDatabase>>,
+    cursor: &AtomicUsize,
+    size: usize,
+) -> Vec<(String, DiscordMessage)> {
+    let posted = db.lock().await.posted_tracks();
+    if posted.is_empty() {
+        return Vec::new();
+    }
+
+    let size = size.clamp(1, posted.len());
+    let start = cursor.fetch_add(size, Ordering::Relaxed) % posted.len();
+    posted.into_iter().cycle().skip(start).take(size).collect()
+}
@@ -0,0 +1,320 @@
+use std::sync::Arc;
+use log::{info, debug, error, warn};
+use tokio::sync::{mpsc, watch, Mutex};
+use crate::config::Config;
+use crate::db::{JobState, TrackDatabase, TrackJob};
+use crate::loghandler::{increment_error_count, increment_new_tracks, increment_total_tracks};
+use crate::scheduler::UploadActivity;
+use crate::soundcloud::{self, PreparedTrack, Track};
+
+/// Producer/consumer pipeline that archives queued tracks
+///
+/// Polls only discover new tracks and hand their jobs to a bounded channel.
+/// A pool of `max_processing_parallelism` download workers prepares them and
+/// passes them on to a pool of `max_discord_parallelism` posting workers. The
+/// database lock is only taken for short reads and writes between the steps.
+#[derive(Clone)]
+pub struct Pipeline {
+    jobs: mpsc::Sender<TrackJob>,
+}
+
+impl Pipeline {
+    /// Spawn the worker pools
+    ///
+    /// The pool and queue sizes are fixed, but every job uses the config that is current when it starts.
+    pub fn start(live_config: &watch::Receiver<Config>, db: Arc<Mutex<TrackDatabase>>) -> Pipeline {
+        let config = live_config.borrow().clone();
+        let (jobs_tx, jobs_rx) = mpsc::channel::<TrackJob>(config.job_queue_size.max(1));
+        // Prepared tracks hold temp files on disk, so only a few wait for a posting worker
+        let (prepared_tx, prepared_rx) = mpsc::channel::<PreparedTrack>(config.max_discord_parallelism.max(1));
+
+        let jobs_rx = Arc::new(Mutex::new(jobs_rx));
+        let prepared_rx = Arc::new(Mutex::new(prepared_rx));
+
+        for worker in 0..config.max_processing_parallelism.max(1) {
+            tokio::spawn(download_worker(
+                worker,
+                live_config.clone(),
+                Arc::clone(&db),
+                Arc::clone(&jobs_rx),
+                prepared_tx.clone(),
+            ));
+        }
+
+        for worker in 0..config.max_discord_parallelism.max(1) {
+            tokio::spawn(posting_worker(
+                worker,
+                live_config.clone(),
+                Arc::clone(&db),
+                Arc::clone(&prepared_rx),
+            ));
+        }
+
+        debug!("Started {} download workers and {} posting workers",
+               config.max_processing_parallelism.max(1), config.max_discord_parallelism.max(1));
+
+        Pipeline { jobs: jobs_tx }
+    }
+
+    /// Poll a user for new tracks and queue them for archiving
+    ///
+    /// Failed jobs of the user whose backoff has passed are queued again as well.
+    /// Returns the number of new tracks found and the user's upload activity.
+    /// Waits when the queue is full.
+    pub async fn poll_user(
+        &self,
+        config: &Config,
+        user_id: &str,
+        db: &Arc<Mutex<TrackDatabase>>,
+    ) -> Result<(usize, UploadActivity), Box<dyn std::error::Error + Send + Sync>> {
+        // Fetch without holding the database, so users are polled in parallel
+        let (tracks, activity) = fetch_user_tracks(config, user_id).await?;
+
+        let (queued, jobs) = {
+            let mut db_guard = db.lock().await;
+
+            let queued = tracks.iter()
+                .filter(|track| db_guard.enqueue_job(&track.id, Some(user_id), track.secret_token.as_deref()))
+                .count();
+
+            let jobs = db_guard.take_due_jobs(Some(user_id));
+
+            // Saved right away so the new jobs survive a crash
+            if queued > 0 || !jobs.is_empty() {
+                if let Err(e) = db_guard.save() {
+                    error!("Failed to save tracks database with new jobs: {}", e);
+                }
+            }
+
+            (queued, jobs)
+        };
+
+        if queued > 0 {
+            info!("Queued {} new tracks for user {}", queued, user_id);
+        }
+
+        self.dispatch(jobs).await;
+        Ok((queued, activity))
+    }
+
+    /// Queue the jobs left over from a previous run
+    ///
+    /// Returns the number of jobs queued.
+    pub async fn resume(&self, db: &Arc<Mutex<TrackDatabase>>) -> usize {
+        let jobs = {
+            let mut db_guard = db.lock().await;
+
+            let interrupted = db_guard.reset_interrupted_jobs();
+            if interrupted > 0 {
+                info!("Reset {} jobs that were interrupted by the last shutdown", interrupted);
+            }
+
+            let jobs = db_guard.take_due_jobs(None);
+            if !jobs.is_empty() {
+                if let Err(e) = db_guard.save() {
+                    error!("Failed to save tracks database with resumed jobs: {}", e);
+                }
+            }
+            jobs
+        };
+
+        let count = jobs.len();
+        if count > 0 {
+            info!("Resuming {} unfinished jobs", count);
+        }
+
+        self.dispatch(jobs).await;
+        count
+    }
+
+    /// Queue the failed jobs of every user whose backoff has passed
+    ///
+    /// Polls only requeue the jobs of the polled user, this also reaches jobs of
+    /// paused, removed and rarely polled users. Returns the number of jobs queued.
+    pub async fn dispatch_due(&self, db: &Arc<Mutex<TrackDatabase>>) -> usize {
+        let jobs = {
+            let mut db_guard = db.lock().await;
+
+            let jobs = db_guard.take_due_jobs(None);
+            if !jobs.is_empty() {
+                if let Err(e) = db_guard.save() {
+                    error!("Failed to save tracks database with retried jobs: {}", e);
+                }
+            }
+            jobs
+        };
+
+        let count = jobs.len();
+        if count > 0 {
+            info!("Retrying {} failed jobs", count);
+        }
+
+        self.dispatch(jobs).await;
+        count
+    }
+
+    /// Hand jobs to the download workers
+    async fn dispatch(&self, jobs: Vec<TrackJob>) {
+        for job in jobs {
+            let track_id = job.track_id.clone();
+            if self.jobs.send(job).await.is_err() {
+                // Only happens when the workers are gone, i.e. during shutdown.
+                // The job stays queued in the database and is resumed on the next start.
+                warn!("Job queue is closed, track {} will be archived after a restart", track_id);
+                return;
+            }
+        }
+    }
+}
+
+/// Fetch a user's tracks, and their likes if enabled
+///
+/// The upload activity only looks at the user's own uploads.
+pub async fn fetch_user_tracks(
+    config: &Config,
+    user_id: &str,
+) -> Result<(Vec<Track>, UploadActivity), Box<dyn std::error::Error + Send + Sync>> {
+    let mut all_tracks = match soundcloud::get_user_tracks(user_id, config.max_tracks_per_user, config.pagination_size).await {
+        Ok(t) => t,
+        Err(e) => {
+            error!("Failed to fetch tracks for user {}: {}", user_id, e);
+            return Err(e);
+        }
+    };
+
+    debug!("Fetched {} tracks for user {}", all_tracks.len(), user_id);
+    let activity = UploadActivity::from_tracks(&all_tracks);
+
+    if config.scrape_user_likes {
+        debug!("Fetching likes for user {} (enabled in config)", user_id);
+        match soundcloud::get_user_likes(user_id, config.max_likes_per_user, config.pagination_size).await {
+            Ok(likes) => {
+                info!("Fetched {} likes for user {}", likes.len(), user_id);
+
+                let liked_tracks = soundcloud::extract_tracks_from_likes(&likes);
+                debug!("Extracted {} tracks from user {}'s likes", liked_tracks.len(), user_id);
+
+                all_tracks.extend(liked_tracks);
+                debug!("Total tracks (uploads + likes): {}", all_tracks.len());
+            },
+            Err(e) => {
+                warn!("Failed to fetch likes for user {}: {}", user_id, e);
+                // Continue with just the user's tracks
+            }
+        }
+    }
+
+    Ok((all_tracks, activity))
+}
+
+/// Take jobs from the queue, download and process them, and pass them to the posting workers
+async fn download_worker(
+    worker: usize,
+    live_config: watch::Receiver<Config>,
+    db: Arc<Mutex<TrackDatabase>>,
+    jobs: Arc<Mutex<mpsc::Receiver<TrackJob>>>,
+    prepared: mpsc::Sender<PreparedTrack>,
+) {
+    loop {
+        // The receiver lock is released as soon as a job arrives
+        let job = match jobs.lock().await.recv().await {
+            Some(job) => job,
+            None => break,
+        };
+        let track_id = job.track_id;
+        let secret_token = job.secret_token;
+        let config = live_config.borrow().clone();
+
+        // Copy of the indexes, so the database isn't held during the download
+        let archive_index = {
+            let mut db_guard = db.lock().await;
+            db_guard.set_job_state(&track_id, JobState::Downloading);
+            db_guard.archive_index()
+        };
+
+        debug!("Download worker {} processing track {}", worker, track_id);
+        let result = soundcloud::prepare_track_with_secret(&track_id, secret_token.as_deref(), &config, Some(&archive_index), false).await;
+
+        match result {
+            Ok(track) => {
+                {
+                    let mut db_guard = db.lock().await;
+                    db_guard.set_job_state(&track_id, JobState::Posting);
+                    if let Err(e) = db_guard.save() {
+                        error!("Failed to save tracks database after processing: {}", e);
+                    }
+                }
+
+                if prepared.send(track).await.is_err() {
+                    warn!("Posting workers are gone, track {} will be archived after a restart", track_id);
+                    break;
+                }
+            },
+            Err(e) => {
+                error!("Failed to process track {}: {}", track_id, e);
+                let mut db_guard = db.lock().await;
+                db_guard.fail_job(&track_id, &e.to_string(), &config);
+                if let Err(e) = db_guard.save() {
+                    error!("Failed to save tracks database after a failed job: {}", e);
+                }
+            }
+        }
+    }
+
+    debug!("Download worker {} stopped", worker);
+}
+
+/// Take prepared tracks, post them to Discord and record the result
+async fn posting_worker(
+    worker: usize,
+    live_config: watch::Receiver<Config>,
+    db: Arc<Mutex<TrackDatabase>>,
+    prepared: Arc<Mutex<mpsc::Receiver<PreparedTrack>>>,
+) {
+    loop {
+        let mut track = match prepared.lock().await.recv().await {
+            Some(track) => track,
+            None => break,
+        };
+        let track_id = track.track_id.clone();
+        let config = live_config.borrow().clone();
+
+        // The index copy is from before the download, another worker may have posted
+        // an identical file since then
+        if config.deduplicate_uploads {
+            let db_guard = db.lock().await;
+            let linked = track.link_duplicates(db_guard.archived_files());
+            if linked > 0 {
+                debug!("Linked {} files of track {} that were posted while it was downloading", linked, track_id);
+            }
+        }
+
+        debug!("Posting worker {} posting track {}", worker, track_id);
+        // The pool size already limits concurrent webhooks, so no semaphore is needed
+        let result = soundcloud::post_prepared_track(track, &config, None).await;
+
+        let mut db_guard = db.lock().await;
+        match result {
+            Ok((_track_id, _uploader_id, response, record)) => {
+                info!("Successfully archived track {} (Discord message ID: {})", track_id, response.message_id);
+                db_guard.finish_job(&track_id, &response, &record);
+
+                if let Err(e) = db_guard.save() {
+                    error!("Failed to save tracks database with Discord info: {}", e);
+                    increment_error_count();
+                }
+                increment_new_tracks(1);
+                increment_total_tracks(1);
+            },
+            Err(e) => {
+                error!("Failed to post track {}: {}", track_id, e);
+                db_guard.fail_job(&track_id, &e.to_string(), &config);
+                if let Err(e) = db_guard.save() {
+                    error!("Failed to save tracks database after a failed job: {}", e);
+                }
+            }
+        }
+    }
+
+    debug!("Posting worker {} stopped", worker);
+}
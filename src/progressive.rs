@@ -0,0 +1,419 @@
+use std::path::Path;
+use std::time::Duration;
+use log::{info, warn, debug};
+use reqwest::{Client, StatusCode};
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tokio::time::sleep;
+
+/// Number of connection attempts (the first one plus resumes) before giving up
+const MAX_ATTEMPTS: u32 = 5;
+
+lazy_static::lazy_static! {
+    // No overall timeout: large files can take longer than any fixed limit,
+    // a stalled connection is caught by the read timeout instead
+    static ref HTTP_CLIENT: Client = Client::builder()
+        .connect_timeout(Duration::from_secs(15))
+        .read_timeout(Duration::from_secs(30))
+        .build()
+        .unwrap();
+}
+
+/// Download a progressive (plain HTTP) stream directly to a file
+///
+/// The body is written to disk as it arrives. If the connection drops, the download
+/// resumes from the last written byte with a `Range` request. The final size is checked
+/// against Content-Length (or the total from Content-Range) so a truncated file is an error.
+/// Returns the number of bytes written.
+pub async fn download_progressive(
+    url: &str,
+    output_path: &Path
+) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+    let mut file = reopen_truncated(output_path).await?;
+    
+    let mut downloaded: u64 = 0;
+    let mut expected_size: Option<u64> = None;
+    let mut last_error: Option<String> = None;
+    
+    for attempt in 0..MAX_ATTEMPTS {
+        if attempt > 0 {
+            info!("Resuming progressive download at byte {} (attempt {}/{}): {}",
+                  downloaded, attempt + 1, MAX_ATTEMPTS, last_error.as_deref().unwrap_or("unknown error"));
+            sleep(Duration::from_secs(2 * attempt as u64)).await;
+        }
+        
+        let mut request = HTTP_CLIENT.get(url);
+        if downloaded > 0 {
+            request = request.header(reqwest::header::RANGE, format!("bytes={}-", downloaded));
+        }
+        
+        let mut response = match request.send().await {
+            Ok(res) => res,
+            Err(e) => {
+                last_error = Some(e.without_url().to_string());
+                continue;
+            }
+        };
+        
+        let status = response.status();
+        if status == StatusCode::RANGE_NOT_SATISFIABLE && expected_size == Some(downloaded) {
+            // We already have everything, the previous attempt just didn't notice the end
+            break;
+        }
+        
+        if !status.is_success() {
+            // Client errors (expired signature, gone) won't fix themselves
+            if status.is_client_error() {
+                return Err(format!("HTTP error {}", status).into());
+            }
+            last_error = Some(format!("HTTP error {}", status));
+            continue;
+        }
+        
+        if downloaded > 0 && status != StatusCode::PARTIAL_CONTENT {
+            // The server ignored the Range header and sent the whole file again
+            warn!("Server does not support resuming, restarting download from the beginning");
+            file = reopen_truncated(output_path).await?;
+            downloaded = 0;
+        }
+        
+        // Work out the total size of the file
+        let total = if status == StatusCode::PARTIAL_CONTENT {
+            response.headers()
+                .get(reqwest::header::CONTENT_RANGE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(parse_content_range_total)
+        } else {
+            response.content_length()
+        };
+        
+        if let Some(total) = total {
+            if let Some(previous) = expected_size {
+                if previous != total {
+                    return Err(format!("File size changed between attempts ({} -> {} bytes)", previous, total).into());
+                }
+            }
+            expected_size = Some(total);
+        }
+        
+        // Stream the body to disk
+        loop {
+            match response.chunk().await {
+                Ok(Some(chunk)) => {
+                    file.write_all(&chunk).await?;
+                    downloaded += chunk.len() as u64;
+                },
+                Ok(None) => {
+                    last_error = None;
+                    break;
+                },
+                Err(e) => {
+                    last_error = Some(format!("connection dropped: {}", e.without_url()));
+                    break;
+                }
+            }
+        }
+        file.flush().await?;
+        
+        match expected_size {
+            Some(expected) if downloaded == expected => break,
+            Some(expected) if downloaded > expected => {
+                return Err(format!("Downloaded more data than expected ({} > {} bytes)", downloaded, expected).into());
+            },
+            Some(expected) if last_error.is_none() => {
+                last_error = Some(format!("stream ended early ({} of {} bytes)", downloaded, expected));
+            },
+            Some(_) => {},
+            None if last_error.is_none() => {
+                // Without a length all we can do is trust a cleanly finished stream
+                debug!("No Content-Length for progressive stream, accepting {} bytes", downloaded);
+                break;
+            },
+            None => {}
+        }
+    }
+    
+    if let Some(expected) = expected_size {
+        if downloaded != expected {
+            return Err(format!("Incomplete download after {} attempts: {} of {} bytes ({})",
+                               MAX_ATTEMPTS, downloaded, expected,
+                               last_error.unwrap_or_else(|| "unknown error".to_string())).into());
+        }
+    } else if let Some(e) = last_error {
+        return Err(format!("Progressive download failed after {} attempts: {}", MAX_ATTEMPTS, e).into());
+    }
+    
+    if downloaded == 0 {
+        return Err("Progressive download returned an empty file".into());
+    }
+    
+    debug!("Progressive download completed: {} bytes to {}", downloaded, output_path.display());
+    Ok(downloaded)
+}
+
+/// Fetch the response headers of a progressive URL without downloading the body
+///
+/// Only the first byte is requested, so this also works for signed URLs that refuse
+/// HEAD requests. Used to read Content-Disposition and Content-Type before a download.
+pub async fn fetch_headers(
+    url: &str
+) -> Result<reqwest::header::HeaderMap, Box<dyn std::error::Error + Send + Sync>> {
+    let response = HTTP_CLIENT.get(url)
+        .header(reqwest::header::RANGE, "bytes=0-0")
+        .send()
+        .await
+        .map_err(|e| e.without_url())?;
+    
+    if !response.status().is_success() {
+        return Err(format!("HTTP error {}", response.status()).into());
+    }
+    
+    Ok(response.headers().clone())
+}
+
+/// Open the output file, truncated, for a (re)start from the beginning
+async fn reopen_truncated(output_path: &Path) -> Result<tokio::fs::File, std::io::Error> {
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(output_path)
+        .await
+}
+
+/// Extract the total size from a Content-Range header ("bytes 100-199/1000")
+fn parse_content_range_total(header: &str) -> Option<u64> {
+    header.rsplit('/').next()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+    
+    /// How the fixture server answers requests for a file
+    #[derive(Clone)]
+    struct Route {
+        body: Vec<u8>,
+        status: &'static str,
+        /// Close the first response after this many bytes, with the full Content-Length sent
+        drop_first_after: Option<usize>,
+        honour_range: bool,
+        send_length: bool,
+    }
+    
+    impl Route {
+        fn new(body: &[u8]) -> Route {
+            Route {
+                body: body.to_vec(),
+                status: "200 OK",
+                drop_first_after: None,
+                honour_range: true,
+                send_length: true,
+            }
+        }
+    }
+    
+    /// Minimal HTTP/1.1 server for one file, like the one in the hls tests, recording the Range headers
+    struct FixtureServer {
+        url: String,
+        ranges: Arc<Mutex<Vec<Option<String>>>>,
+    }
+    
+    impl FixtureServer {
+        async fn start(route: Route) -> FixtureServer {
+            let ranges = Arc::new(Mutex::new(Vec::new()));
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}/track.mp3", listener.local_addr().unwrap());
+            
+            let server_ranges = ranges.clone();
+            tokio::spawn(async move {
+                loop {
+                    let (socket, _) = match listener.accept().await {
+                        Ok(conn) => conn,
+                        Err(_) => return,
+                    };
+                    let route = route.clone();
+                    let ranges = server_ranges.clone();
+                    tokio::spawn(async move {
+                        handle_connection(socket, &route, &ranges).await;
+                    });
+                }
+            });
+            
+            FixtureServer { url, ranges }
+        }
+        
+        /// Range header of every request so far, `None` for requests without one
+        fn ranges(&self) -> Vec<Option<String>> {
+            self.ranges.lock().unwrap().clone()
+        }
+    }
+    
+    async fn handle_connection(mut socket: tokio::net::TcpStream, route: &Route, ranges: &Mutex<Vec<Option<String>>>) {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+            match socket.read(&mut buf).await {
+                Ok(0) | Err(_) => return,
+                Ok(n) => request.extend_from_slice(&buf[..n]),
+            }
+        }
+        let request = String::from_utf8_lossy(&request).to_string();
+        
+        let range = request.lines()
+            .find_map(|line| line.split_once(':').filter(|(name, _)| name.eq_ignore_ascii_case("range")))
+            .map(|(_, value)| value.trim().to_string());
+        let first_request = {
+            let mut ranges = ranges.lock().unwrap();
+            ranges.push(range.clone());
+            ranges.len() == 1
+        };
+        
+        let total = route.body.len();
+        let requested = range.as_deref()
+            .and_then(|value| value.strip_prefix("bytes="))
+            .and_then(|value| value.split_once('-'))
+            .map(|(start, end)| (start.parse::<usize>().unwrap(), end.parse::<usize>().unwrap_or(total - 1)));
+        
+        let (status, mut headers, body) = match requested {
+            _ if route.status != "200 OK" => (route.status, String::new(), Vec::new()),
+            Some((start, end)) if route.honour_range => (
+                "206 Partial Content",
+                format!("Content-Range: bytes {}-{}/{}\r\n", start, end, total),
+                route.body[start..=end].to_vec()
+            ),
+            _ => ("200 OK", String::new(), route.body.clone()),
+        };
+        if route.send_length {
+            headers.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        
+        let sent = match route.drop_first_after {
+            Some(cut) if first_request => &body[..cut],
+            _ => &body[..],
+        };
+        let head = format!("HTTP/1.1 {}\r\nContent-Type: audio/mpeg\r\n{}Connection: close\r\n\r\n", status, headers);
+        let _ = socket.write_all(head.as_bytes()).await;
+        let _ = socket.write_all(sent).await;
+        let _ = socket.shutdown().await;
+    }
+    
+    /// Unique output path in the system temp directory
+    fn temp_output() -> PathBuf {
+        std::env::temp_dir().join(format!("progressive_test_{}.mp3", uuid::Uuid::new_v4()))
+    }
+    
+    fn body(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+    
+    #[tokio::test]
+    async fn downloads_whole_file() {
+        let server = FixtureServer::start(Route::new(&body(10_000))).await;
+        let output = temp_output();
+        
+        let written = download_progressive(&server.url, &output).await.unwrap();
+        
+        assert_eq!(written, 10_000);
+        assert_eq!(std::fs::read(&output).unwrap(), body(10_000));
+        assert_eq!(server.ranges(), vec![None]);
+        std::fs::remove_file(&output).unwrap();
+    }
+    
+    #[tokio::test]
+    async fn dropped_connection_resumes_with_range() {
+        let server = FixtureServer::start(Route {
+            drop_first_after: Some(4_000),
+            ..Route::new(&body(10_000))
+        }).await;
+        let output = temp_output();
+        
+        let written = download_progressive(&server.url, &output).await.unwrap();
+        
+        assert_eq!(written, 10_000);
+        assert_eq!(std::fs::read(&output).unwrap(), body(10_000));
+        assert_eq!(server.ranges(), vec![None, Some("bytes=4000-".to_string())]);
+        std::fs::remove_file(&output).unwrap();
+    }
+    
+    #[tokio::test]
+    async fn server_ignoring_range_restarts_download() {
+        let server = FixtureServer::start(Route {
+            drop_first_after: Some(4_000),
+            honour_range: false,
+            ..Route::new(&body(10_000))
+        }).await;
+        let output = temp_output();
+        
+        let written = download_progressive(&server.url, &output).await.unwrap();
+        
+        // The whole file was sent again and must not be appended to the first 4000 bytes
+        assert_eq!(written, 10_000);
+        assert_eq!(std::fs::read(&output).unwrap(), body(10_000));
+        assert_eq!(server.ranges(), vec![None, Some("bytes=4000-".to_string())]);
+        std::fs::remove_file(&output).unwrap();
+    }
+    
+    #[tokio::test]
+    async fn missing_content_length_accepts_finished_stream() {
+        let server = FixtureServer::start(Route {
+            send_length: false,
+            ..Route::new(&body(5_000))
+        }).await;
+        let output = temp_output();
+        
+        let written = download_progressive(&server.url, &output).await.unwrap();
+        
+        assert_eq!(written, 5_000);
+        assert_eq!(std::fs::read(&output).unwrap(), body(5_000));
+        std::fs::remove_file(&output).unwrap();
+    }
+    
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let server = FixtureServer::start(Route {
+            status: "403 Forbidden",
+            ..Route::new(b"")
+        }).await;
+        let output = temp_output();
+        
+        let error = download_progressive(&server.url, &output).await.unwrap_err();
+        
+        assert_eq!(error.to_string(), "HTTP error 403 Forbidden");
+        assert_eq!(server.ranges().len(), 1);
+        let _ = std::fs::remove_file(&output);
+    }
+    
+    #[tokio::test]
+    async fn empty_file_is_an_error() {
+        let server = FixtureServer::start(Route::new(b"")).await;
+        let output = temp_output();
+        
+        let error = download_progressive(&server.url, &output).await.unwrap_err();
+        
+        assert_eq!(error.to_string(), "Progressive download returned an empty file");
+        let _ = std::fs::remove_file(&output);
+    }
+    
+    #[tokio::test]
+    async fn fetch_headers_requests_only_the_first_byte() {
+        let server = FixtureServer::start(Route::new(&body(10_000))).await;
+        
+        let headers = fetch_headers(&server.url).await.unwrap();
+        
+        assert_eq!(headers.get("content-type").unwrap(), "audio/mpeg");
+        assert_eq!(headers.get("content-range").unwrap(), "bytes 0-0/10000");
+        assert_eq!(server.ranges(), vec![Some("bytes=0-0".to_string())]);
+    }
+    
+    #[test]
+    fn content_range_total_is_parsed() {
+        assert_eq!(parse_content_range_total("bytes 100-199/1000"), Some(1000));
+        assert_eq!(parse_content_range_total("bytes 0-0/ 42"), Some(42));
+        assert_eq!(parse_content_range_total("bytes 0-99/*"), None);
+    }
+}
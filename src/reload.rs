@@ -0,0 +1,339 @@
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use log::{info, debug, error, warn};
+use serde_json::Value;
+use tokio::sync::mpsc;
+use crate::config::{Config, Users};
+
+/// How often the watched files are checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Time to let an editor finish writing before a changed file is read
+const SETTLE_DELAY: Duration = Duration::from_secs(1);
+
+/// Settings that are only read on startup
+const RESTART_REQUIRED: [&str; 8] = [
+    "users_file",
+    "tracks_file",
+    "log_file",
+    "max_discord_parallelism",
+    "max_processing_parallelism",
+    "job_queue_size",
+    "adaptive_polling",
+    "schedule",
+];
+
+/// Settings whose values are never logged
+const SECRET_SETTINGS: [&str; 2] = ["discord_webhook_url", "soundcloud_oauth_token"];
+
+/// Watch files for changes by their modification time
+///
+/// Sends on `changed` when one of the files was modified. The paths are fixed,
+/// a changed `users_file` needs a restart.
+pub fn spawn_file_watcher(paths: Vec<String>, changed: mpsc::Sender<()>) {
+    tokio::spawn(async move {
+        let mut modified: Vec<Option<SystemTime>> = paths.iter().map(|p| modified_time(p)).collect();
+
+        loop {
+            tokio::time::sleep(WATCH_INTERVAL).await;
+
+            let current: Vec<Option<SystemTime>> = paths.iter().map(|p| modified_time(p)).collect();
+            if current == modified {
+                continue;
+            }
+
+            debug!("Watched files changed, reloading after {} ms", SETTLE_DELAY.as_millis());
+            tokio::time::sleep(SETTLE_DELAY).await;
+            modified = paths.iter().map(|p| modified_time(p)).collect();
+
+            if changed.send(()).await.is_err() {
+                break;
+            }
+        }
+    });
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Load the config again and log what changed
+///
+/// Returns `None` if the file is invalid or nothing changed, the running config
+/// is kept then. Settings that are only read on startup are kept at their
+/// running values, with a warning.
+pub fn reload_config(current: &Config, config_path: &str) -> Option<Config> {
+    // Editors may delete and rewrite the file, so a missing file isn't an error yet
+    if !Path::new(config_path).exists() {
+        warn!("{} is missing, keeping the running configuration", config_path);
+        return None;
+    }
+
+    let mut new_config = match Config::load(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Not reloading {}: {}", config_path, e);
+            return None;
+        }
+    };
+
+    let (old_values, new_values) = match (serde_json::to_value(current), serde_json::to_value(&new_config)) {
+        (Ok(Value::Object(old)), Ok(Value::Object(new))) => (old, new),
+        _ => {
+            error!("Not reloading {}: failed to compare configurations", config_path);
+            return None;
+        }
+    };
+
+    let mut changed = false;
+    for (key, new_value) in &new_values {
+        let old_value = old_values.get(key).unwrap_or(&Value::Null);
+        if old_value == new_value {
+            continue;
+        }
+
+        if RESTART_REQUIRED.contains(&key.as_str()) {
+            warn!("Config setting {} changed, restart to apply it", key);
+            continue;
+        }
+
+        info!("{}", change_message(key, old_value, new_value));
+        changed = true;
+    }
+
+    if !changed {
+        debug!("No config changes to apply");
+        return None;
+    }
+
+    keep_startup_settings(current, &mut new_config);
+    Some(new_config)
+}
+
+/// Log line for a changed setting, without the values of secrets
+fn change_message(key: &str, old_value: &Value, new_value: &Value) -> String {
+    if SECRET_SETTINGS.contains(&key) {
+        format!("Config setting {} changed", key)
+    } else {
+        format!("Config setting {} changed: {} -> {}", key, old_value, new_value)
+    }
+}
+
+/// Copy the settings that need a restart from the running config
+fn keep_startup_settings(current: &Config, new_config: &mut Config) {
+    new_config.users_file = current.users_file.clone();
+    new_config.tracks_file = current.tracks_file.clone();
+    new_config.log_file = current.log_file.clone();
+    new_config.max_discord_parallelism = current.max_discord_parallelism;
+    new_config.max_processing_parallelism = current.max_processing_parallelism;
+    new_config.job_queue_size = current.job_queue_size;
+    new_config.adaptive_polling = current.adaptive_polling;
+    new_config.schedule = current.schedule.clone();
+}
+
+/// Load the users file again and log the added and removed users
+///
+/// Returns `None` if the file is invalid or the list didn't change.
+pub fn reload_users(current: &Users, users_path: &str) -> Option<Users> {
+    // Users::load creates an empty list for a missing file, which would drop every user
+    if !Path::new(users_path).exists() {
+        warn!("{} is missing, keeping the watched users", users_path);
+        return None;
+    }
+
+    let new_users = match Users::load(users_path) {
+        Ok(users) => users,
+        Err(e) => {
+            error!("Not reloading {}: {}", users_path, e);
+            return None;
+        }
+    };
+
+    let added: Vec<&String> = new_users.users.iter().filter(|u| !current.users.contains(u)).collect();
+    let removed: Vec<&String> = current.users.iter().filter(|u| !new_users.users.contains(u)).collect();
+
+    let paused: Vec<&String> = new_users.paused.iter().filter(|u| !current.paused.contains(u)).collect();
+    let resumed: Vec<&String> = current.paused.iter().filter(|u| !new_users.paused.contains(u)).collect();
+
+    if added.is_empty() && removed.is_empty() && paused.is_empty() && resumed.is_empty() {
+        debug!("No user changes to apply");
+        return None;
+    }
+
+    for user_id in &added {
+        info!("Now watching user {}", user_id);
+    }
+    for user_id in &removed {
+        info!("No longer watching user {}", user_id);
+    }
+    for user_id in &paused {
+        info!("Paused user {}", user_id);
+    }
+    for user_id in &resumed {
+        info!("Resumed user {}", user_id);
+    }
+    info!("Reloaded users from {}: {} added, {} removed, {} watched, {} paused",
+          users_path, added.len(), removed.len(), new_users.users.len(), new_users.paused.len());
+
+    Some(new_users)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WEBHOOK: &str = "https://discord.com/api/webhooks/1/secret-token";
+
+    /// Path in the temp dir with a unique name, removed again by the caller
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("scarchive-reload-{}-{}", uuid::Uuid::new_v4(), name))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    fn running_config() -> Config {
+        Config {
+            discord_webhook_url: WEBHOOK.to_string(),
+            ..Config::default()
+        }
+    }
+
+    /// Write the running config with `changes` applied and reload it
+    fn reload_with(changes: Value) -> Option<Config> {
+        let current = running_config();
+        let mut json = serde_json::to_value(&current).unwrap();
+        for (key, value) in changes.as_object().unwrap() {
+            json[key] = value.clone();
+        }
+
+        let path = temp_path("config.json");
+        std::fs::write(&path, json.to_string()).unwrap();
+        let reloaded = reload_config(&current, &path);
+        std::fs::remove_file(&path).unwrap();
+        reloaded
+    }
+
+    fn users(watched: &[&str], paused: &[&str]) -> Users {
+        Users {
+            users: watched.iter().map(|u| u.to_string()).collect(),
+            paused: paused.iter().map(|u| u.to_string()).collect(),
+        }
+    }
+
+    fn reload_users_with(current: &Users, new_users: &Users) -> Option<Users> {
+        let path = temp_path("users.json");
+        std::fs::write(&path, serde_json::to_string(new_users).unwrap()).unwrap();
+        let reloaded = reload_users(current, &path);
+        std::fs::remove_file(&path).unwrap();
+        reloaded
+    }
+
+    #[test]
+    fn reload_config_applies_changed_settings() {
+        let reloaded = reload_with(serde_json::json!({"poll_interval_sec": 120, "log_level": "debug"})).unwrap();
+
+        assert_eq!(reloaded.poll_interval_sec, 120);
+        assert_eq!(reloaded.log_level, "debug");
+    }
+
+    #[test]
+    fn reload_config_ignores_unchanged_file() {
+        assert!(reload_with(serde_json::json!({})).is_none());
+    }
+
+    #[test]
+    fn reload_config_keeps_restart_only_settings() {
+        // Changing only settings that need a restart leaves nothing to apply
+        assert!(reload_with(serde_json::json!({"tracks_file": "other.json", "job_queue_size": 5})).is_none());
+
+        let changes = serde_json::json!({
+            "poll_interval_sec": 120,
+            "users_file": "other-users.json",
+            "tracks_file": "other-tracks.json",
+            "log_file": "other.log",
+            "max_discord_parallelism": 1,
+            "max_processing_parallelism": 1,
+            "job_queue_size": 5,
+            "adaptive_polling": true,
+            "schedule": {"auto_follow": 60},
+        });
+        let reloaded = serde_json::to_value(reload_with(changes).unwrap()).unwrap();
+        let current = serde_json::to_value(running_config()).unwrap();
+
+        assert_eq!(reloaded["poll_interval_sec"], 120);
+        for key in RESTART_REQUIRED {
+            assert_eq!(reloaded[key], current[key], "{} was not kept", key);
+        }
+    }
+
+    #[test]
+    fn reload_config_rejects_invalid_file() {
+        assert!(reload_with(serde_json::json!({"poll_interval_sec": 0})).is_none());
+        assert!(reload_with(serde_json::json!({"unknown_setting": 1})).is_none());
+
+        let path = temp_path("config.json");
+        std::fs::write(&path, "{\"discord_webhook_url\": ").unwrap();
+        let reloaded = reload_config(&running_config(), &path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(reloaded.is_none());
+
+        assert!(reload_config(&running_config(), &temp_path("missing.json")).is_none());
+    }
+
+    #[test]
+    fn reload_config_applies_secrets_without_logging_them() {
+        let new_webhook = "https://discord.com/api/webhooks/2/new-token";
+        let reloaded = reload_with(serde_json::json!({
+            "discord_webhook_url": new_webhook,
+            "soundcloud_oauth_token": "oauth-secret",
+        })).unwrap();
+        assert_eq!(reloaded.discord_webhook_url, new_webhook);
+        assert_eq!(reloaded.soundcloud_oauth_token.as_deref(), Some("oauth-secret"));
+
+        let message = change_message("discord_webhook_url", &Value::from(WEBHOOK), &Value::from(new_webhook));
+        assert_eq!(message, "Config setting discord_webhook_url changed");
+        let message = change_message("soundcloud_oauth_token", &Value::Null, &Value::from("oauth-secret"));
+        assert!(!message.contains("oauth-secret"));
+
+        let message = change_message("poll_interval_sec", &Value::from(60), &Value::from(120));
+        assert_eq!(message, "Config setting poll_interval_sec changed: 60 -> 120");
+    }
+
+    #[test]
+    fn reload_users_reports_added_and_removed_users() {
+        let current = users(&["1", "2"], &[]);
+        let reloaded = reload_users_with(&current, &users(&["2", "3"], &[])).unwrap();
+
+        assert_eq!(reloaded.users, vec!["2", "3"]);
+    }
+
+    #[test]
+    fn reload_users_reports_paused_and_resumed_users() {
+        let current = users(&["1", "2"], &["1"]);
+
+        let reloaded = reload_users_with(&current, &users(&["1", "2"], &["1", "2"])).unwrap();
+        assert_eq!(reloaded.paused, vec!["1", "2"]);
+
+        let reloaded = reload_users_with(&current, &users(&["1", "2"], &[])).unwrap();
+        assert!(reloaded.paused.is_empty());
+    }
+
+    #[test]
+    fn reload_users_ignores_unchanged_or_missing_file() {
+        let current = users(&["1", "2"], &["2"]);
+
+        assert!(reload_users_with(&current, &users(&["1", "2"], &["2"])).is_none());
+        assert!(reload_users(&current, &temp_path("missing.json")).is_none());
+    }
+
+    #[test]
+    fn reload_users_rejects_invalid_file() {
+        let path = temp_path("users.json");
+        std::fs::write(&path, "{\"users\": [1, ").unwrap();
+        let reloaded = reload_users(&users(&["1"], &[]), &path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(reloaded.is_none());
+    }
+}
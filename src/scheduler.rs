@@ -0,0 +1,547 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+use std::time::Duration;
+use log::{info, debug, error, trace};
+use rand::Rng;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use tokio::time::Instant;
+use crate::config::Config;
+use crate::soundcloud::Track;
+
+/// When a scheduled job runs
+///
+/// In the config, a number is an interval in seconds and a string is a cron
+/// expression, either with 5 fields ("*/30 * * * *") or with a leading seconds
+/// field ("0 */30 * * * *"). Cron expressions are evaluated in UTC.
+#[derive(Debug, Clone)]
+pub enum JobSchedule {
+    Every(Duration),
+    Cron(Box<cron::Schedule>),
+}
+
+impl JobSchedule {
+    /// Time until the next run, counted from now
+    pub fn next_delay(&self) -> Duration {
+        match self {
+            JobSchedule::Every(interval) => *interval,
+            JobSchedule::Cron(schedule) => {
+                let now = chrono::Utc::now();
+                schedule.after(&now).next()
+                    .and_then(|next| (next - now).to_std().ok())
+                    // An expression without future runs, e.g. a fixed past year
+                    .unwrap_or(Duration::MAX)
+            }
+        }
+    }
+
+    /// Instant of the next run, counted from now
+    pub fn next_run(&self) -> Instant {
+        let now = Instant::now();
+        now.checked_add(self.next_delay())
+            // Far enough in the future to never fire
+            .unwrap_or_else(|| now + Duration::from_secs(100 * 365 * 86400))
+    }
+}
+
+impl FromStr for JobSchedule {
+    type Err = String;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let expression = expression.trim();
+        // A plain number is an interval, as in the config file, e.g. from an environment variable
+        if let Ok(seconds) = expression.parse::<u64>() {
+            return match seconds {
+                0 => Err("interval must be a positive number of seconds".to_string()),
+                seconds => Ok(JobSchedule::Every(Duration::from_secs(seconds))),
+            };
+        }
+        // The cron crate wants a seconds field, standard 5-field expressions run at second 0
+        let full = if expression.split_whitespace().count() == 5 {
+            format!("0 {}", expression)
+        } else {
+            expression.to_string()
+        };
+
+        cron::Schedule::from_str(&full)
+            .map(|schedule| JobSchedule::Cron(Box::new(schedule)))
+            .map_err(|e| format!("invalid cron expression '{}': {}", expression, e))
+    }
+}
+
+impl fmt::Display for JobSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobSchedule::Every(interval) => write!(f, "every {} seconds", interval.as_secs()),
+            JobSchedule::Cron(schedule) => write!(f, "cron '{}'", schedule.source()),
+        }
+    }
+}
+
+impl Serialize for JobSchedule {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            JobSchedule::Every(interval) => serializer.serialize_u64(interval.as_secs()),
+            JobSchedule::Cron(schedule) => serializer.serialize_str(schedule.source()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for JobSchedule {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        match Value::deserialize(deserializer)? {
+            Value::Number(seconds) => match seconds.as_u64() {
+                Some(0) | None => Err(D::Error::custom("interval must be a positive number of seconds")),
+                Some(seconds) => Ok(JobSchedule::Every(Duration::from_secs(seconds))),
+            },
+            Value::String(expression) => expression.parse().map_err(D::Error::custom),
+            other => Err(D::Error::custom(format!(
+                "expected an interval in seconds or a cron expression, got {}", other))),
+        }
+    }
+}
+
+/// Serde for the schedule of a job that can be disabled
+///
+/// TOML has no `null`, so a disabled job is written as `false`. Both `false` and
+/// `null` are read as disabled.
+mod optional_schedule {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use serde_json::Value;
+    use super::JobSchedule;
+
+    pub fn serialize<S: Serializer>(schedule: &Option<JobSchedule>, serializer: S) -> Result<S::Ok, S::Error> {
+        match schedule {
+            Some(schedule) => schedule.serialize(serializer),
+            None => serializer.serialize_bool(false),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<JobSchedule>, D::Error> {
+        use serde::de::Error;
+
+        match Value::deserialize(deserializer)? {
+            Value::Null | Value::Bool(false) => Ok(None),
+            value => serde_json::from_value(value).map(Some).map_err(D::Error::custom),
+        }
+    }
+}
+
+/// Schedules of the background jobs, `false` or `null` disables a job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleConfig {
+    /// Polling all watched users, defaults to `poll_interval_sec`
+    /// (not used with `adaptive_polling`, which schedules every user on its own)
+    #[serde(default)]
+    pub poll: Option<JobSchedule>,
+    /// Checking the auto-follow source for new followings
+    #[serde(default = "default_auto_follow_schedule", with = "optional_schedule")]
+    pub auto_follow: Option<JobSchedule>,
+    /// Checking archived tracks for deletion from SoundCloud
+    #[serde(default, with = "optional_schedule")]
+    pub deletion_sweep: Option<JobSchedule>,
+    /// Updating posted embeds with the current track metadata
+    #[serde(default, with = "optional_schedule")]
+    pub embed_refresh: Option<JobSchedule>,
+    /// Dropping finished jobs from the tracks database and saving it
+    #[serde(default = "default_db_compaction_schedule", with = "optional_schedule")]
+    pub db_compaction: Option<JobSchedule>,
+    /// Queueing failed tracks whose retry delay has passed, whichever user found them
+    #[serde(default = "default_retry_schedule", with = "optional_schedule")]
+    pub retry: Option<JobSchedule>,
+    /// How many tracks one deletion sweep or embed refresh run checks
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+}
+
+fn default_auto_follow_schedule() -> Option<JobSchedule> {
+    Some(JobSchedule::Every(Duration::from_secs(3600)))
+}
+
+fn default_db_compaction_schedule() -> Option<JobSchedule> {
+    Some(JobSchedule::Every(Duration::from_secs(3600)))
+}
+
+fn default_retry_schedule() -> Option<JobSchedule> {
+    Some(JobSchedule::Every(Duration::from_secs(60)))
+}
+
+fn default_batch_size() -> usize {
+    50
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        ScheduleConfig {
+            poll: None,
+            auto_follow: default_auto_follow_schedule(),
+            deletion_sweep: None,
+            embed_refresh: None,
+            db_compaction: default_db_compaction_schedule(),
+            retry: default_retry_schedule(),
+            batch_size: default_batch_size(),
+        }
+    }
+}
+
+/// Run a job on its schedule in the background
+///
+/// The next run is planned when the previous one has finished, so runs of the
+/// same job never overlap. Jobs don't run on startup, only at their first
+/// scheduled time.
+pub fn spawn_job<F, Fut>(name: &'static str, schedule: JobSchedule, mut job: F)
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send,
+{
+    info!("Scheduled {} job {}", name, schedule);
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep_until(schedule.next_run()).await;
+
+            debug!("Running scheduled {} job", name);
+            if let Err(e) = job().await {
+                error!("Scheduled {} job failed: {}", name, e);
+                crate::loghandler::increment_error_count();
+            }
+        }
+    });
+}
+
+/// Number of recent uploads used to work out a user's upload cadence
+const CADENCE_SAMPLE: usize = 10;
+
+/// How many polls to spread over a user's typical gap between uploads, e.g. every 15 minutes for daily uploads
+const POLLS_PER_GAP: i64 = 96;
+
+/// Upload cadence of a user, from the `created_at` of their uploads
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UploadActivity {
+    /// Unix timestamp of the newest upload
+    pub last_upload: Option<i64>,
+    /// Median gap in seconds between the recent uploads
+    pub typical_gap: Option<i64>,
+}
+
+impl UploadActivity {
+    /// Work out the cadence from a user's uploads (not their likes)
+    pub fn from_tracks(tracks: &[Track]) -> UploadActivity {
+        let mut uploads: Vec<i64> = tracks.iter()
+            .filter_map(|track| parse_created_at(&track.created_at))
+            .collect();
+        uploads.sort_unstable_by(|a, b| b.cmp(a));
+        uploads.truncate(CADENCE_SAMPLE);
+
+        let mut gaps: Vec<i64> = uploads.windows(2)
+            .map(|pair| pair[0] - pair[1])
+            .collect();
+        gaps.sort_unstable();
+
+        UploadActivity {
+            last_upload: uploads.first().copied(),
+            typical_gap: gaps.get(gaps.len() / 2).copied(),
+        }
+    }
+
+    /// Seconds until the next poll, before clamping and jitter
+    ///
+    /// A user is expected to upload again after their typical gap, or after the time
+    /// since their last upload if they have been quiet for longer than that. The
+    /// expected wait is split into `POLLS_PER_GAP` polls.
+    fn expected_interval(&self, now: i64) -> Option<i64> {
+        let since_last = now - self.last_upload?;
+        let expected = match self.typical_gap {
+            Some(gap) => gap.max(since_last),
+            None => since_last,
+        };
+        Some(expected / POLLS_PER_GAP)
+    }
+}
+
+/// Parse a SoundCloud `created_at`, e.g. "2024-05-01T12:00:00Z" or "2024/05/01 12:00:00 +0000"
+pub fn parse_created_at(created_at: &str) -> Option<i64> {
+    chrono::DateTime::parse_from_rfc3339(created_at)
+        .or_else(|_| chrono::DateTime::parse_from_str(created_at, "%Y/%m/%d %H:%M:%S %z"))
+        .ok()
+        .map(|time| time.timestamp())
+}
+
+/// Time until a user should be polled again with `adaptive_polling`
+///
+/// When the poll failed and there is no activity, this is `poll_interval_sec`.
+/// Otherwise the interval follows the user's upload cadence between
+/// `min_poll_interval_sec` and `max_poll_interval_sec`, with up to
+/// `poll_jitter_percent` random jitter so users don't end up polled in lockstep.
+pub fn poll_interval(activity: Option<&UploadActivity>, config: &Config) -> Duration {
+    let activity = match activity {
+        Some(activity) => activity,
+        None => return Duration::from_secs(config.poll_interval_sec),
+    };
+
+    let now = chrono::Utc::now().timestamp();
+    let interval = match activity.expected_interval(now) {
+        Some(interval) => interval.max(0) as u64,
+        // Users without uploads only matter for their likes
+        None => config.max_poll_interval_sec,
+    };
+    let interval = interval.clamp(config.min_poll_interval_sec, config.max_poll_interval_sec.max(config.min_poll_interval_sec));
+
+    let jitter = interval as f64 * config.poll_jitter_percent.min(100) as f64 / 100.0;
+    let interval = if jitter > 0.0 {
+        interval as f64 + rand::rng().random_range(-jitter..=jitter)
+    } else {
+        interval as f64
+    };
+
+    Duration::from_secs_f64(interval.max(1.0))
+}
+
+/// When each watched user is polled next with `adaptive_polling`
+#[derive(Debug, Default)]
+pub struct PollScheduler {
+    next_poll: HashMap<String, Instant>,
+}
+
+impl PollScheduler {
+    pub fn new() -> Self {
+        PollScheduler::default()
+    }
+
+    /// Users whose next poll is due, including users that were never polled
+    pub fn due_users(&self, users: &[String]) -> Vec<String> {
+        let now = Instant::now();
+        users.iter()
+            .filter(|user_id| self.next_poll.get(*user_id).is_none_or(|at| *at <= now))
+            .cloned()
+            .collect()
+    }
+
+    /// Schedule a user's next poll after a poll finished
+    pub fn schedule(&mut self, user_id: &str, activity: Option<&UploadActivity>, config: &Config) {
+        let interval = poll_interval(activity, config);
+        trace!("Next poll of user {} in {} seconds", user_id, interval.as_secs());
+        self.next_poll.insert(user_id.to_string(), Instant::now() + interval);
+    }
+
+    /// Earliest next poll of the given users
+    ///
+    /// Users that were never polled are due now. Without users, this is one
+    /// `poll_interval_sec` from now.
+    pub fn next_wakeup(&self, users: &[String], config: &Config) -> Instant {
+        let now = Instant::now();
+        users.iter()
+            .map(|user_id| self.next_poll.get(user_id).copied().unwrap_or(now))
+            .min()
+            .unwrap_or(now + Duration::from_secs(config.poll_interval_sec))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::soundcloud::TrackUser;
+
+    fn track(id: &str, created_at: &str) -> Track {
+        Track {
+            id: id.to_string(),
+            title: format!("Track {}", id),
+            permalink_url: format!("https://soundcloud.com/user/track-{}", id),
+            artwork_url: None,
+            description: None,
+            user: TrackUser {
+                id: "1".to_string(),
+                username: "user".to_string(),
+                permalink_url: "https://soundcloud.com/user".to_string(),
+                avatar_url: None,
+            },
+            created_at: created_at.to_string(),
+            duration: 180_000,
+            stream_url: None,
+            hls_url: None,
+            download_url: None,
+            playback_count: None,
+            likes_count: None,
+            reposts_count: None,
+            comment_count: None,
+            genre: None,
+            tag_list: None,
+            downloadable: None,
+            secret_token: None,
+            raw_data: None,
+        }
+    }
+
+    /// Config with fixed (jitter-free) adaptive poll intervals
+    fn polling_config(min: u64, max: u64) -> Config {
+        Config {
+            poll_interval_sec: 60,
+            min_poll_interval_sec: min,
+            max_poll_interval_sec: max,
+            poll_jitter_percent: 0,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn job_schedule_parses_intervals() {
+        match "90".parse::<JobSchedule>() {
+            Ok(JobSchedule::Every(interval)) => assert_eq!(interval, Duration::from_secs(90)),
+            other => panic!("expected an interval, got {:?}", other),
+        }
+        assert!(" 3600 ".parse::<JobSchedule>().is_ok());
+        assert!("0".parse::<JobSchedule>().unwrap_err().contains("positive"));
+    }
+
+    #[test]
+    fn job_schedule_parses_cron_expressions() {
+        // Five fields run at second 0, six fields are used as they are
+        match "*/30 * * * *".parse::<JobSchedule>() {
+            Ok(JobSchedule::Cron(schedule)) => assert_eq!(schedule.source(), "0 */30 * * * *"),
+            other => panic!("expected a cron schedule, got {:?}", other),
+        }
+        match "15 0 3 * * *".parse::<JobSchedule>() {
+            Ok(JobSchedule::Cron(schedule)) => assert_eq!(schedule.source(), "15 0 3 * * *"),
+            other => panic!("expected a cron schedule, got {:?}", other),
+        }
+
+        let delay = "* * * * *".parse::<JobSchedule>().unwrap().next_delay();
+        assert!(delay <= Duration::from_secs(60));
+    }
+
+    #[test]
+    fn job_schedule_rejects_invalid_expressions() {
+        for expression in ["", "every hour", "61 * * * *", "-5"] {
+            let error = expression.parse::<JobSchedule>().unwrap_err();
+            assert!(error.contains("invalid cron expression"), "unexpected error for '{}': {}", expression, error);
+        }
+    }
+
+    #[test]
+    fn job_schedule_round_trips_through_serde() {
+        let interval: JobSchedule = serde_json::from_str("600").unwrap();
+        assert_eq!(serde_json::to_string(&interval).unwrap(), "600");
+
+        let cron: JobSchedule = serde_json::from_str("\"0 0 * * *\"").unwrap();
+        assert_eq!(serde_json::to_string(&cron).unwrap(), "\"0 0 0 * * *\"");
+
+        assert!(serde_json::from_str::<JobSchedule>("0").is_err());
+        assert!(serde_json::from_str::<JobSchedule>("true").is_err());
+    }
+
+    #[test]
+    fn upload_activity_uses_newest_upload_and_median_gap() {
+        // Out of order and in both formats SoundCloud uses; the gaps are 1, 2 and 10 days
+        let tracks = vec![
+            track("2", "2024/01/02 00:00:00 +0000"),
+            track("4", "2024-01-14T00:00:00Z"),
+            track("1", "2024-01-01T00:00:00Z"),
+            track("3", "2024-01-04T00:00:00Z"),
+            track("bad", "not a date"),
+        ];
+
+        let activity = UploadActivity::from_tracks(&tracks);
+
+        assert_eq!(activity.last_upload, parse_created_at("2024-01-14T00:00:00Z"));
+        assert_eq!(activity.typical_gap, Some(2 * 86400));
+    }
+
+    #[test]
+    fn upload_activity_only_looks_at_recent_uploads() {
+        // Ten daily uploads, preceded by old ones a year apart that must not count
+        let mut tracks: Vec<Track> = (1..=10)
+            .map(|day| track(&day.to_string(), &format!("2024-03-{:02}T12:00:00Z", day)))
+            .collect();
+        tracks.push(track("old1", "2022-01-01T00:00:00Z"));
+        tracks.push(track("old2", "2023-01-01T00:00:00Z"));
+
+        let activity = UploadActivity::from_tracks(&tracks);
+
+        assert_eq!(activity.typical_gap, Some(86400));
+    }
+
+    #[test]
+    fn upload_activity_without_uploads_is_empty() {
+        assert_eq!(UploadActivity::from_tracks(&[]), UploadActivity::default());
+
+        let single = UploadActivity::from_tracks(&[track("1", "2024-01-01T00:00:00Z")]);
+        assert!(single.last_upload.is_some());
+        assert_eq!(single.typical_gap, None);
+    }
+
+    #[test]
+    fn poll_interval_follows_cadence_within_bounds() {
+        let config = polling_config(60, 21600);
+        let now = chrono::Utc::now().timestamp();
+
+        // Daily uploader who just uploaded: one day split into POLLS_PER_GAP polls
+        let daily = UploadActivity { last_upload: Some(now), typical_gap: Some(86400) };
+        assert_eq!(poll_interval(Some(&daily), &config), Duration::from_secs(86400 / POLLS_PER_GAP as u64));
+
+        // Very active users are clamped to the minimum, dormant ones to the maximum
+        let hourly = UploadActivity { last_upload: Some(now), typical_gap: Some(3600) };
+        assert_eq!(poll_interval(Some(&hourly), &config), Duration::from_secs(60));
+        let dormant = UploadActivity { last_upload: Some(now - 365 * 86400), typical_gap: Some(86400) };
+        assert_eq!(poll_interval(Some(&dormant), &config), Duration::from_secs(21600));
+
+        // No uploads at all, and failed polls
+        assert_eq!(poll_interval(Some(&UploadActivity::default()), &config), Duration::from_secs(21600));
+        assert_eq!(poll_interval(None, &config), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn poll_interval_jitter_stays_within_percentage() {
+        let config = Config { poll_jitter_percent: 10, ..polling_config(1000, 1000) };
+        let activity = UploadActivity { last_upload: Some(chrono::Utc::now().timestamp()), typical_gap: Some(86400) };
+
+        for _ in 0..100 {
+            let interval = poll_interval(Some(&activity), &config).as_secs_f64();
+            assert!((900.0..=1100.0).contains(&interval), "interval {} outside jitter range", interval);
+        }
+    }
+
+    #[test]
+    fn poll_scheduler_polls_new_users_right_away() {
+        let config = polling_config(3600, 3600);
+        let users = vec!["a".to_string(), "b".to_string()];
+        let scheduler = PollScheduler::new();
+
+        assert_eq!(scheduler.due_users(&users), users);
+        assert!(scheduler.next_wakeup(&users, &config) <= Instant::now());
+    }
+
+    #[test]
+    fn poll_scheduler_waits_for_scheduled_users() {
+        let config = polling_config(3600, 3600);
+        let users = vec!["a".to_string(), "b".to_string()];
+        let activity = UploadActivity { last_upload: Some(chrono::Utc::now().timestamp()), typical_gap: Some(86400) };
+        let mut scheduler = PollScheduler::new();
+
+        let before = Instant::now();
+        scheduler.schedule("a", Some(&activity), &config);
+        assert_eq!(scheduler.due_users(&users), vec!["b".to_string()]);
+
+        scheduler.schedule("b", None, &config);
+        assert!(scheduler.due_users(&users).is_empty());
+
+        // "b" failed and is retried after poll_interval_sec, "a" follows its cadence
+        let wakeup = scheduler.next_wakeup(&users, &config);
+        assert!(wakeup >= before + Duration::from_secs(60));
+        assert!(wakeup < before + Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn poll_scheduler_without_users_wakes_up_after_poll_interval() {
+        let config = polling_config(3600, 3600);
+        let before = Instant::now();
+
+        let wakeup = PollScheduler::new().next_wakeup(&[], &config);
+
+        assert!(wakeup >= before + Duration::from_secs(config.poll_interval_sec));
+    }
+}
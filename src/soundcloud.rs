@@ -11,6 +11,8 @@ use std::sync::Arc;
 // Global client ID cache
 lazy_static::lazy_static! {
     static ref CLIENT_ID: Mutex<Option<String>> = Mutex::new(None);
+    // OAuth token of a logged-in SoundCloud session (unlocks Go+ and private tracks)
+    static ref OAUTH_TOKEN: Mutex<Option<String>> = Mutex::new(None);
     static ref HTTP_CLIENT: Client = Client::builder()
         .timeout(Duration::from_secs(30))
         .pool_max_idle_per_host(10)
@@ -44,6 +46,10 @@ pub struct Track {
     pub genre: Option<String>,
     pub tag_list: Option<String>,
     pub downloadable: Option<bool>,
+    // Share token for private tracks, needed on every API call for them.
+    // Never serialized so it can't leak into the archived JSON snapshot.
+    #[serde(skip)]
+    pub secret_token: Option<String>,
     // Raw JSON data
     #[serde(skip)]
     pub raw_data: Option<Value>,
@@ -85,6 +91,45 @@ pub fn get_client_id() -> Option<String> {
     client_id.clone()
 }
 
+/// Set the OAuth token used to authenticate API requests
+///
+/// With a token from a logged-in session, SoundCloud also serves Go+ (HQ) transcodings
+/// and tracks shared through private links. The token itself is never logged.
+pub fn set_oauth_token(token: Option<String>) {
+    let token = token.filter(|t| !t.trim().is_empty());
+    if token.is_some() {
+        info!("Using authenticated SoundCloud session");
+    } else {
+        debug!("No SoundCloud OAuth token configured, using anonymous access");
+    }
+    
+    let mut oauth_token = OAUTH_TOKEN.lock().unwrap();
+    *oauth_token = token;
+}
+
+/// Check whether an OAuth token is configured
+pub fn has_oauth_token() -> bool {
+    OAUTH_TOKEN.lock().unwrap().is_some()
+}
+
+/// Attach the Authorization header to an API request if an OAuth token is configured
+fn with_auth(request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    let token = OAUTH_TOKEN.lock().unwrap().clone();
+    match token {
+        Some(token) => request.header("Authorization", format!("OAuth {}", token)),
+        None => request,
+    }
+}
+
+/// Append the secret token of a private track to an API URL
+fn with_secret_token(url: String, secret_token: Option<&str>) -> String {
+    match secret_token {
+        Some(token) if url.contains('?') => format!("{}&secret_token={}", url, token),
+        Some(token) => format!("{}?secret_token={}", url, token),
+        None => url,
+    }
+}
+
 /// Refresh the SoundCloud client ID
 pub async fn refresh_client_id() -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
     let new_id = generate_client_id().await?;
@@ -211,12 +256,6 @@ pub async fn get_user_tracks(
         },
     };
     
-    // Try to fetch all tracks in one go with a large limit
-    let url = format!(
-        "https://api-v2.soundcloud.com/users/{}/tracks?client_id={}&limit={}&linked_partitioning=1",
-        user_id, client_id, effective_limit
-    );
-    
     debug!("Attempting to fetch all {} tracks in one request", effective_limit);
     
     // Make the request with retry logic
@@ -230,7 +269,13 @@ pub async fn get_user_tracks(
             sleep(Duration::from_secs(2 * retry as u64)).await;
         }
         
-        let response = match client.get(&url).send().await {
+        // Try to fetch all tracks in one go with a large limit
+        let url = format!(
+            "https://api-v2.soundcloud.com/users/{}/tracks?client_id={}&limit={}&linked_partitioning=1",
+            user_id, client_id, effective_limit
+        );
+        
+        let response = match with_auth(client.get(&url)).send().await {
             Ok(res) => {
                 if !res.status().is_success() {
                     // Check for auth error and refresh client ID
@@ -347,6 +392,7 @@ pub async fn get_user_tracks(
                 genre: track_json.get("genre").and_then(Value::as_str).map(String::from),
                 tag_list: track_json.get("tag_list").and_then(Value::as_str).map(String::from),
                 downloadable: track_json.get("downloadable").and_then(Value::as_bool),
+                secret_token: track_json.get("secret_token").and_then(Value::as_str).map(String::from),
                 raw_data: Some(track_json.clone()),
             };
             tracks.push(track);
@@ -363,7 +409,7 @@ pub async fn get_user_tracks(
 }
 
 /// Get user details from SoundCloud
-async fn get_user_details(user_id: &str) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+pub async fn get_user_details(user_id: &str) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
     let client = &HTTP_CLIENT;
     
     // Get the current client ID or refresh it
@@ -373,10 +419,6 @@ async fn get_user_details(user_id: &str) -> Result<Value, Box<dyn std::error::Er
     };
     
     let max_retries = 3;
-    let url = format!(
-        "https://api-v2.soundcloud.com/users/{}?client_id={}",
-        user_id, client_id
-    );
     
     debug!("Fetching user details for user ID: {}", user_id);
     
@@ -387,7 +429,12 @@ async fn get_user_details(user_id: &str) -> Result<Value, Box<dyn std::error::Er
             sleep(Duration::from_secs(2 * retry as u64)).await;
         }
         
-        let response = match client.get(&url).send().await {
+        let url = format!(
+            "https://api-v2.soundcloud.com/users/{}?client_id={}",
+            user_id, client_id
+        );
+        
+        let response = match with_auth(client.get(&url)).send().await {
             Ok(res) => {
                 if !res.status().is_success() {
                     // Check for auth error and refresh client ID
@@ -443,7 +490,7 @@ fn parse_track_user(track_json: &Value) -> TrackUser {
                 .to_string(),
             avatar_url: user.get("avatar_url")
                 .and_then(Value::as_str)
-            .map(|url| get_original_artwork_url(url)),
+                .map(get_original_artwork_url),
         }
     } else {
         // Default user if not found
@@ -459,6 +506,54 @@ fn parse_track_user(track_json: &Value) -> TrackUser {
 /// Get detailed information for a track including stream URLs
 pub async fn get_track_details(
     track_id: &str
+) -> Result<Track, Box<dyn std::error::Error + Send + Sync>> {
+    get_track_details_with_secret(track_id, None).await
+}
+
+/// Check whether a track is still available on SoundCloud
+/// 
+/// Returns false only when SoundCloud answers 404 (deleted or made private). Private tracks
+/// need their `secret_token`, without it they look deleted. Other failures, including
+/// 401/403 that a fresh client ID doesn't fix, are errors: the track's state is unknown.
+pub async fn track_exists(
+    track_id: &str,
+    secret_token: Option<&str>
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let client = &HTTP_CLIENT;
+    
+    let mut client_id = match get_client_id() {
+        Some(id) => id,
+        None => refresh_client_id().await?,
+    };
+    
+    // One retry with a fresh client ID on auth errors
+    for _ in 0..2 {
+        let url = with_secret_token(
+            format!("https://api-v2.soundcloud.com/tracks/{}?client_id={}", track_id, client_id),
+            secret_token
+        );
+        let response = with_auth(client.get(&url)).send().await
+            .map_err(|e| e.without_url())?;
+        
+        match response.status().as_u16() {
+            200..=299 => return Ok(true),
+            404 => return Ok(false),
+            401 | 403 => {
+                warn!("Auth error ({}), refreshing client ID", response.status());
+                client_id = refresh_client_id().await?;
+            },
+            status => return Err(format!("API error: HTTP {} for track {}", status, track_id).into()),
+        }
+    }
+    
+    // Access denied says nothing about whether the track still exists
+    Err(format!("Failed to check track {}: access denied, state unknown", track_id).into())
+}
+
+/// Get detailed information for a track, passing the secret token for private tracks
+pub async fn get_track_details_with_secret(
+    track_id: &str,
+    secret_token: Option<&str>
 ) -> Result<Track, Box<dyn std::error::Error + Send + Sync>> {
     let client = &HTTP_CLIENT;
     
@@ -478,12 +573,12 @@ pub async fn get_track_details(
             sleep(Duration::from_secs(2 * retry as u64)).await;
         }
         
-        let url = format!(
+        let url = with_secret_token(format!(
             "https://api-v2.soundcloud.com/tracks/{}?client_id={}",
             track_id, client_id
-        );
+        ), secret_token);
         
-        let response = match client.get(&url).send().await {
+        let response = match with_auth(client.get(&url)).send().await {
             Ok(res) => {
                 if !res.status().is_success() {
                     // Check for auth error and refresh client ID
@@ -534,7 +629,7 @@ pub async fn get_track_details(
             .to_string(),
         artwork_url: json.get("artwork_url")
             .and_then(Value::as_str)
-            .map(|url| get_original_artwork_url(url)),
+            .map(get_original_artwork_url),
         description: json.get("description")
             .and_then(Value::as_str)
             .map(String::from),
@@ -562,6 +657,10 @@ pub async fn get_track_details(
         genre: json.get("genre").and_then(Value::as_str).map(String::from),
         tag_list: json.get("tag_list").and_then(Value::as_str).map(String::from),
         downloadable: json.get("downloadable").and_then(Value::as_bool),
+        secret_token: json.get("secret_token")
+            .and_then(Value::as_str)
+            .map(String::from)
+            .or_else(|| secret_token.map(String::from)),
         raw_data: Some(json.clone()),
     };
     
@@ -590,7 +689,12 @@ pub async fn get_track_details(
 }
 
 /// Resolve the actual download/stream URL for a track
-pub async fn get_stream_url(url: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+///
+/// `secret_token` must be passed for tracks only reachable through a private share link.
+pub async fn get_stream_url(
+    url: &str,
+    secret_token: Option<&str>
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
     let client = &HTTP_CLIENT;
     
     // Get the current client ID or refresh it
@@ -599,14 +703,19 @@ pub async fn get_stream_url(url: &str) -> Result<String, Box<dyn std::error::Err
         None => refresh_client_id().await?,
     };
     
-    // Add client_id to URL
+    // Add client_id (and secret token for private tracks) to URL
     let full_url = if url.contains('?') {
         format!("{}&client_id={}", url, client_id)
     } else {
         format!("{}?client_id={}", url, client_id)
     };
+    let full_url = with_secret_token(full_url, secret_token);
     
-    let response = client.get(&full_url).send().await?;
+    // Strip the URL from request errors, it may contain a secret token
+    let response = with_auth(client.get(&full_url))
+        .send()
+        .await
+        .map_err(|e| e.without_url())?;
     
     if !response.status().is_success() {
         return Err(format!("HTTP error {}", response.status()).into());
@@ -617,14 +726,58 @@ pub async fn get_stream_url(url: &str) -> Result<String, Box<dyn std::error::Err
         url: String,
     }
     
-    let stream_response: StreamResponse = response.json().await?;
+    let stream_response: StreamResponse = response.json().await.map_err(|e| e.without_url())?;
     Ok(stream_response.url)
 }
 
+/// Resolve the download link of the original uploaded file for a downloadable track
+///
+/// Only works when the uploader enabled downloads. The returned URL points to the
+/// original file (often WAV or FLAC) and is only valid for a short time.
+pub async fn get_original_download_url(
+    track_id: &str,
+    secret_token: Option<&str>
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let client = &HTTP_CLIENT;
+    
+    // Get the current client ID or refresh it
+    let client_id = match get_client_id() {
+        Some(id) => id,
+        None => refresh_client_id().await?,
+    };
+    
+    let url = with_secret_token(format!(
+        "https://api-v2.soundcloud.com/tracks/{}/download?client_id={}",
+        track_id, client_id
+    ), secret_token);
+    
+    debug!("Requesting original download link for track {}", track_id);
+    let response = with_auth(client.get(&url))
+        .send()
+        .await
+        .map_err(|e| e.without_url())?;
+    
+    if !response.status().is_success() {
+        return Err(format!("HTTP error {}", response.status()).into());
+    }
+    
+    #[derive(Deserialize)]
+    struct DownloadResponse {
+        #[serde(rename = "redirectUri")]
+        redirect_uri: String,
+    }
+    
+    let download_response: DownloadResponse = response.json().await.map_err(|e| e.without_url())?;
+    Ok(download_response.redirect_uri)
+}
+
 /// Resolve a SoundCloud URL to a track/user ID
 pub async fn resolve_url(url: &str) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
     let client = &HTTP_CLIENT;
     
+    // Private share links carry their token in the URL, keep it out of the logs
+    let display_url = redact_url(url);
+    
     // Get the current client ID or refresh it
     let mut client_id = match get_client_id() {
         Some(id) => id,
@@ -636,7 +789,7 @@ pub async fn resolve_url(url: &str) -> Result<Value, Box<dyn std::error::Error +
     for retry in 0..max_retries {
         if retry > 0 {
             debug!("Retrying URL resolution (attempt {}/{}) for {}", 
-                  retry + 1, max_retries, url);
+                  retry + 1, max_retries, display_url);
             sleep(Duration::from_secs(2 * retry as u64)).await;
         }
         
@@ -645,7 +798,7 @@ pub async fn resolve_url(url: &str) -> Result<Value, Box<dyn std::error::Error +
             url, client_id
         );
         
-        let response = match client.get(&resolve_url).send().await {
+        let response = match with_auth(client.get(&resolve_url)).send().await {
             Ok(res) => {
                 if !res.status().is_success() {
                     // Check for auth error and refresh client ID
@@ -655,24 +808,24 @@ pub async fn resolve_url(url: &str) -> Result<Value, Box<dyn std::error::Error +
                         continue;
                     }
                     
-                    warn!("API error: HTTP {} for URL {}", res.status(), url);
+                    warn!("API error: HTTP {} for URL {}", res.status(), display_url);
                     continue;
                 }
                 res
             }
             Err(e) => {
-                warn!("Request error for URL {}: {}", url, e);
+                warn!("Request error for URL {}: {}", display_url, e.without_url());
                 continue;
             }
         };
         
         match response.json::<Value>().await {
             Ok(json) => {
-                info!("Successfully resolved URL: {}", url);
+                info!("Successfully resolved URL: {}", display_url);
                 return Ok(json);
             }
             Err(e) => {
-                warn!("JSON parse error for URL {}: {}", url, e);
+                warn!("JSON parse error for URL {}: {}", display_url, e);
                 if retry == max_retries - 1 {
                     return Err(format!("Failed to parse JSON after {} retries", max_retries).into());
                 }
@@ -680,7 +833,61 @@ pub async fn resolve_url(url: &str) -> Result<Value, Box<dyn std::error::Error +
         }
     }
     
-    Err(format!("Failed to resolve URL {} after {} retries", url, max_retries).into())
+    Err(format!("Failed to resolve URL {} after {} retries", display_url, max_retries).into())
+}
+
+/// Resolve a user ID, profile URL or permalink (e.g. "artist-name") to the user's details
+///
+/// Fails if a URL points to something other than a user, e.g. a track or playlist.
+pub async fn resolve_user(input: &str) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+    let input = input.trim();
+    if !input.is_empty() && input.chars().all(|c| c.is_ascii_digit()) {
+        return get_user_details(input).await;
+    }
+
+    let url = if input.starts_with("http") {
+        input.to_string()
+    } else if input.contains("soundcloud.com") {
+        format!("https://{}", input)
+    } else {
+        format!("https://soundcloud.com/{}", input.trim_start_matches('/'))
+    };
+
+    let data = resolve_url(&url).await?;
+    match data.get("kind").and_then(Value::as_str) {
+        Some("user") => Ok(data),
+        Some(kind) => Err(format!("{} is a {}, not a user", redact_url(&url), kind).into()),
+        None => Err(format!("{} did not resolve to a user", redact_url(&url)).into()),
+    }
+}
+
+/// Hide secret tokens of private share links in a URL before it is logged
+///
+/// Example: https://soundcloud.com/artist/track/s-AbCdEf → https://soundcloud.com/artist/track/s-[redacted]
+pub fn redact_url(url: &str) -> String {
+    let (path, query) = match url.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (url, None),
+    };
+    
+    let path = path.split('/')
+        .map(|segment| if segment.starts_with("s-") && segment.len() > 2 { "s-[redacted]" } else { segment })
+        .collect::<Vec<&str>>()
+        .join("/");
+    
+    match query {
+        Some(query) => {
+            let query = query.split('&')
+                .map(|param| match param.split_once('=') {
+                    Some(("secret_token", _)) => "secret_token=[redacted]".to_string(),
+                    _ => param.to_string(),
+                })
+                .collect::<Vec<String>>()
+                .join("&");
+            format!("{}?{}", path, query)
+        },
+        None => path,
+    }
 }
 
 /// Convert artwork URL to get the original high-resolution version
@@ -700,6 +907,43 @@ pub fn get_original_artwork_url(artwork_url: &str) -> String {
     artwork_url.to_string()
 }
 
+/// Get the original-size URL of a user's profile banner ("visuals"), if they have one
+///
+/// The banner is only part of the full user object, not of the user embedded in tracks.
+pub async fn get_user_banner_url(user_id: &str) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    if user_id.is_empty() {
+        return Ok(None);
+    }
+    
+    let user_data = get_user_details(user_id).await?;
+    
+    let banner_url = user_data.get("visuals")
+        .and_then(|v| v.get("visuals"))
+        .and_then(Value::as_array)
+        .and_then(|visuals| visuals.first())
+        .and_then(|visual| visual.get("visual_url"))
+        .and_then(Value::as_str)
+        .filter(|url| !url.is_empty())
+        .map(get_original_artwork_url);
+    
+    Ok(banner_url)
+}
+
+/// Get the URL of the waveform data SoundCloud draws its player from
+///
+/// Older tracks point to a PNG rendering, the same path with .json has the raw samples.
+pub fn get_waveform_json_url(track: &Track) -> Option<String> {
+    let waveform_url = track.raw_data.as_ref()?
+        .get("waveform_url")
+        .and_then(Value::as_str)
+        .filter(|url| !url.is_empty())?;
+    
+    match waveform_url.strip_suffix(".png") {
+        Some(base) => Some(format!("{}.json", base)),
+        None => Some(waveform_url.to_string()),
+    }
+}
+
 /// Get a list of users that a SoundCloud user is following
 pub async fn get_user_followings(
     user_id: &str, 
@@ -751,7 +995,7 @@ pub async fn get_user_followings(
                 sleep(Duration::from_secs(2 * retry as u64)).await;
             }
             
-            let response = match client.get(&url).send().await {
+            let response = match with_auth(client.get(&url)).send().await {
                 Ok(res) => {
                     if !res.status().is_success() {
                         // Check for auth error and refresh client ID
@@ -877,12 +1121,6 @@ pub async fn get_user_likes(
         },
     };
     
-    // Try to fetch all likes in one go with a large limit
-    let url = format!(
-        "https://api-v2.soundcloud.com/users/{}/likes?client_id={}&limit={}&linked_partitioning=1",
-        user_id, client_id, limit
-    );
-    
     debug!("Attempting to fetch all {} likes in one request", limit);
     
     // Make the request with retry logic
@@ -896,7 +1134,13 @@ pub async fn get_user_likes(
             sleep(Duration::from_secs(2 * retry as u64)).await;
         }
         
-        let response = match client.get(&url).send().await {
+        // Try to fetch all likes in one go with a large limit
+        let url = format!(
+            "https://api-v2.soundcloud.com/users/{}/likes?client_id={}&limit={}&linked_partitioning=1",
+            user_id, client_id, limit
+        );
+        
+        let response = match with_auth(client.get(&url)).send().await {
             Ok(res) => {
                 if !res.status().is_success() {
                     // Check for auth error and refresh client ID
@@ -1023,6 +1267,7 @@ pub async fn get_user_likes(
                             genre: track_json.get("genre").and_then(Value::as_str).map(String::from),
                             tag_list: track_json.get("tag_list").and_then(Value::as_str).map(String::from),
                             downloadable: track_json.get("downloadable").and_then(Value::as_bool),
+                            secret_token: track_json.get("secret_token").and_then(Value::as_str).map(String::from),
                             raw_data: Some(track_json.clone()),
                         };
                         
@@ -1173,20 +1418,48 @@ pub async fn display_soundcloud_info(url: &str) -> Result<(), Box<dyn std::error
     Ok(())
 }
 
+/// A track that has been downloaded and processed, waiting to be posted
+#[derive(Debug, Clone)]
+pub struct PreparedTrack {
+    pub track_id: String,
+    pub track: Track,
+    /// (file_path, file_name) of every file archived for the track
+    pub files: Vec<(String, String)>,
+    /// Files to attach, without the ones already on Discord
+    pub upload_files: Vec<(String, String)>,
+    pub notes: crate::discord::ArchiveNotes,
+    pub record: crate::db::ArchiveRecord,
+    /// Paths the local-sink transcode profiles and their analysis images were exported to, or would be in a dry run
+    pub local_exports: Vec<String>,
+    /// Temp directory holding the files
+    pub work_dir: std::path::PathBuf,
+}
+
 /// Process and post a single track to Discord
 /// 
 /// Takes either a track ID or URL, resolves it, processes the audio, and posts to Discord.
-/// Returns the Discord message ID and track ID for further processing.
+/// This runs `prepare_track` and `post_prepared_track` back to back.
+/// Returns the track ID, user ID, Discord response and the hashes and fingerprint to record.
 pub async fn process_and_post_track(
     id_or_url: &str,
-    discord_webhook_url: &str,
-    temp_dir: Option<&str>,
+    config: &crate::config::Config,
+    archive_index: Option<&crate::db::ArchiveIndex>,
     discord_semaphore: Option<&Arc<tokio::sync::Semaphore>>
-) -> Result<(String, String, crate::discord::WebhookResponse), Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<(String, String, crate::discord::WebhookResponse, crate::db::ArchiveRecord), Box<dyn std::error::Error + Send + Sync>> {
+    let prepared = prepare_track(id_or_url, config, archive_index).await?;
+    post_prepared_track(prepared, config, discord_semaphore).await
+}
+
+/// Track ID and private share token of a track ID or URL
+/// 
+/// URLs are resolved, private share links carry the token of the track.
+pub async fn resolve_track_input(
+    id_or_url: &str
+) -> Result<(String, Option<String>), Box<dyn std::error::Error + Send + Sync>> {
     // Check if this is a URL or an ID
-    let track_id = if id_or_url.starts_with("http") {
+    let ids = if id_or_url.starts_with("http") {
         // This is a URL, resolve it
-        info!("Resolving SoundCloud URL: {}", id_or_url);
+        info!("Resolving SoundCloud URL: {}", redact_url(id_or_url));
         let resolved = match resolve_url(id_or_url).await {
             Ok(data) => data,
             Err(e) => {
@@ -1201,7 +1474,16 @@ pub async fn process_and_post_track(
                 if let Some(id) = resolved.get("id").and_then(|v| v.as_u64()) {
                     let track_id = id.to_string();
                     info!("URL resolved to track ID: {}", track_id);
-                    track_id
+                    
+                    // Private share links resolve to a track carrying its secret token
+                    let secret_token = resolved.get("secret_token")
+                        .and_then(|v| v.as_str())
+                        .map(String::from);
+                    if secret_token.is_some() {
+                        info!("Track {} is private, using its share token", track_id);
+                    }
+                    
+                    (track_id, secret_token)
                 } else {
                     error!("Could not extract track ID from resolved URL");
                     return Err("Could not extract track ID from resolved URL".into());
@@ -1216,12 +1498,42 @@ pub async fn process_and_post_track(
         }
     } else {
         // Assume this is a track ID
-        id_or_url.to_string()
+        (id_or_url.to_string(), None)
     };
     
+    Ok(ids)
+}
+
+/// Download and process a track without posting it
+/// 
+/// Takes either a track ID or URL. Files whose hash is in the archive index are linked to
+/// their earlier upload instead of attached again, and a track that sounds like an archived
+/// one is flagged as a re-upload.
+pub async fn prepare_track(
+    id_or_url: &str,
+    config: &crate::config::Config,
+    archive_index: Option<&crate::db::ArchiveIndex>
+) -> Result<PreparedTrack, Box<dyn std::error::Error + Send + Sync>> {
+    let (track_id, secret_token) = resolve_track_input(id_or_url).await?;
+    prepare_track_with_secret(&track_id, secret_token.as_deref(), config, archive_index, false).await
+}
+
+/// Download and process a track by ID, passing the secret token for private tracks
+/// 
+/// A dry run leaves the local-sink transcode profiles in the work directory and only
+/// reports where they would be exported to.
+pub async fn prepare_track_with_secret(
+    track_id: &str,
+    secret_token: Option<&str>,
+    config: &crate::config::Config,
+    archive_index: Option<&crate::db::ArchiveIndex>,
+    dry_run: bool
+) -> Result<PreparedTrack, Box<dyn std::error::Error + Send + Sync>> {
+    let track_id = track_id.to_string();
+    
     // Get track details
     info!("Fetching track details for ID: {}", track_id);
-    let track_details = match get_track_details(&track_id).await {
+    let track_details = match get_track_details_with_secret(&track_id, secret_token).await {
         Ok(t) => {
             info!("Successfully fetched track: {} by {}", t.title, t.user.username);
             t
@@ -1234,10 +1546,32 @@ pub async fn process_and_post_track(
     
     // Download and process audio
     info!("Processing audio and artwork for track");
-    let processing_result = match crate::audio::process_track_audio(&track_details, temp_dir).await {
-        Ok((audio_files, artwork, json)) => {
+    let primary_audio: Option<String>;
+    let mut loudness = None;
+    let audio_hashes;
+    let local_exports;
+    let work_dir;
+    let processing_result = match crate::audio::process_track_audio(&track_details, config, dry_run).await {
+        Ok(processed) => {
+            let crate::audio::ProcessedAudio {
+                audio_files, artwork, json, media_files, analysis, audio_hashes: hashes, local_exports: exports, work_dir: dir
+            } = processed;
+            
+            // Posting just the embed would mark the job done, fail it so it's retried instead
+            if audio_files.is_empty() {
+                crate::audio::cleanup_temp_dir(&dir).await?;
+                error!("No audio file could be downloaded for track {}", track_id);
+                return Err(format!("No audio file could be downloaded for track {}", track_id).into());
+            }
+            
+            audio_hashes = hashes;
+            local_exports = exports;
+            work_dir = dir;
             let mut files = Vec::new();
             
+            // Files are sorted best first, that one gets fingerprinted
+            primary_audio = audio_files.first().map(|(_, path)| path.clone());
+            
             // Process all audio files
             for (format_info, path) in &audio_files {
                 let file_path = path.clone();
@@ -1275,14 +1609,160 @@ pub async fn process_and_post_track(
                 files.push((file_path, filename));
             }
             
+            for file_path in media_files {
+                let filename = std::path::Path::new(&file_path)
+                    .file_name()
+                    .unwrap_or_else(|| std::ffi::OsStr::new("media"))
+                    .to_string_lossy()
+                    .to_string();
+                
+                info!("Downloaded track media: {}", filename);
+                files.push((file_path, filename));
+            }
+            
+            if let Some(analysis) = analysis {
+                for file_path in analysis.images() {
+                    let filename = std::path::Path::new(&file_path)
+                        .file_name()
+                        .unwrap_or_else(|| std::ffi::OsStr::new("analysis.png"))
+                        .to_string_lossy()
+                        .to_string();
+                    
+                    info!("Rendered analysis image: {}", filename);
+                    files.push((file_path, filename));
+                }
+                loudness = analysis.loudness;
+            }
+            
             files
         },
         Err(e) => {
             error!("Failed to process track media: {}", e);
-            Vec::new() // Continue without audio files
+            return Err(e);
+        }
+    };
+    
+    // Hash everything we archived, so identical files can be found later.
+    // Audio was already hashed before tagging, everything else is hashed as posted.
+    let other_files: Vec<(String, String)> = processing_result.iter()
+        .filter(|(path, _)| !audio_hashes.iter().any(|hash| &hash.path == path))
+        .cloned()
+        .collect();
+    let mut file_hashes = audio_hashes;
+    file_hashes.extend(crate::hashing::hash_files(&other_files).await);
+    
+    let mut notes = crate::discord::ArchiveNotes {
+        loudness,
+        ..Default::default()
+    };
+    
+    // Fingerprint the audio to recognise re-uploads under a different ID or account
+    let mut fingerprint_record = None;
+    if let (true, Some(path)) = (config.fingerprint_tracks, primary_audio.as_ref()) {
+        match crate::fingerprint::compute_fingerprint(std::path::Path::new(path)).await {
+            Ok(fingerprint) => {
+                if let Some(index) = archive_index {
+                    notes.possible_reupload = index.find_reupload(
+                        &track_id,
+                        track_details.duration,
+                        &fingerprint,
+                        config.reupload_similarity_threshold
+                    );
+                    
+                    if let Some(found) = &notes.possible_reupload {
+                        info!("Track {} sounds like archived track {} '{}' ({:.0}% similar)",
+                              track_id, found.track_id, found.record.title, found.similarity * 100.0);
+                    }
+                }
+                
+                fingerprint_record = Some(crate::db::FingerprintRecord {
+                    title: track_details.title.clone(),
+                    permalink_url: track_details.permalink_url.clone(),
+                    duration_ms: track_details.duration,
+                    fingerprint: crate::fingerprint::encode(&fingerprint),
+                    message_id: None,
+                    channel_id: None,
+                });
+            },
+            Err(e) => warn!("Failed to fingerprint track {}: {}", track_id, e),
         }
+    }
+    
+    let mut prepared = PreparedTrack {
+        track_id,
+        record: crate::db::ArchiveRecord {
+            file_hashes,
+            fingerprint: fingerprint_record,
+            secret_token: track_details.secret_token.clone(),
+        },
+        track: track_details,
+        upload_files: processing_result.clone(),
+        files: processing_result,
+        notes,
+        local_exports,
+        work_dir,
     };
     
+    // Files that are already on Discord get a link instead of a second upload
+    if let (true, Some(index)) = (config.deduplicate_uploads, archive_index) {
+        prepared.link_duplicates(&index.files);
+    }
+    
+    Ok(prepared)
+}
+
+impl PreparedTrack {
+    /// Link files whose hash is already on Discord instead of uploading them again
+    /// 
+    /// Runs against the index copy while preparing, and again against the live database
+    /// right before posting to catch files another worker posted in the meantime.
+    /// Returns the number of newly linked files.
+    pub fn link_duplicates(&mut self, archived_files: &std::collections::HashMap<String, crate::db::ArchivedFile>) -> usize {
+        let mut linked = 0;
+        
+        for hash in &self.record.file_hashes {
+            if !self.upload_files.iter().any(|(path, _)| path == &hash.path) {
+                continue;
+            }
+            
+            if let Some(archived) = archived_files.get(&hash.sha256) {
+                info!("{} is identical to {} from track {}, linking instead of uploading",
+                      hash.file_name, archived.file_name, archived.track_id);
+                self.upload_files.retain(|(path, _)| path != &hash.path);
+                self.notes.duplicate_files.push((hash.file_name.clone(), archived.clone()));
+                linked += 1;
+            }
+        }
+        
+        linked
+    }
+    
+    /// Delete the temp files of the track, with the rest of its work directory
+    pub async fn cleanup(&self) {
+        for (path, _) in &self.files {
+            if let Err(e) = crate::audio::delete_temp_file(path).await {
+                warn!("Failed to clean up temp file {}: {}", path, e);
+            }
+        }
+        
+        if let Err(e) = crate::audio::cleanup_temp_dir(&self.work_dir).await {
+            warn!("Failed to clean up temp directory {}: {}", self.work_dir.display(), e);
+        }
+    }
+}
+
+/// Post a prepared track to Discord and clean up its temp files
+/// 
+/// Returns the track ID, user ID, Discord response and the hashes and fingerprint to record.
+pub async fn post_prepared_track(
+    mut prepared: PreparedTrack,
+    config: &crate::config::Config,
+    discord_semaphore: Option<&Arc<tokio::sync::Semaphore>>
+) -> Result<(String, String, crate::discord::WebhookResponse, crate::db::ArchiveRecord), Box<dyn std::error::Error + Send + Sync>> {
+    let upload_files = std::mem::take(&mut prepared.upload_files);
+    let track_details = &prepared.track;
+    let track_id = &prepared.track_id;
+    
     // Send to Discord
     info!("Sending webhook for track: {} by {}", track_details.title, track_details.user.username);
     
@@ -1299,7 +1779,12 @@ pub async fn process_and_post_track(
         None
     };
     
-    let webhook_response = match crate::discord::send_track_webhook(discord_webhook_url, &track_details, Some(processing_result.clone())).await {
+    let result = crate::discord::send_track_webhook(&config.discord_webhook_url, track_details, Some(upload_files), &prepared.notes).await;
+    
+    // Clean up temp files, a failed post is prepared again from scratch
+    prepared.cleanup().await;
+    
+    let webhook_response = match result {
         Ok(response) => {
             info!("Successfully sent webhook for track with message ID: {}", response.message_id);
             println!("Track successfully posted to Discord: {} by {}", 
@@ -1313,12 +1798,6 @@ pub async fn process_and_post_track(
         }
     };
     
-    // Clean up temp files
-    for (path, _) in processing_result.clone() {
-        if let Err(e) = crate::audio::delete_temp_file(&path).await {
-            warn!("Failed to clean up temp file {}: {}", path, e);
-        }
-    }
-    
-    Ok((track_id, track_details.user.id.clone(), webhook_response))
-} 
\ No newline at end of file
+    Ok((prepared.track_id, prepared.track.user.id, webhook_response, prepared.record))
+}
+ 
\ No newline at end of file
@@ -0,0 +1,269 @@
+use std::borrow::Cow;
+use std::path::Path;
+use log::debug;
+use lofty::config::WriteOptions;
+use lofty::file::FileType;
+use lofty::id3::v2::{Frame, FrameId, Id3v2Tag, UrlLinkFrame};
+use lofty::mp4::{Atom, AtomData, AtomIdent, Ilst};
+use lofty::ogg::tag::VorbisComments;
+use lofty::picture::{MimeType, Picture, PictureType};
+use lofty::prelude::*;
+use lofty::tag::{Tag, TagType};
+use crate::soundcloud::Track;
+
+/// Name of the custom tag holding the SoundCloud permalink
+const URL_TAG: &str = "SOUNDCLOUD_URL";
+
+/// Name of the custom tag holding the SoundCloud track ID
+const TRACK_ID_TAG: &str = "SOUNDCLOUD_TRACK_ID";
+
+/// Freeform atom namespace used by iTunes-compatible taggers
+const MP4_FREEFORM_MEAN: &str = "com.apple.iTunes";
+
+/// Write title, artist, genre, date, URL, track ID and description tags into an audio file,
+/// and embed the cover art if given
+///
+/// Supports MP3 (ID3v2), M4A (MP4 ilst), Opus/Ogg and FLAC (Vorbis comments). Existing
+/// tags are kept and our fields overwrite theirs.
+pub async fn embed_metadata(
+    path: &Path,
+    track: &Track,
+    cover: Option<Vec<u8>>
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let path = path.to_path_buf();
+    let track = track.clone();
+
+    // lofty does blocking file IO
+    tokio::task::spawn_blocking(move || write_tags(&path, &track, cover)).await?
+}
+
+fn write_tags(
+    path: &Path,
+    track: &Track,
+    cover: Option<Vec<u8>>
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let tagged_file = lofty::read_from_path(path)?;
+    let file_type = tagged_file.file_type();
+    let tag_type = tagged_file.primary_tag_type();
+
+    if !matches!(file_type, FileType::Mpeg | FileType::Mp4 | FileType::Opus | FileType::Vorbis | FileType::Flac) {
+        return Err(format!("Tagging {:?} files is not supported", file_type).into());
+    }
+
+    // Start from the existing tag so encoder info and the Ogg vendor string survive
+    let mut tag = tagged_file.tag(tag_type)
+        .cloned()
+        .unwrap_or_else(|| Tag::new(tag_type));
+
+    tag.set_title(track.title.clone());
+    tag.set_artist(track.user.username.clone());
+
+    if let Some(genre) = track.genre.as_ref().filter(|g| !g.trim().is_empty()) {
+        tag.set_genre(genre.clone());
+    }
+
+    // created_at is "2023-05-01T12:34:56Z", the date part is what players understand
+    if let Some(date) = track.created_at.get(..10) {
+        tag.insert_text(ItemKey::RecordingDate, date.to_string());
+    }
+
+    if let Some(description) = track.description.as_ref().filter(|d| !d.trim().is_empty()) {
+        tag.set_comment(description.clone());
+    }
+
+    if let Some(data) = cover {
+        let mime_type = if data.starts_with(b"\x89PNG") { MimeType::Png } else { MimeType::Jpeg };
+        tag.remove_picture_type(PictureType::CoverFront);
+        tag.push_picture(
+            Picture::unchecked(data)
+                .pic_type(PictureType::CoverFront)
+                .mime_type(mime_type)
+                .build()
+        );
+    }
+
+    // URL and track ID have no generic key, so add them in each format's own way.
+    // Share links of private tracks would give their token away, it is left out.
+    let url = crate::soundcloud::redact_url(&track.permalink_url);
+    match tag_type {
+        TagType::Id3v2 => {
+            let mut id3v2: Id3v2Tag = tag.into();
+            id3v2.insert(Frame::Url(UrlLinkFrame::new(
+                FrameId::Valid(Cow::Borrowed("WOAF")),
+                url.clone()
+            )));
+            id3v2.insert_user_text(URL_TAG.to_string(), url);
+            id3v2.insert_user_text(TRACK_ID_TAG.to_string(), track.id.clone());
+            id3v2.save_to_path(path, WriteOptions::default())?;
+        },
+        TagType::Mp4Ilst => {
+            let mut ilst: Ilst = tag.into();
+            for (name, value) in [(URL_TAG, &url), (TRACK_ID_TAG, &track.id)] {
+                ilst.replace_atom(Atom::new(
+                    AtomIdent::Freeform {
+                        mean: Cow::Borrowed(MP4_FREEFORM_MEAN),
+                        name: Cow::Borrowed(name),
+                    },
+                    AtomData::UTF8(value.clone())
+                ));
+            }
+            ilst.save_to_path(path, WriteOptions::default())?;
+        },
+        TagType::VorbisComments => {
+            let mut comments: VorbisComments = tag.into();
+            comments.insert(URL_TAG.to_string(), url);
+            comments.insert(TRACK_ID_TAG.to_string(), track.id.clone());
+            comments.save_to_path(path, WriteOptions::default())?;
+        },
+        other => {
+            return Err(format!("Unexpected tag type {:?} for {:?} file", other, file_type).into());
+        }
+    }
+
+    debug!("Embedded metadata into {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lofty::config::ParseOptions;
+    use lofty::mpeg::MpegFile;
+    use std::path::PathBuf;
+    use crate::soundcloud::TrackUser;
+
+    fn track(permalink_url: &str) -> Track {
+        Track {
+            id: "123456".to_string(),
+            title: "Night Drive".to_string(),
+            permalink_url: permalink_url.to_string(),
+            artwork_url: None,
+            description: Some("Made at night".to_string()),
+            user: TrackUser {
+                id: "1".to_string(),
+                username: "artist".to_string(),
+                permalink_url: "https://soundcloud.com/artist".to_string(),
+                avatar_url: None,
+            },
+            created_at: "2023-05-01T12:34:56Z".to_string(),
+            duration: 1_000,
+            stream_url: None,
+            hls_url: None,
+            download_url: None,
+            playback_count: None,
+            likes_count: None,
+            reposts_count: None,
+            comment_count: None,
+            genre: Some("Electronic".to_string()),
+            tag_list: None,
+            downloadable: None,
+            secret_token: None,
+            raw_data: None,
+        }
+    }
+
+    /// Unique path in the system temp directory
+    fn temp_file(extension: &str, contents: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("tags_test_{}.{}", uuid::Uuid::new_v4(), extension));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// Silent MPEG-1 Layer III frames (128 kbit/s, 44.1 kHz), enough for lofty to read
+    fn silent_mp3() -> Vec<u8> {
+        let mut frame = vec![0u8; 417];
+        frame[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0x64]);
+        frame.repeat(20)
+    }
+
+    /// A WAV file with a few samples of 16-bit mono PCM
+    fn wav() -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(b"RIFF");
+        data.extend_from_slice(&(36u32 + 16).to_le_bytes());
+        data.extend_from_slice(b"WAVEfmt ");
+        data.extend_from_slice(&16u32.to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&8000u32.to_le_bytes());
+        data.extend_from_slice(&16000u32.to_le_bytes());
+        data.extend_from_slice(&2u16.to_le_bytes());
+        data.extend_from_slice(&16u16.to_le_bytes());
+        data.extend_from_slice(b"data");
+        data.extend_from_slice(&16u32.to_le_bytes());
+        data.extend_from_slice(&[0u8; 16]);
+        data
+    }
+
+    fn read_id3v2(path: &Path) -> Id3v2Tag {
+        let mut file = std::fs::File::open(path).unwrap();
+        let mpeg = MpegFile::read_from(&mut file, ParseOptions::new()).unwrap();
+        mpeg.id3v2().unwrap().clone()
+    }
+
+    fn read_pictures(path: &Path) -> Vec<Picture> {
+        let tagged_file = lofty::read_from_path(path).unwrap();
+        tagged_file.primary_tag().unwrap().pictures().to_vec()
+    }
+
+    #[tokio::test]
+    async fn mp3_gets_id3v2_tags_and_cover() {
+        let path = temp_file("mp3", &silent_mp3());
+        let cover = b"\x89PNG\r\n\x1a\nnot really a png".to_vec();
+
+        embed_metadata(&path, &track("https://soundcloud.com/artist/night-drive"), Some(cover.clone())).await.unwrap();
+
+        let tag = read_id3v2(&path);
+        assert_eq!(tag.title().as_deref(), Some("Night Drive"));
+        assert_eq!(tag.artist().as_deref(), Some("artist"));
+        assert_eq!(tag.genre().as_deref(), Some("Electronic"));
+        assert_eq!(tag.comment().as_deref(), Some("Made at night"));
+        assert_eq!(tag.get_user_text(URL_TAG), Some("https://soundcloud.com/artist/night-drive"));
+        assert_eq!(tag.get_user_text(TRACK_ID_TAG), Some("123456"));
+
+        let pictures = read_pictures(&path);
+        assert_eq!(pictures.len(), 1);
+        assert_eq!(pictures[0].data(), &cover[..]);
+        assert_eq!(pictures[0].mime_type(), Some(&MimeType::Png));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn retagging_replaces_instead_of_duplicating() {
+        let path = temp_file("mp3", &silent_mp3());
+        let track = track("https://soundcloud.com/artist/night-drive");
+
+        embed_metadata(&path, &track, Some(b"first".to_vec())).await.unwrap();
+        embed_metadata(&path, &track, Some(b"second".to_vec())).await.unwrap();
+
+        let pictures = read_pictures(&path);
+        assert_eq!(pictures.len(), 1);
+        assert_eq!(pictures[0].data(), b"second");
+        assert_eq!(pictures[0].mime_type(), Some(&MimeType::Jpeg));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn share_tokens_are_left_out_of_url_tags() {
+        let path = temp_file("mp3", &silent_mp3());
+
+        embed_metadata(&path, &track("https://soundcloud.com/artist/night-drive/s-AbCdEf"), None).await.unwrap();
+
+        let tag = read_id3v2(&path);
+        assert_eq!(tag.get_user_text(URL_TAG), Some("https://soundcloud.com/artist/night-drive/s-[redacted]"));
+        let written = std::fs::read(&path).unwrap();
+        assert!(!written.windows(6).any(|w| w == b"AbCdEf"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unsupported_file_types_are_rejected() {
+        let path = temp_file("wav", &wav());
+
+        let error = embed_metadata(&path, &track("https://soundcloud.com/artist/night-drive"), None).await.unwrap_err();
+
+        assert_eq!(error.to_string(), "Tagging Wav files is not supported");
+        assert_eq!(std::fs::read(&path).unwrap(), wav());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
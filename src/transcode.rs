@@ -0,0 +1,181 @@
+use std::path::{Path, PathBuf};
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use tokio::process::Command as TokioCommand;
+use crate::soundcloud::Track;
+
+/// Where the files of a transcode profile end up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscodeSink {
+    /// Attached to the Discord post with the downloaded formats
+    Discord,
+    /// Copied into the profile's `output_dir`, not posted
+    Local,
+}
+
+/// A named ffmpeg output made from the best downloaded file of each track
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TranscodeProfile {
+    /// Name used in file names and the manifest, e.g. "opus-160"
+    pub name: String,
+    /// ffmpeg audio encoder, e.g. "libopus", "flac", "libmp3lame"
+    pub codec: String,
+    /// Target bitrate, e.g. "160k" (not used by lossless encoders)
+    #[serde(default)]
+    pub bitrate: Option<String>,
+    /// File extension of the output, which also picks the container
+    pub extension: String,
+    /// Additional ffmpeg output arguments, e.g. ["-compression_level", "8"]
+    #[serde(default)]
+    pub extra_args: Vec<String>,
+    #[serde(default = "default_sink")]
+    pub sink: TranscodeSink,
+    /// Directory for the local sink, files go to `<output_dir>/<artist>/<title> [<track id>].<ext>`
+    #[serde(default)]
+    pub output_dir: Option<String>,
+}
+
+fn default_sink() -> TranscodeSink {
+    TranscodeSink::Discord
+}
+
+impl TranscodeProfile {
+    /// Format info of the files this profile produces
+    pub fn format_info(&self) -> String {
+        format!("transcoded/{}", self.name)
+    }
+
+    /// Check the profile for mistakes that would only show up when a track is processed
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("transcode profile has an empty name".to_string());
+        }
+        if self.codec.trim().is_empty() {
+            return Err(format!("transcode profile '{}' has no codec", self.name));
+        }
+        if self.extension.trim().is_empty() || self.extension.contains(['/', '\\', '.']) {
+            return Err(format!("transcode profile '{}' has an invalid extension '{}'", self.name, self.extension));
+        }
+        if self.sink == TranscodeSink::Local && self.output_dir.as_deref().is_none_or(|dir| dir.trim().is_empty()) {
+            return Err(format!("transcode profile '{}' uses the local sink but has no output_dir", self.name));
+        }
+        Ok(())
+    }
+}
+
+/// Transcode an audio file or stream URL with ffmpeg
+///
+/// `codec_args` are the output options, e.g. `["-c:a", "libmp3lame", "-q:a", "2"]`.
+/// Only the first audio stream is kept, so embedded cover art doesn't break
+/// containers that can't hold video.
+pub async fn ffmpeg_transcode(
+    input: &str,
+    output_path: &Path,
+    codec_args: &[String]
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    debug!("Executing ffmpeg transcoding command");
+    let mut cmd = TokioCommand::new("ffmpeg");
+
+    // Check if we should show ffmpeg output
+    let show_output = matches!(crate::config::Config::show_ffmpeg_output(), Some(true));
+
+    cmd.arg("-i")
+        .arg(input)
+        .arg("-map")
+        .arg("0:a:0")
+        .args(codec_args)
+        .arg("-y") // Overwrite output
+        .kill_on_drop(true); // Ensure process is killed if parent process exits
+
+    // Configure stdout/stderr redirection based on config
+    if !show_output {
+        // Silence ffmpeg output
+        cmd.stdout(std::process::Stdio::null())
+           .stderr(std::process::Stdio::null());
+    }
+
+    // Add output path
+    cmd.arg(output_path);
+
+    // Log command (without the input, which may be a signed URL)
+    debug!("ffmpeg command: -i [input] -map 0:a:0 {} -y {}",
+          codec_args.join(" "), output_path.display());
+
+    // Execute command
+    let status = cmd.status().await?;
+
+    if !status.success() {
+        error!("ffmpeg transcoding failed with exit code: {}", status);
+        return Err(format!("ffmpeg failed with exit code: {}", status).into());
+    }
+
+    debug!("ffmpeg transcoding completed successfully");
+    Ok(())
+}
+
+/// Produce a profile's output from a source file
+pub async fn transcode_with_profile(
+    profile: &TranscodeProfile,
+    source_path: &Path,
+    output_path: &Path
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut args = vec!["-c:a".to_string(), profile.codec.clone()];
+    if let Some(bitrate) = &profile.bitrate {
+        args.push("-b:a".to_string());
+        args.push(bitrate.clone());
+    }
+    args.extend(profile.extra_args.iter().cloned());
+
+    ffmpeg_transcode(&source_path.to_string_lossy(), output_path, &args).await
+}
+
+/// Where a local-sink profile puts its file of a track
+/// 
+/// `<output_dir>/<artist>/<title> [<id>].<extension>`
+pub fn local_sink_path(
+    profile: &TranscodeProfile,
+    track: &Track
+) -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync>> {
+    let output_dir = profile.output_dir.as_deref()
+        .ok_or_else(|| format!("transcode profile '{}' has no output_dir", profile.name))?;
+
+    let artist_dir = Path::new(output_dir).join(sanitize_path_component(&track.user.username));
+    Ok(artist_dir.join(format!("{} [{}].{}",
+                               sanitize_path_component(&track.title), track.id, profile.extension)))
+}
+
+/// Move a finished file of a local-sink profile into its output directory
+///
+/// Returns the final path.
+pub async fn export_to_local_sink(
+    profile: &TranscodeProfile,
+    track: &Track,
+    file_path: &Path
+) -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync>> {
+    let target = local_sink_path(profile, track)?;
+    if let Some(artist_dir) = target.parent() {
+        tokio::fs::create_dir_all(artist_dir).await?;
+    }
+
+    // The work directory may be on another filesystem, so copy instead of renaming
+    tokio::fs::copy(file_path, &target).await?;
+    tokio::fs::remove_file(file_path).await?;
+
+    info!("Exported {} profile of track {} to {}", profile.name, track.id, target.display());
+    Ok(target)
+}
+
+/// Make a name safe to use as a single path component
+fn sanitize_path_component(name: &str) -> String {
+    let sanitized = crate::audio::sanitize_filename(name);
+
+    // Names like ".." would otherwise leave the output directory
+    let trimmed = sanitized.trim().trim_matches('.');
+    if trimmed.is_empty() {
+        "untitled".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
@@ -0,0 +1,280 @@
+use std::path::Path;
+use std::process::Command;
+use log::{debug, warn};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::process::Command as TokioCommand;
+
+/// Outcome of probing a downloaded audio file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerificationStatus {
+    /// Duration and codec match what SoundCloud reported
+    Passed,
+    /// The file is truncated, corrupt or not the codec we asked for
+    Failed,
+    /// Verification is disabled or ffprobe is not installed
+    Skipped,
+}
+
+/// Result of verifying one audio file, recorded in the track manifest
+#[derive(Debug, Clone, Serialize)]
+pub struct VerificationReport {
+    pub status: VerificationStatus,
+    /// Number of downloads it took to get this file
+    pub attempts: usize,
+    /// Codec name reported by ffprobe
+    pub codec: Option<String>,
+    /// Codecs that the format info allows (empty when unknown)
+    pub expected_codecs: Vec<String>,
+    /// Duration measured by ffprobe, in milliseconds
+    pub duration_ms: Option<u64>,
+    /// Duration reported by SoundCloud, in milliseconds
+    pub expected_duration_ms: u64,
+    /// Human readable reasons for a failed or skipped verification
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub problems: Vec<String>,
+}
+
+impl VerificationReport {
+    /// Report for a file that was not probed
+    pub fn skipped(expected_duration_ms: u64, reason: &str) -> Self {
+        VerificationReport {
+            status: VerificationStatus::Skipped,
+            attempts: 1,
+            codec: None,
+            expected_codecs: Vec::new(),
+            duration_ms: None,
+            expected_duration_ms,
+            problems: vec![reason.to_string()],
+        }
+    }
+
+    pub fn failed(&self) -> bool {
+        self.status == VerificationStatus::Failed
+    }
+}
+
+/// Check if ffprobe is available, caching the result
+pub fn ffprobe_available() -> bool {
+    lazy_static::lazy_static! {
+        static ref FFPROBE_AVAILABLE: bool = Command::new("ffprobe").arg("-version").output().is_ok();
+    }
+
+    *FFPROBE_AVAILABLE
+}
+
+/// Probe a downloaded file and compare it against what SoundCloud reported
+///
+/// The measured duration must be within `tolerance_ms` of `expected_duration_ms`, and
+/// the codec of the first audio stream must match the format info (e.g. "hls/audio/mpeg/sq"
+/// must contain MP3). Formats we can't map to a codec only get the duration check.
+pub async fn verify_audio_file(
+    path: &Path,
+    format_info: &str,
+    expected_duration_ms: u64,
+    tolerance_ms: u64
+) -> VerificationReport {
+    if !ffprobe_available() {
+        return VerificationReport::skipped(expected_duration_ms, "ffprobe not found in PATH");
+    }
+
+    let expected_codecs: Vec<String> = expected_codecs_for_format(format_info)
+        .iter()
+        .map(|c| c.to_string())
+        .collect();
+
+    let mut report = VerificationReport {
+        status: VerificationStatus::Passed,
+        attempts: 1,
+        codec: None,
+        expected_codecs,
+        duration_ms: None,
+        expected_duration_ms,
+        problems: Vec::new(),
+    };
+
+    let probe = match probe_file(path).await {
+        Ok(probe) => probe,
+        Err(e) => {
+            report.status = VerificationStatus::Failed;
+            report.problems.push(format!("ffprobe could not read the file: {}", e));
+            return report;
+        }
+    };
+
+    report.codec = probe.codec.clone();
+    report.duration_ms = probe.duration_ms;
+
+    // Duration check
+    match probe.duration_ms {
+        Some(duration) if expected_duration_ms > 0 => {
+            let difference = duration.abs_diff(expected_duration_ms);
+            if difference > tolerance_ms {
+                report.problems.push(format!(
+                    "duration {:.1}s differs from expected {:.1}s by {:.1}s",
+                    duration as f64 / 1000.0,
+                    expected_duration_ms as f64 / 1000.0,
+                    difference as f64 / 1000.0
+                ));
+            }
+        },
+        Some(_) => debug!("Track has no duration, skipping duration check for {}", path.display()),
+        None => report.problems.push("file has no measurable duration".to_string()),
+    }
+
+    // Codec check
+    match &probe.codec {
+        Some(codec) => {
+            let matches = report.expected_codecs.is_empty() || report.expected_codecs.iter()
+                .any(|expected| codec_matches(codec, expected));
+            if !matches {
+                report.problems.push(format!(
+                    "codec {} does not match expected {}",
+                    codec, report.expected_codecs.join("/")
+                ));
+            }
+        },
+        None => report.problems.push("file has no audio stream".to_string()),
+    }
+
+    if !report.problems.is_empty() {
+        report.status = VerificationStatus::Failed;
+        warn!("Verification failed for {}: {}", path.display(), report.problems.join("; "));
+    } else {
+        debug!("Verified {} ({}, {} ms)", path.display(),
+               report.codec.as_deref().unwrap_or("unknown codec"),
+               report.duration_ms.unwrap_or(0));
+    }
+
+    report
+}
+
+/// Duration and codec of a file as reported by ffprobe
+struct ProbeResult {
+    duration_ms: Option<u64>,
+    codec: Option<String>,
+}
+
+/// Run ffprobe on a file and read the container duration and first audio codec
+async fn probe_file(path: &Path) -> Result<ProbeResult, Box<dyn std::error::Error + Send + Sync>> {
+    let output = TokioCommand::new("ffprobe")
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("a:0")
+        .arg("-show_entries")
+        .arg("format=duration:stream=codec_name,duration")
+        .arg("-of")
+        .arg("json")
+        .arg(path)
+        .kill_on_drop(true)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = stderr.lines().next().unwrap_or("").trim().to_string();
+        return Err(format!("exit code {}{}", output.status,
+                           if message.is_empty() { String::new() } else { format!(": {}", message) }).into());
+    }
+
+    let json: Value = serde_json::from_slice(&output.stdout)?;
+    let stream = json.get("streams")
+        .and_then(Value::as_array)
+        .and_then(|streams| streams.first());
+
+    let codec = stream
+        .and_then(|s| s.get("codec_name"))
+        .and_then(Value::as_str)
+        .map(String::from);
+
+    // Fragmented MP4 sometimes only has a stream duration, so fall back to it
+    let duration_ms = json.get("format")
+        .and_then(|f| f.get("duration"))
+        .or_else(|| stream.and_then(|s| s.get("duration")))
+        .and_then(Value::as_str)
+        .and_then(|d| d.parse::<f64>().ok())
+        .map(|seconds| (seconds * 1000.0).round() as u64);
+
+    Ok(ProbeResult { duration_ms, codec })
+}
+
+/// Map a format info string to the ffprobe codec names it may contain
+///
+/// A trailing underscore marks a prefix (e.g. "pcm_" matches pcm_s16le and pcm_s24be).
+fn expected_codecs_for_format(format_info: &str) -> &'static [&'static str] {
+    let format = format_info.to_lowercase();
+
+    if let Some(extension) = format.strip_prefix("original/") {
+        // Original uploads are identified by their file extension
+        return match extension {
+            "wav" | "aif" | "aiff" => &["pcm_"],
+            "flac" => &["flac"],
+            "mp3" => &["mp3"],
+            "m4a" | "mp4" | "aac" => &["aac", "alac"],
+            "ogg" | "oga" => &["vorbis", "opus", "flac"],
+            "opus" => &["opus"],
+            _ => &[],
+        };
+    }
+
+    if format.contains("opus") {
+        &["opus"]
+    } else if format.contains("audio/ogg") {
+        &["vorbis", "opus"]
+    } else if format.contains("audio/mpeg") || format.ends_with("/mp3") {
+        &["mp3"]
+    } else if format.contains("audio/mp4") || format.contains("aac") || format.contains("mp4a") {
+        &["aac"]
+    } else if format.contains("flac") {
+        &["flac"]
+    } else {
+        &[]
+    }
+}
+
+/// Compare a codec name against an expected name or prefix
+fn codec_matches(codec: &str, expected: &str) -> bool {
+    if expected.ends_with('_') {
+        codec.starts_with(expected)
+    } else {
+        codec == expected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn originals_are_checked_by_extension() {
+        assert_eq!(expected_codecs_for_format("original/wav"), ["pcm_"]);
+        assert_eq!(expected_codecs_for_format("original/AIFF"), ["pcm_"]);
+        assert_eq!(expected_codecs_for_format("original/flac"), ["flac"]);
+        assert_eq!(expected_codecs_for_format("original/m4a"), ["aac", "alac"]);
+        assert_eq!(expected_codecs_for_format("original/ogg"), ["vorbis", "opus", "flac"]);
+        assert!(expected_codecs_for_format("original/xyz").is_empty());
+    }
+
+    #[test]
+    fn transcodings_are_checked_by_mime_type() {
+        assert_eq!(expected_codecs_for_format("hls/audio/mpeg/sq"), ["mp3"]);
+        assert_eq!(expected_codecs_for_format("progressive/audio/mpeg/sq"), ["mp3"]);
+        assert_eq!(expected_codecs_for_format("transcoded/mp3"), ["mp3"]);
+        assert_eq!(expected_codecs_for_format("hls/audio/mp4/hq"), ["aac"]);
+        assert_eq!(expected_codecs_for_format("hls/audio/mp4; codecs=\"mp4a.40.2\"/hq"), ["aac"]);
+        assert_eq!(expected_codecs_for_format("hls/audio/ogg; codecs=\"opus\"/sq"), ["opus"]);
+        assert_eq!(expected_codecs_for_format("hls/audio/ogg/sq"), ["vorbis", "opus"]);
+        assert!(expected_codecs_for_format("hls/unknown").is_empty());
+    }
+
+    #[test]
+    fn codec_prefixes_end_with_underscore() {
+        assert!(codec_matches("pcm_s16le", "pcm_"));
+        assert!(codec_matches("pcm_s24be", "pcm_"));
+        assert!(codec_matches("mp3", "mp3"));
+        assert!(!codec_matches("mp3float", "mp3"));
+        assert!(!codec_matches("aac", "pcm_"));
+    }
+}